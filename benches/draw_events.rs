@@ -0,0 +1,80 @@
+// Compares the containers considered for the draw event channel, run with
+// `cargo bench`. The frontend pushes a frame's worth of pixel changes and
+// drains all of them before presenting.
+extern crate chip8rs;
+
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::time::Instant;
+
+use chip8rs::Chip8;
+use chip8rs::emu::{Chip8Event, Rom};
+
+const FRAMES: usize = 20_000;
+const EVENTS_PER_FRAME: usize = 2_000;
+
+fn time<F: FnMut() -> u64>(name: &str, mut f: F) {
+    let start = Instant::now();
+    let checksum = f();
+    let elapsed = start.elapsed();
+
+    println!("{:<24} {:>10.2?} (checksum {})", name, elapsed, checksum);
+}
+
+fn main() {
+    time("VecDeque pop_front", || {
+        let mut queue: VecDeque<(u16, u16, u8)> = VecDeque::new();
+        let mut sum = 0;
+
+        for frame in 0..FRAMES {
+            for i in 0..EVENTS_PER_FRAME {
+                queue.push_back((i as u16, frame as u16, 1));
+            }
+            while let Some((x, y, v)) = queue.pop_front() {
+                sum += x as u64 + y as u64 + v as u64;
+            }
+        }
+        sum
+    });
+
+    time("Vec drain", || {
+        let mut queue: Vec<(u16, u16, u8)> = Vec::new();
+        let mut sum = 0;
+
+        for frame in 0..FRAMES {
+            for i in 0..EVENTS_PER_FRAME {
+                queue.push((i as u16, frame as u16, 1));
+            }
+            for (x, y, v) in queue.drain(..) {
+                sum += x as u64 + y as u64 + v as u64;
+            }
+        }
+        sum
+    });
+
+    // End to end through the emulator: draw the '0' glyph at every position.
+    let rom = Rom::try_from(&[
+        0x60, 0x00, // LD V0, #00
+        0x61, 0x00, // LD V1, #00
+        0xD0, 0x15, // DRW V0, V1, 5
+        0x70, 0x01, // ADD V0, #01
+        0x12, 0x04, // JP #204
+    ][..]).unwrap();
+    time("Chip8::drain_events", || {
+        let mut c8 = Chip8::with_seed(0);
+        c8.load_rom(&rom);
+        let mut sum = 0;
+
+        for _ in 0..FRAMES {
+            for _ in 0..30 {
+                c8.cycle();
+            }
+            for event in c8.drain_events() {
+                if let Chip8Event::Draw { x, y, on } = event {
+                    sum += x as u64 + y as u64 + on as u64;
+                }
+            }
+        }
+        sum
+    });
+}
@@ -0,0 +1,49 @@
+// Runs arbitrary bytes as a ROM under arbitrary quirks, looking for anything
+// in decode/execute that panics. Run with `cargo fuzz run cycle` from the repo root.
+#![no_main]
+
+use std::convert::TryFrom;
+
+use chip8rs::emu::{Quirks, Rom, StackPolicy};
+use chip8rs::Chip8;
+use libfuzzer_sys::fuzz_target;
+
+// Long enough to get through loops that set up registers before the interesting part.
+const CYCLES: usize = 10_000;
+
+fuzz_target!(|data: &[u8]| {
+    // The first two bytes pick the quirks, the rest is the program.
+    if data.len() < 2 {
+        return;
+    }
+    let flags = u16::from_le_bytes([data[0], data[1]]);
+    let program = &data[2..];
+    let rom = match Rom::try_from(program) {
+        Ok(rom) => rom,
+        Err(_) => return,
+    };
+
+    let mut c8 = Chip8::with_seed(0);
+    c8.quirks = Quirks {
+        wrap_x: flags & 0x01 != 0,
+        wrap_y: flags & 0x100 != 0,
+        xo_chip: flags & 0x02 != 0,
+        schip: flags & 0x04 != 0,
+        shift_vy: flags & 0x08 != 0,
+        jump_vx: flags & 0x10 != 0,
+        load_store_increment: flags & 0x20 != 0,
+        vf_reset: flags & 0x40 != 0,
+        display_wait: flags & 0x80 != 0,
+        clamp_index: flags & 0x200 != 0,
+        half_pixel_scroll: flags & 0x800 != 0,
+        // Only Wrap survives every call and return, the others panic on
+        // purpose (Error on overflow, both on a 00EE with nothing to return to).
+        stack: StackPolicy::Wrap,
+    };
+    c8.load_rom(&rom);
+
+    let ipf = c8.cycles_per_timer_tick();
+    for _ in 0..CYCLES {
+        c8.cycle_in_frame(ipf);
+    }
+});
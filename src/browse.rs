@@ -0,0 +1,72 @@
+// Flipping through a folder of ROMs from the window, PageDown and PageUp load
+// the next and previous file by name beside the current one.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Files in the same directory as `rom` with the same extension, which is the
+// best guess at what else is a ROM, sorted by name.
+pub fn siblings(rom: &Path) -> io::Result<Vec<PathBuf>> {
+    let dir = match rom.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let extension = rom.extension().map(|ext| ext.to_ascii_lowercase());
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().map(|ext| ext.to_ascii_lowercase()) == extension {
+            files.push(path);
+        }
+    }
+    files.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+
+    Ok(files)
+}
+
+// Where to go from `current` in a list of `len`, wrapping around at both ends.
+// Without a current position going forward starts at the top, going back at
+// the bottom.
+pub fn adjacent(current: Option<usize>, len: usize, forward: bool) -> Option<usize> {
+    if len == 0 {
+        return None;
+    }
+
+    Some(match (current, forward) {
+        (Some(i), true) => (i + 1) % len,
+        (Some(i), false) => (i + len - 1) % len,
+        (None, true) => 0,
+        (None, false) => len - 1,
+    })
+}
+
+// The ROM after (or before) `rom` in its directory, None when there's nothing else.
+pub fn neighbour(rom: &Path, forward: bool) -> io::Result<Option<PathBuf>> {
+    let mut files = siblings(rom)?;
+    let current = files.iter().position(|path| path.file_name() == rom.file_name());
+
+    match adjacent(current, files.len(), forward) {
+        Some(next) if Some(next) != current => Ok(Some(files.swap_remove(next))),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adjacent_wraps_at_both_ends() {
+        assert_eq!(adjacent(Some(0), 3, true), Some(1));
+        assert_eq!(adjacent(Some(2), 3, true), Some(0));
+        assert_eq!(adjacent(Some(1), 3, false), Some(0));
+        assert_eq!(adjacent(Some(0), 3, false), Some(2));
+
+        assert_eq!(adjacent(None, 3, true), Some(0));
+        assert_eq!(adjacent(None, 3, false), Some(2));
+        assert_eq!(adjacent(Some(0), 1, true), Some(0));
+        assert_eq!(adjacent(None, 0, true), None);
+    }
+}
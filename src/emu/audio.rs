@@ -0,0 +1,227 @@
+// Sample generation for the XO-CHIP audio pattern buffer. Frontends pull
+// samples from a `PatternPlayer` while `Chip8::sound_active` is true.
+
+// Something that can make the buzzer heard. Frontends drive it once per
+// frame from `Chip8::sound_active`, `audio_pattern` and `pitch`.
+// An error means the device is gone, see `FallbackAudio`.
+pub trait AudioBackend {
+    fn set_tone(&mut self, on: bool) -> Result<(), String>;
+
+    // Backends that can play the XO-CHIP pattern buffer pick up changes here.
+    fn set_pattern(&mut self, _pattern: &[u8; 16], _pitch: u8) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// Stays silent, for builds without an audio device.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NullAudio;
+
+impl AudioBackend for NullAudio {
+    fn set_tone(&mut self, _on: bool) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// Wraps a backend and drops it the first time it fails, so losing the
+// device mid-run only costs the sound.
+pub struct FallbackAudio {
+    backend: Option<Box<dyn AudioBackend>>,
+}
+
+impl FallbackAudio {
+    pub fn new(backend: Box<dyn AudioBackend>) -> FallbackAudio {
+        FallbackAudio { backend: Some(backend) }
+    }
+
+    // False once the backend has failed and sound is off for good.
+    pub fn enabled(&self) -> bool {
+        self.backend.is_some()
+    }
+
+    fn check(&mut self, result: Result<(), String>) {
+        if let Err(e) = result {
+            println!("[!] Audio device failed ({}), continuing without sound.", e);
+            self.backend = None;
+        }
+    }
+}
+
+impl AudioBackend for FallbackAudio {
+    fn set_tone(&mut self, on: bool) -> Result<(), String> {
+        if let Some(result) = self.backend.as_mut().map(|backend| backend.set_tone(on)) {
+            self.check(result);
+        }
+        Ok(())
+    }
+
+    fn set_pattern(&mut self, pattern: &[u8; 16], pitch: u8) -> Result<(), String> {
+        if let Some(result) = self.backend.as_mut().map(|backend| backend.set_pattern(pattern, pitch)) {
+            self.check(result);
+        }
+        Ok(())
+    }
+}
+
+// Until a ROM loads its own pattern, play a plain square wave like a classic buzzer.
+pub const DEFAULT_PATTERN: [u8; 16] = [
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00
+];
+
+// Pitch 64 plays the pattern at 4000 bits per second.
+pub const DEFAULT_PITCH: u8 = 64;
+
+// Furthest the player may retune the ROM's pitch, two octaves either way.
+pub const MAX_TUNING: i16 = 96;
+
+// One semitone in pitch register steps.
+pub const TUNING_STEP: i16 = 4;
+
+pub fn clamp_tuning(tuning: i16) -> i16 {
+    tuning.clamp(-MAX_TUNING, MAX_TUNING)
+}
+
+// The ROM's pitch moved by the player's tuning, kept in register range.
+pub fn tuned_pitch(pitch: u8, tuning: i16) -> u8 {
+    (pitch as i16 + clamp_tuning(tuning)).clamp(0, 255) as u8
+}
+
+// Number of pattern bits played per second for a given pitch register value.
+pub fn playback_rate(pitch: u8) -> f64 {
+    4000.0 * 2f64.powf((pitch as f64 - 64.0) / 48.0)
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PatternPlayer {
+    position: f64, // Current bit in the 128 bit pattern, fractional.
+}
+
+impl PatternPlayer {
+    pub fn new() -> PatternPlayer {
+        PatternPlayer { position: 0.0 }
+    }
+
+    // Fill `out` with samples in the range -1.0..=1.0, continuing from where the last call stopped.
+    pub fn fill(&mut self, pattern: &[u8; 16], pitch: u8, sample_rate: u32, out: &mut [f32]) {
+        let step = playback_rate(pitch) / sample_rate as f64;
+
+        for sample in out.iter_mut() {
+            let bit = self.position as usize;
+            let set = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+
+            *sample = if set { 1.0 } else { -1.0 };
+
+            self.position = (self.position + step) % 128.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emu::{Chip8, Chip8Event, Rom};
+    use std::convert::TryFrom;
+    use std::sync::{Arc, Mutex};
+
+    // Works for the first `works_for` calls, then acts unplugged.
+    struct Unplugged {
+        works_for: usize,
+        calls: Arc<Mutex<usize>>,
+    }
+
+    impl AudioBackend for Unplugged {
+        fn set_tone(&mut self, _on: bool) -> Result<(), String> {
+            let mut calls = self.calls.lock().unwrap();
+            *calls += 1;
+            if *calls > self.works_for {
+                return Err("device unplugged".to_string());
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn emulation_continues_without_the_device() {
+        // Beeps for two frames out of every four, forever.
+        let program = crate::chip8_program![0x6002, 0xF018, 0x6004, 0xF015, 0xF107, 0x3100, 0x1208, 0x1200];
+        let mut c8 = Chip8::with_seed(0);
+        c8.load_rom(&Rom::try_from(&program[..]).unwrap());
+
+        let calls = Arc::new(Mutex::new(0));
+        let mut audio = FallbackAudio::new(Box::new(Unplugged { works_for: 3, calls: calls.clone() }));
+        let mut beeps = 0;
+
+        for _ in 0..40 {
+            c8.advance_frame(10);
+            for event in c8.take_events() {
+                let result = match event {
+                    Chip8Event::BeepStart => { beeps += 1; audio.set_tone(true) },
+                    Chip8Event::BeepStop => audio.set_tone(false),
+                    _ => Ok(()),
+                };
+                assert_eq!(result, Ok(()));
+            }
+        }
+
+        assert!(!audio.enabled());
+        assert_eq!(*calls.lock().unwrap(), 4);
+        assert!(beeps >= 5);
+        assert_eq!(c8.cycles(), 400);
+    }
+
+    #[test]
+    fn null_audio_never_fails() {
+        let mut audio = FallbackAudio::new(Box::new(NullAudio));
+        for &on in [true, false, true].iter() {
+            assert_eq!(audio.set_tone(on), Ok(()));
+        }
+        assert_eq!(audio.set_pattern(&DEFAULT_PATTERN, DEFAULT_PITCH), Ok(()));
+        assert!(audio.enabled());
+    }
+
+    // Full periods of the square wave in `samples`, counted by rising edges.
+    fn periods(samples: &[f32]) -> usize {
+        samples.windows(2).filter(|pair| pair[0] < 0.0 && pair[1] > 0.0).count()
+    }
+
+    #[test]
+    fn pitch_sets_the_frequency() {
+        let mut c8 = Chip8::with_seed(0);
+        c8.quirks.xo_chip = true;
+        c8.load_rom(&Rom::try_from(&crate::chip8_program![0x6070, 0xF03A][..]).unwrap());
+        c8.cycle();
+        c8.cycle();
+        assert_eq!(c8.pitch(), 112);
+
+        // Alternating bits, so the tone is half the bit rate: 4000Hz at pitch
+        // 112 (8000 bits a second), 2000Hz at the default 64. A tenth of a second each.
+        let pattern = [0xAA; 16];
+        let mut samples = [0.0; 1601];
+        PatternPlayer::new().fill(&pattern, c8.pitch(), 16000, &mut samples);
+        assert_eq!(periods(&samples), 400);
+
+        PatternPlayer::new().fill(&pattern, DEFAULT_PITCH, 16000, &mut samples);
+        assert_eq!(periods(&samples), 200);
+    }
+
+    #[test]
+    fn tuning_stays_in_range() {
+        assert_eq!(clamp_tuning(TUNING_STEP), TUNING_STEP);
+        assert_eq!(clamp_tuning(MAX_TUNING + TUNING_STEP), MAX_TUNING);
+        assert_eq!(clamp_tuning(-MAX_TUNING - TUNING_STEP), -MAX_TUNING);
+
+        // Holding the key down never gets past the limit.
+        let tuning = (0..100).fold(0, |tuning, _| clamp_tuning(tuning + TUNING_STEP));
+        assert_eq!(tuning, MAX_TUNING);
+    }
+
+    #[test]
+    fn tuned_pitch_stays_in_register_range() {
+        assert_eq!(tuned_pitch(DEFAULT_PITCH, 0), DEFAULT_PITCH);
+        assert_eq!(tuned_pitch(64, -TUNING_STEP), 60);
+        assert_eq!(tuned_pitch(250, MAX_TUNING), 255);
+        assert_eq!(tuned_pitch(10, -MAX_TUNING), 0);
+        assert_eq!(tuned_pitch(100, 1000), 196);
+    }
+}
@@ -0,0 +1,23 @@
+use std::fmt;
+
+use super::disasm;
+
+// One instruction reading or writing VF, recorded while the flag audit is on
+// (see `Chip8::enable_flag_audit`). A ROM keeping its own data in VF gets it
+// overwritten by the next carry or collision, and this shows where.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FlagAccess {
+    pub cycle: u64, // Cycle count when the instruction ran.
+    pub pc: u16,
+    pub opcode: u16,
+    pub write: bool,
+    pub value: u8, // What was read, or what was written.
+}
+
+impl fmt::Display for FlagAccess {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>8}  {:03X}: {:04X}  {:<16} {} VF = {:02X}",
+            self.cycle, self.pc, self.opcode, disasm::disassemble(self.opcode),
+            if self.write { "write" } else { "read " }, self.value)
+    }
+}
@@ -0,0 +1,66 @@
+// Turns wall clock time into whole timer frames to run. After a stall (a
+// debugger pause, the OS swapping, a slow disk) the frames owed are capped, so
+// the machine loses the time instead of running a long burst to catch up while
+// the frontend stops responding, and falling further behind doing it.
+
+use std::time::Duration;
+
+// Frames run back to back at most, the rest of a stall is dropped.
+pub const MAX_CATCH_UP_FRAMES: u32 = 4;
+
+pub struct FrameClock {
+    interval: Duration, // Length of a frame, see `Chip8::timer_interval`.
+    max_frames: u32,
+    owed: Duration, // Time passed that no frame has been run for yet.
+}
+
+impl FrameClock {
+    pub fn new(interval: Duration, max_frames: u32) -> FrameClock {
+        FrameClock {
+            interval: interval.max(Duration::from_nanos(1)),
+            max_frames: max_frames.max(1),
+            owed: Duration::from_secs(0),
+        }
+    }
+
+    // Count `elapsed` more time and take the frames it adds up to, never more
+    // than `max_frames`. Each one is `ipf` cycles for `Chip8::advance_frame`.
+    pub fn advance(&mut self, elapsed: Duration) -> u32 {
+        self.owed += elapsed;
+
+        let due = self.owed.as_nanos() / self.interval.as_nanos();
+        if due > self.max_frames as u128 {
+            // Keep how far into the current frame we are, drop the rest.
+            self.owed = Duration::from_nanos((self.owed.as_nanos() % self.interval.as_nanos()) as u64);
+            return self.max_frames;
+        }
+
+        self.owed -= self.interval * due as u32;
+        due as u32
+    }
+
+    // Time left until the next frame is due.
+    pub fn until_next(&self) -> Duration {
+        self.interval.checked_sub(self.owed).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stall_is_capped() {
+        let frame = Duration::from_millis(10);
+        let mut clock = FrameClock::new(frame, MAX_CATCH_UP_FRAMES);
+
+        assert_eq!(clock.advance(Duration::from_millis(25)), 2);
+        assert_eq!(clock.until_next(), Duration::from_millis(5));
+
+        // A ten second hitch runs the capped few frames, not a thousand.
+        assert_eq!(clock.advance(Duration::from_secs(10) + Duration::from_millis(3)), MAX_CATCH_UP_FRAMES);
+        assert_eq!(clock.until_next(), Duration::from_millis(2));
+        assert_eq!(clock.advance(Duration::from_millis(2)), 1);
+        assert_eq!(clock.advance(Duration::from_millis(9)), 0);
+    }
+}
@@ -0,0 +1,108 @@
+use std::fmt::Write;
+
+use super::instruction::{self, Instruction, Opcode};
+use super::Chip8;
+
+// Turn a single opcode into its mnemonic, anything that doesn't decode is
+// shown as data. So are opcodes the interpreter would run as something else
+// because it ignores some of their nibbles, they're more likely data than code.
+pub fn disassemble(opcode: u16) -> String {
+    let instruction = instruction::decode(opcode);
+
+    if instruction.encode() == opcode {
+        instruction.to_string()
+    } else {
+        Instruction::Unknown(opcode).to_string()
+    }
+}
+
+// Addresses of the instructions shown around `pc`, `before` above it and `after` below.
+// The window stays inside memory and on the same alignment as `pc`, and always
+// holds `pc` itself, even odd at the last byte or run off the end by strict_pc.
+pub fn window(pc: u16, before: u16, after: u16) -> Vec<u16> {
+    let first = pc - (pc / 2).min(before) * 2;
+    let last = (pc as u32 + after as u32 * 2).min(pc.max(4095) as u32);
+
+    (first as u32..=last).step_by(2).map(|addr| addr as u16).collect()
+}
+
+// Disassemble `len` bytes of memory starting at `start`, one line per instruction.
+// When the machine was tracking coverage each line is marked with how often it ran.
+pub fn annotated_disassembly(c8: &Chip8, start: u16, len: usize) -> String {
+    let memory = c8.memory();
+    let end = (start as usize + len).min(memory.len());
+    let mut out = String::new();
+
+    for addr in (start as usize..end).step_by(2) {
+        let lo = if addr + 1 < memory.len() { memory[addr + 1] } else { 0 };
+        let opcode = Opcode::from_bytes(memory[addr], lo).0;
+
+        let annotation = match c8.coverage() {
+            Some(counts) if counts[addr] > 0 => format!("* {:>8}", counts[addr]),
+            Some(_) => format!("  {:>8}", "-"),
+            None => String::new(),
+        };
+
+        writeln!(out, "{}  {:03X}: {:04X}  {}", annotation, addr, opcode, disassemble(opcode)).unwrap();
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+    use crate::emu::Rom;
+
+    #[test]
+    fn window_around_pc() {
+        assert_eq!(window(0x208, 2, 3), vec![0x204, 0x206, 0x208, 0x20A, 0x20C, 0x20E]);
+        // Cut off at both ends of memory.
+        assert_eq!(window(0x002, 4, 1), vec![0x000, 0x002, 0x004]);
+        assert_eq!(window(0xFFC, 1, 4), vec![0xFFA, 0xFFC, 0xFFE]);
+        // An odd PC keeps its alignment.
+        assert_eq!(window(0x301, 1, 1), vec![0x2FF, 0x301, 0x303]);
+        // Even at the very top, or past it.
+        assert_eq!(window(0xFFF, 2, 2), vec![0xFFB, 0xFFD, 0xFFF]);
+        assert_eq!(window(0x1000, 1, 1), vec![0xFFE, 0x1000]);
+    }
+
+    #[test]
+    fn annotates_executed_lines() {
+        let mut c8 = Chip8::with_seed(0);
+        c8.load_rom(&Rom::try_from(&crate::chip8_program![0x6001, 0x1206, 0x6002, 0x1206][..]).unwrap());
+        c8.enable_coverage();
+        for _ in 0..5 {
+            c8.cycle();
+        }
+
+        let lines: Vec<String> = annotated_disassembly(&c8, 0x200, 8).lines().map(String::from).collect();
+        assert_eq!(lines, vec![
+            "*        1  200: 6001  LD V0, #01",
+            "*        1  202: 1206  JP #206",
+            "         -  204: 6002  LD V0, #02",
+            "*        3  206: 1206  JP #206",
+        ]);
+    }
+
+    #[test]
+    fn range_covers_only_its_addresses() {
+        let mut c8 = Chip8::with_seed(0);
+        c8.load_rom(&Rom::try_from(&crate::chip8_program![0x6001, 0x6102, 0x6203, 0x6304][..]).unwrap());
+
+        // 202:205, as --disasm-range gives it.
+        assert_eq!(annotated_disassembly(&c8, 0x202, 0x205 - 0x202 + 1), "  202: 6102  LD V1, #02\n  204: 6203  LD V2, #03\n");
+        // Clamped to the end of memory.
+        assert_eq!(annotated_disassembly(&c8, 0xFFC, 0x100), "  FFC: 0000  DB #00, #00\n  FFE: 0000  DB #00, #00\n");
+        assert_eq!(annotated_disassembly(&c8, 0x1000, 0x10), "");
+    }
+
+    #[test]
+    fn no_annotations_without_coverage() {
+        let mut c8 = Chip8::with_seed(0);
+        c8.load_rom(&Rom::try_from(&crate::chip8_program![0x6001][..]).unwrap());
+
+        assert_eq!(annotated_disassembly(&c8, 0x200, 2), "  200: 6001  LD V0, #01\n");
+    }
+}
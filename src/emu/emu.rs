@@ -1,7 +1,24 @@
 use std::fmt;
-use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use std::time::{Duration, Instant};
 
 use rand::Rng;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+use super::audio;
+use super::audit::FlagAccess;
+use super::disasm;
+use super::events::{self, Chip8Event, Observer};
+use super::instruction::{self, Instruction, Opcode, VReg};
+use super::peripheral::Peripheral;
+use super::profile::Profile;
+use super::quirks::{Quirks, StackPolicy};
+use super::rom::Rom;
+use super::state::SaveState;
+use super::trace::TraceRecord;
+use super::warnings::Warning;
 
 const FONTSET: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -22,32 +39,260 @@ const FONTSET: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80  // F
 ];
 
+// Programs are loaded (and start executing) here, below is reserved for the interpreter.
+pub const PROGRAM_START: usize = 0x200;
+pub const MAX_ROM_SIZE: usize = 4096 - PROGRAM_START;
+
+// The delay and sound timers count down at this rate unless `timer_hz` says otherwise.
+pub const TIMER_HZ: u32 = 60;
+
+// 600 instructions per second is a good fit for most ROMs.
+pub const DEFAULT_IPS: u32 = 600;
+pub const DEFAULT_IPF: u32 = DEFAULT_IPS / TIMER_HZ;
+
+// Pending warnings past this many are dropped until the frontend takes them.
+pub const MAX_WARNINGS: usize = 256;
+
+// The COSMAC VIP interpreter had room for this many return addresses.
+const CLASSIC_STACK_DEPTH: u16 = 12;
+
+// Return addresses there's room for before `Quirks::stack` decides.
+pub const STACK_SIZE: usize = 16;
+
+// The built in font lives at the bottom of memory.
+const FONT_END: u16 = FONTSET.len() as u16;
+
+// How many recently executed instructions are kept around.
+pub const TRACE_LEN: usize = 16;
+
+// Keypad key i is key i to the ROM, see `Chip8::key_order`.
+pub const STANDARD_KEY_ORDER: [u8; 16] = [0x0, 0x1, 0x2, 0x3, 0x4, 0x5, 0x6, 0x7, 0x8, 0x9, 0xA, 0xB, 0xC, 0xD, 0xE, 0xF];
+
+// What a held host key looks like to the ROM.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyRepeat {
+    Hold, // Pressed for as long as the host key is held.
+    Pulse, // Pressed until a key opcode sees it once, then released until the next press.
+}
+
+// When key changes from the host become visible to the ROM. Sampling every
+// cycle is the most responsive, sampling once per frame is what the VIP's
+// keypad scan did: a tap shorter than a frame can be missed, but no frame
+// ever sees a key change halfway through, which some input loops rely on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeySampling {
+    PerCycle, // The next instruction sees the change.
+    PerFrame, // Changes are picked up at the end of each frame.
+}
+
+// A copy of the display, rows of 64 pixels that are either 0 or 1.
+pub type Frame = [[u8; 64]; 32];
+
+// Start of `Chip8::save_display` output.
+const DISPLAY_MAGIC: &[u8; 4] = b"C8GF";
+
+// Undo `Chip8::gfx_rle`. None if the runs don't add up to exactly one screen.
+pub fn decode_gfx_rle(rle: &str) -> Option<Frame> {
+    let mut gfx = [[0; 64]; 32];
+    let mut pos = 0;
+    let mut on = false;
+
+    for run in rle.split(',').filter(|run| !run.is_empty()) {
+        let len: usize = run.trim().parse().ok()?;
+        if pos + len > 64 * 32 {
+            return None;
+        }
+
+        for i in pos..pos + len {
+            gfx[i / 64][i % 64] = on as u8;
+        }
+        pos += len;
+        on = !on;
+    }
+
+    if pos != 64 * 32 {
+        return None;
+    }
+    Some(gfx)
+}
+
+// A frame as a binary PPM (P6) at native resolution, white on black.
+pub fn frame_to_ppm(frame: &Frame) -> Vec<u8> {
+    let mut ppm = b"P6\n64 32\n255\n".to_vec();
+
+    for &pixel in frame.iter().flatten() {
+        let level = if pixel == 1 { 255 } else { 0 };
+        ppm.extend_from_slice(&[level; 3]);
+    }
+
+    ppm
+}
+
+// The last `count` frames as ASCII art (like `Chip8::to_ascii`), one above the
+// other with a separator line naming each frame's position in `frames`, so
+// a glitch across a few frames can be pasted into a bug report.
+pub fn film_strip(frames: &[Frame], count: usize) -> String {
+    let first = frames.len().saturating_sub(count);
+    let mut text = String::new();
+
+    for (i, frame) in frames.iter().enumerate().skip(first) {
+        // Padded out to the width of a frame.
+        text.push_str(&format!("{:-<64}\n", format!("--- frame {} ", i)));
+
+        for row in frame.iter() {
+            text.extend(row.iter().map(|&pixel| if pixel != 0 { '#' } else { '.' }));
+            text.push('\n');
+        }
+    }
+
+    text
+}
+
+// A frame as RGBA bytes, white on black, for backends and tools that want an
+// image without pulling in a GUI crate. Rows run top to bottom, 4 bytes a pixel.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameSnapshot {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl FrameSnapshot {
+    // Each CHIP-8 pixel becomes a `scale` x `scale` block, a scale of 0 is taken as 1.
+    pub fn from_frame(frame: &Frame, scale: u32) -> FrameSnapshot {
+        let scale = scale.max(1);
+        let width = frame[0].len() as u32 * scale;
+        let height = frame.len() as u32 * scale;
+
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        for row in frame.iter() {
+            let mut line = Vec::with_capacity((width * 4) as usize);
+            for &pixel in row.iter() {
+                let level = if pixel == 1 { 255 } else { 0 };
+                for _ in 0..scale {
+                    line.extend_from_slice(&[level, level, level, 255]);
+                }
+            }
+            for _ in 0..scale {
+                pixels.extend_from_slice(&line);
+            }
+        }
+
+        FrameSnapshot { width, height, pixels }
+    }
+
+    // The RGBA value at (x, y), None outside the image.
+    pub fn pixel(&self, x: u32, y: u32) -> Option<[u8; 4]> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+
+        let i = ((y * self.width + x) * 4) as usize;
+        let mut rgba = [0; 4];
+        rgba.copy_from_slice(&self.pixels[i..i + 4]);
+        Some(rgba)
+    }
+}
+
+// Why a bounded run like `run_to_next_draw` stopped.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RunResult {
+    Drew, // The last instruction executed was a DXYN or 00E0.
+    PausedOnUnknown, // Stopped on an opcode we can't decode, see `paused_on_unknown`.
+    PcOverrun, // Stopped with PC past the end of memory, see `pc_overrun`.
+    CycleLimit, // Ran out of cycles first.
+    Stepped, // `step_over` got past the instruction, and the subroutine if it was a call.
+    Beeped, // The last instruction executed started the sound timer.
+}
+
+// Called with the key index and whether it is now pressed.
+pub type KeyCallback = Box<dyn FnMut(usize, bool) + Send>;
+
+// Registers X through Y inclusive, counting down when X > Y.
+fn register_range(x: VReg, y: VReg) -> Box<dyn Iterator<Item = VReg>> {
+    let regs = VReg::ALL.iter().copied();
+    if x <= y {
+        Box::new(regs.skip(x.index()).take(y.index() - x.index() + 1))
+    } else {
+        Box::new(regs.skip(y.index()).take(x.index() - y.index() + 1).rev())
+    }
+}
+
 pub struct Chip8 {
     opcode: u16, // Current opcode
     memory: [u8; 4096],
     registers: [u8; 16], // V0 - VF
     index: u16, // Index register
     pc: u16, // Program counter
-    pub gfx: [[u8; 64]; 32], // Pixel values (64 x 32 screen)
+    pub gfx: Frame, // Pixel values (64 x 32 screen)
+    drawn: bool, // The ROM has drawn, cleared or scrolled the screen, see `has_drawn`.
+    scrolled: bool, // The ROM has scrolled, `Warning::LowResScroll` was given.
 
     // When set > zero, these timer registers will count down to zero
     // System buzzer should sound whenever either timer reaches zero
     delay_timer: u8,
     sound_timer: u8,
+    sound_ran: bool, // The sound timer was non-zero at some point since the last tick.
+    sounding: bool, // Whether the buzzer is on until the next tick, see `sound_active`.
+    audio_enabled: bool, // Off silences the buzzer without touching the sound timer, see `set_audio_enabled`.
 
-    stack: [u16; 16],
+    // XO-CHIP audio, the pattern is played back one bit per sample at a rate set by pitch.
+    audio_pattern: [u8; 16],
+    pitch: u8,
+
+    stack: Vec<u16>, // STACK_SIZE entries, more if StackPolicy::Grow made room.
     sp: u16,
 
     keys: [u8; 16], // Current key state
+    held: [bool; 16], // Host key state, differs from keys once a pulse was consumed.
+    vblank: bool, // A vblank happened since the last draw, see `Quirks::display_wait`.
+    pub key_repeat: KeyRepeat,
+    pub key_sampling: KeySampling,
+    pub key_order: [u8; 16], // The key number EX9E/EXA1/FX0A see for each keypad key, for variants that numbered them differently.
+    sampled: [bool; 16], // Host key state as of the last sample.
+    key_callback: Option<KeyCallback>, // Told about every key state change.
 
     pub show_debug: bool, // Should I print helpful opcode messages?
-
-    pub redraw: bool, // Should gfx be completely redrawn?
-    pub draw_queue: VecDeque<(u16, u16, u8)>,
+    debug_output: Option<Box<dyn Write + Send>>, // Where they go instead of stdout.
+    json_trace: Option<Box<dyn Write + Send>>, // Gets a `TraceRecord` line per instruction, when set.
+    pub pause_on_unknown: bool, // Stop instead of skipping opcodes we can't decode?
+    pub strict_pc: bool, // Stop when PC runs off the end of memory, instead of wrapping it around?
+    pub check_targets: bool, // Scan ROMs for jumps, calls and ANNNs into the reserved area as they load?
+    pub paused: bool, // Hold execution and the timers until the frontend clears this.
+    pub pause_on_focus_lost: bool, // Should `on_focus_lost` also pause?
+    pub quirks: Quirks, // Which interpreter behaviors to emulate.
+    pub ips: u32, // Instructions per second the frontend is asked to run.
+    pub timer_hz: u32, // How often per second the frontend should tick the timers.
+
+    events: Vec<Chip8Event>, // Display and sound changes since the last drain.
+    pub coalesce_events: bool, // Drop redundant draws before handing events over, see `events::coalesce`.
+    warnings: Vec<Warning>, // Compatibility problems since the last take.
+
+    seed: u64, // Seed used for CXNN, so runs can be reproduced.
+    rng: StdRng,
+    rng_draws: u64, // Numbers taken from rng so far, so a restore can catch back up.
+    rng_log: Option<Vec<u8>>, // Every number CXNN got, when recording them.
+    rng_replay: Option<std::vec::IntoIter<u8>>, // Numbers CXNN gets instead of asking rng.
+    cycles: u64, // Number of cycles executed so far.
+    coverage: Option<Vec<u32>>, // Times each address was executed, when tracking.
+    flag_audit: Option<Vec<FlagAccess>>, // Every VF access by an instruction, when auditing.
+    profile: Option<Profile>, // Time spent per opcode family, when profiling.
+    peripheral: Option<(RangeInclusive<u16>, Box<dyn Peripheral + Send>)>, // Device mapped over part of memory.
+    captured_frames: Option<Vec<Frame>>, // Display after every timer tick, when capturing.
+    paused_on_unknown: Option<(u16, u16)>, // (pc, opcode) of the opcode we stopped at.
+    pc_overrun: Option<u16>, // Where PC ran off the end of memory, when `strict_pc` stopped there.
+
+    // Last few (pc, opcode) pairs executed, oldest first, for crash reports.
+    trace: [(u16, u16); TRACE_LEN],
+    trace_len: usize,
 }
 
 impl Chip8 {
     pub fn new() -> Chip8 {
+        Chip8::with_seed(rand::random())
+    }
+
+    pub fn with_seed(seed: u64) -> Chip8 {
         let mut c = Chip8 {
             opcode: 0,
             memory: [0; 4096],
@@ -55,655 +300,3496 @@ impl Chip8 {
             index: 0,
             pc: 0x0200, // PC starts at 0x0200
             gfx: [[0; 64]; 32],
+            drawn: false,
+            scrolled: false,
             delay_timer: 0,
             sound_timer: 0,
-            stack: [0; 16],
+            sound_ran: false,
+            sounding: false,
+            audio_enabled: true,
+            audio_pattern: audio::DEFAULT_PATTERN,
+            pitch: audio::DEFAULT_PITCH,
+            stack: vec![0; STACK_SIZE],
             sp: 0,
             keys: [0; 16],
+            held: [false; 16],
+            vblank: false,
+            key_repeat: KeyRepeat::Hold,
+            key_order: STANDARD_KEY_ORDER,
+            key_sampling: KeySampling::PerCycle,
+            sampled: [false; 16],
+            key_callback: None,
             show_debug: false,
-            redraw: false,
-            draw_queue: VecDeque::new(),
+            debug_output: None,
+            json_trace: None,
+            pause_on_unknown: false,
+            strict_pc: false,
+            check_targets: false,
+            quirks: Quirks::default(),
+            ips: DEFAULT_IPS,
+            timer_hz: TIMER_HZ,
+            events: Vec::new(),
+            coalesce_events: false,
+            warnings: Vec::new(),
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            rng_draws: 0,
+            cycles: 0,
+            coverage: None,
+            flag_audit: None,
+            profile: None,
+            peripheral: None,
+            rng_log: None,
+            rng_replay: None,
+            captured_frames: None,
+            paused_on_unknown: None,
+            pc_overrun: None,
+            paused: false,
+            pause_on_focus_lost: false,
+            trace: [(0, 0); TRACE_LEN],
+            trace_len: 0,
         };
 
         c.fontset_into_mem();
         c
     }
 
-    pub fn key_pressed(&mut self, key_index: usize) {
-        self.keys[key_index] = 1;
+    // Hand over everything that happened to the display and sound since the
+    // last call, in order. The buffer keeps its allocation between frames, a Vec
+    // drained once per frame benchmarked about twice as fast as popping from a
+    // VecDeque. It is empty afterwards even if the iterator is dropped early.
+    pub fn drain_events(&mut self) -> impl Iterator<Item = Chip8Event> + '_ {
+        self.coalesce_pending();
+        self.events.drain(..)
     }
 
-    pub fn key_released(&mut self, key_index: usize) {
-        self.keys[key_index] = 0;
+    // Like `drain_events`, for callers that want to keep the events around.
+    pub fn take_events(&mut self) -> Vec<Chip8Event> {
+        self.coalesce_pending();
+        std::mem::take(&mut self.events)
     }
 
-    fn fontset_into_mem(&mut self) {
-        // Load fontset into memory.
-        for i in 0..80 {
-            self.memory[i] = FONTSET[i];
+    fn coalesce_pending(&mut self) {
+        if self.coalesce_events {
+            self.events = events::coalesce(&self.events);
         }
     }
 
-    fn clear_screen(&mut self) {
-        self.gfx = [[0; 64]; 32];
-        self.redraw = true;
-    }
+    // Show the events since the last call to each observer in turn, then
+    // drop them like `drain_events` would.
+    pub fn publish_events(&mut self, observers: &mut [&mut dyn Observer]) {
+        self.coalesce_pending();
+        let mut events = std::mem::take(&mut self.events);
 
-    pub fn load_rom(&mut self, rom: &[u8;4096 - 0x200]) {
-        let mut mem = self.memory[..0x200].to_vec();
-        mem.extend_from_slice(rom);
+        for observer in observers.iter_mut() {
+            observer.observe(self, &events);
+        }
 
-        self.memory.copy_from_slice(&mem);
+        events.clear();
+        self.events = events;
     }
 
-    fn get_nibble(&self, i: u8) -> u8 {
-        let shift = (i % 4) * 4;
-        let mask = 0xF << shift;
+    fn emit(&mut self, event: Chip8Event) {
+        self.events.push(event);
+    }
 
-        ((self.opcode & mask) >> shift) as u8
+    // Hand over the compatibility warnings raised since the last call.
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        std::mem::take(&mut self.warnings)
     }
 
-    fn reg_dump(&mut self, end_index: u8) {
-        let mut offset = self.index;
-        for i in 0..(end_index+1) {
-            self.memory[offset as usize] = self.registers[i as usize];
-            offset += 1;
+    fn warn(&mut self, warning: Warning) {
+        if self.warnings.len() < MAX_WARNINGS {
+            self.warnings.push(warning);
         }
     }
 
-    fn reg_load(&mut self, end_index: u8) {
-        let mut offset = self.index;
-        for i in 0..(end_index+1) {
-            self.registers[i as usize] = self.memory[offset as usize];
-            offset += 1;
+    // Flag a store starting at `addr` that lands on the font.
+    fn check_font_write(&mut self, addr: u16) {
+        if addr < FONT_END {
+            self.warn(Warning::FontWrite { pc: self.pc, addr });
         }
     }
 
-    fn perform_opcode(&mut self) {
-
-        // Get next opcode.
-        self.opcode = (self.memory[self.pc as usize] as u16) << 8 | self.memory[self.pc as usize + 1] as u16;
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
 
-        if self.show_debug {
-            println!("PC: {}, opcode: <{:#X?}>", self.pc, self.opcode);
-        }
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
 
-        // Store values that some opcodes need to use.
-        let x: u8 = self.get_nibble(2);
-        let y: u8 = self.get_nibble(1);
-        let n: u8 = (self.opcode & 0xF) as u8;
-        let nn: u8 = (self.opcode & 0xFF) as u8;
-        let nnn: u16 = self.opcode & 0xFFF;
+    // Send the `show_debug` messages here rather than to stdout.
+    pub fn set_debug_output(&mut self, output: Box<dyn Write + Send>) {
+        self.debug_output = Some(output);
+    }
 
+    // Write a JSON line describing every instruction from now on to `output`,
+    // see `TraceRecord`. Independent of `show_debug` and its messages.
+    pub fn set_json_trace(&mut self, output: Box<dyn Write + Send>) {
+        self.json_trace = Some(output);
+    }
 
-        // Decode opcode.
-        match self.opcode & 0xF000 {
-            0x0000 => match self.opcode & 0x000F {
-                // Clear Screen
-                0x0000 => {
-                    self.clear_screen();
-                    self.pc += 2;
+    fn write_trace_record(&mut self, pc: u16, registers: [u8; 16], index: u16) {
+        let record = TraceRecord {
+            cycle: self.cycles,
+            pc,
+            opcode: self.opcode,
+            registers: VReg::ALL.iter().copied()
+                .filter(|reg| self.registers[reg.index()] != registers[reg.index()])
+                .map(|reg| (reg, self.registers[reg.index()]))
+                .collect(),
+            index: if self.index != index { Some(self.index) } else { None },
+        };
 
-                    if self.show_debug {
-                        println!("\tClearing screen.");
-                    }
-                },
+        if let Some(output) = self.json_trace.as_mut() {
+            if let Err(e) = writeln!(output, "{}", record.to_json()) {
+                println!("[-] Couldn't write JSON trace: {}", e);
+                self.json_trace = None;
+            }
+        }
+    }
 
-                // 0x00EE => Return from a subroutine
-                0x000E => {
-                    self.sp -= 1;
-                    self.pc = self.stack[self.sp as usize];
+    fn debug_line(&mut self, line: &str) {
+        match self.debug_output.as_mut() {
+            Some(output) => {
+                if let Err(e) = writeln!(output, "{}", line) {
+                    println!("[-] Couldn't write debug output: {}", e);
+                    self.debug_output = None;
+                }
+            },
+            None => println!("{}", line),
+        }
+    }
 
-                    self.pc += 2;
+    // Set when `pause_on_unknown` stopped execution, as (pc, opcode).
+    pub fn paused_on_unknown(&self) -> Option<(u16, u16)> {
+        self.paused_on_unknown
+    }
 
-                    if self.show_debug {
-                        println!("\tReturning from subroutine, setting sp={} pc={}+2", self.sp, self.pc - 2);
-                    }
-                },
+    // Set when `strict_pc` stopped execution, as the PC that ran off the end.
+    // Only a reset or loading a state gets going again.
+    pub fn pc_overrun(&self) -> Option<u16> {
+        self.pc_overrun
+    }
 
-                _ => println!("NOP"),
-            },
+    // Execution is stopped until the frontend deals with it, see
+    // `paused_on_unknown` and `pc_overrun`. Unlike `paused` the ROM caused it.
+    pub fn stopped(&self) -> bool {
+        self.paused_on_unknown.is_some() || self.pc_overrun.is_some()
+    }
 
-            // 0x1NNN => jump to address NNN
-            0x1000 => {
-                self.pc = nnn;
+    // Why a bounded run has to end early, if it does.
+    fn stop_result(&self) -> Option<RunResult> {
+        if self.paused_on_unknown.is_some() {
+            Some(RunResult::PausedOnUnknown)
+        } else if self.pc_overrun.is_some() {
+            Some(RunResult::PcOverrun)
+        } else {
+            None
+        }
+    }
 
-                if self.show_debug {
-                    println!("\tJumping to address {}", nnn);
-                }
-            },
+    // Skip over the opcode we paused on and carry on executing.
+    pub fn resume_from_unknown(&mut self) {
+        if self.paused_on_unknown.take().is_some() {
+            self.pc += 2;
+        }
+    }
 
-            // 0x2NNN => call subroutine at NNN
-            0x2000 => {
-                self.stack[self.sp as usize] = self.pc;
+    // The most recently executed (pc, opcode) pairs, oldest first.
+    pub fn recent_trace(&self) -> &[(u16, u16)] {
+        &self.trace[..self.trace_len]
+    }
 
-                self.sp += 1;
+    fn push_trace(&mut self) {
+        if self.trace_len == TRACE_LEN {
+            self.trace.copy_within(1.., 0);
+            self.trace_len -= 1;
+        }
 
-                self.pc = nnn;
+        self.trace[self.trace_len] = (self.pc, self.opcode);
+        self.trace_len += 1;
+    }
 
-                if self.show_debug {
-                    println!("\tCalling subroutine at {}", nnn);
-                }
-            },
+    // Whether the buzzer should be on until the next timer tick. It follows
+    // the tick that just happened rather than the timer itself, so a timer
+    // set to 1 and gone again by the end of the frame still beeps for a tick.
+    pub fn sound_active(&self) -> bool {
+        self.audio_enabled && self.sounding
+    }
 
-            // 0x3XNN => skip next instruction if register VX == NN
-            0x3000 => {
-                let val = self.registers[x as usize];
-                if val == nn {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
-                }
+    // Stop (or start again) the beep events and `sound_active`, with the sound
+    // timer counting down the same either way. A beep in progress is ended or
+    // started with the change, so frontends following the events don't get stuck.
+    pub fn set_audio_enabled(&mut self, enabled: bool) {
+        if enabled == self.audio_enabled {
+            return;
+        }
 
-                if self.show_debug {
-                    println!("\tSkip next if V{}({})=={}", x, val, nn);
-                }
-            },
+        if self.sound_timer > 0 {
+            self.emit(if enabled { Chip8Event::BeepStart } else { Chip8Event::BeepStop });
+        }
+        self.audio_enabled = enabled;
+    }
 
-            // 0x4XNN => skip next if VX != NN
-            0x4000 => {
-                let val = self.registers[x as usize];
-                if val != nn {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
-                }
+    pub fn audio_enabled(&self) -> bool {
+        self.audio_enabled
+    }
 
-                if self.show_debug {
-                    println!("\tSkip next if V{}({})!={}", x, val, nn);
-                }
-            },
+    pub fn audio_pattern(&self) -> &[u8; 16] {
+        &self.audio_pattern
+    }
 
-            // 0x5XY0 => skip next if VX == VY
-            0x5000 => {
-                let valx = self.registers[x as usize];
-                let valy = self.registers[y as usize];
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
 
-                if valx == valy {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
-                }
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
 
-                if self.show_debug {
-                    println!("\tSkip next if V{}({})==V{}({})", x, valx, y, valy);
-                }
-            },
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
 
-            // 0x6XNN => VX = NN
-            0x6000 => {
-                self.registers[x as usize] = nn;
+    // The next instruction jumps to itself, how most ROMs finish up.
+    pub fn halted(&self) -> bool {
+        let next = Opcode(self.fetch());
 
-                self.pc += 2;
+        next.family() == 0x1 && next.nnn() == self.pc
+    }
 
-                if self.show_debug {
-                    println!("\tSet V{}={}", x, nn);
-                }
-            },
+    pub fn index(&self) -> u16 {
+        self.index
+    }
 
-            // 0x7XNN => VX += NN
-            0x7000 => {
+    // Whether the ROM touched the display yet. The VIP showed nothing at all
+    // until then, which a frontend can copy by not presenting frames before.
+    pub fn has_drawn(&self) -> bool {
+        self.drawn
+    }
 
-                let val = &mut self.registers[x as usize];
-                let prev_val = *val;
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
 
-                *val = (*val).wrapping_add(nn);
+    pub fn reg(&self, reg: VReg) -> u8 {
+        self.registers[reg.index()]
+    }
 
-                self.pc += 2;
+    pub fn set_reg(&mut self, reg: VReg, value: u8) {
+        self.registers[reg.index()] = value;
+    }
 
-                if self.show_debug {
-                    println!("\tV{}={} wrapadd {} = {}", x, prev_val, nn, *val);
-                }
-            },
+    // Register access for the instruction running, which the flag audit sees.
+    fn read_reg(&mut self, reg: VReg) -> u8 {
+        let value = self.reg(reg);
+        if reg == VReg::VF {
+            self.audit_flag(false, value);
+        }
+        value
+    }
 
-            0x8000 => match self.opcode & 0x000F {
+    fn write_reg(&mut self, reg: VReg, value: u8) {
+        if reg == VReg::VF {
+            self.audit_flag(true, value);
+        }
+        self.set_reg(reg, value);
+    }
 
-                // 0x8XY0 => VX = VY
-                0x0000 => {
-                    let val = self.registers[y as usize];
+    // Carry, borrow, shifted out bit or collision, see `VReg::VF`.
+    fn set_flag(&mut self, value: u8) {
+        self.write_reg(VReg::VF, value);
+    }
 
-                    self.registers[x as usize] = val;
+    fn audit_flag(&mut self, write: bool, value: u8) {
+        if let Some(log) = self.flag_audit.as_mut() {
+            log.push(FlagAccess { cycle: self.cycles, pc: self.pc, opcode: self.opcode, write, value });
+        }
+    }
 
-                    self.pc += 2;
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
 
-                    if self.show_debug {
-                        println!("\tV{}=V{} ({:#X?})", x, y, val);
-                    }
-                },
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
 
-                // 0x8XY1 => VX = VX | VY
-                0x0001 => {
-                    let xval = self.registers[x as usize];
-                    let yval = self.registers[y as usize];
+    // Addresses of the 2NNN calls on the stack, innermost call last. Each one
+    // returns to the instruction after it.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.sp as usize]
+    }
 
-                    let result = xval | yval;
-                    self.registers[x as usize] = result;
+    // The calls in progress as (call site, subroutine) pairs, innermost last.
+    // The subroutine is read back from the 2NNN at the call site, so it is
+    // wrong if the ROM has rewritten that instruction since.
+    pub fn call_trace(&self) -> Vec<(u16, u16)> {
+        self.stack().iter().map(|&site| {
+            let hi = self.memory.get(site as usize).copied().unwrap_or(0);
+            let lo = self.memory.get(site as usize + 1).copied().unwrap_or(0);
+            (site, Opcode::from_bytes(hi, lo).nnn())
+        }).collect()
+    }
 
-                    self.pc += 2;
+    // Every address where `needle` occurs in memory, empty or oversized needles never match.
+    pub fn find_bytes(&self, needle: &[u8]) -> Vec<u16> {
+        if needle.is_empty() || needle.len() > self.memory.len() {
+            return Vec::new();
+        }
 
-                    if self.show_debug {
-                        println!("\tV{}=V{}({:#X?}) | V{}({:#X?}) -> {:#X?}", x, x, xval, y, yval, result);
-                    }
-                },
+        self.memory.windows(needle.len())
+            .enumerate()
+            .filter(|(_, window)| *window == needle)
+            .map(|(addr, _)| addr as u16)
+            .collect()
+    }
 
-                // 0x8XY2 => VX = VX & VY
-                0x0002 => {
-                    let xval = self.registers[x as usize];
-                    let yval = self.registers[y as usize];
+    // Start recording every time an instruction reads or writes VF, see
+    // `FlagAccess`. Off by default, it costs a check on each register access.
+    pub fn enable_flag_audit(&mut self) {
+        if self.flag_audit.is_none() {
+            self.flag_audit = Some(Vec::new());
+        }
+    }
 
-                    let result = xval & yval;
-                    self.registers[x as usize] = result;
+    pub fn flag_audit(&self) -> &[FlagAccess] {
+        self.flag_audit.as_deref().unwrap_or(&[])
+    }
 
-                    self.pc += 2;
+    pub fn take_flag_audit(&mut self) -> Vec<FlagAccess> {
+        self.flag_audit.as_mut().map_or_else(Vec::new, std::mem::take)
+    }
 
-                    if self.show_debug {
-                        println!("\tV{}=V{}({:#X?}) & V{}({:#X?}) -> {:#X?}", x, x, xval, y, yval, result);
-                    }
-                },
+    // Start counting how many times each address gets executed.
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(vec![0; self.memory.len()]);
+    }
 
-                // 0x8XY3 => VX = VX ^(bitwise xor) VY
-                0x0003 => {
-                    let xval = self.registers[x as usize];
-                    let yval = self.registers[y as usize];
+    pub fn coverage(&self) -> Option<&[u32]> {
+        self.coverage.as_deref()
+    }
 
-                    let result = xval ^ yval;
-                    self.registers[x as usize] = result;
+    // Start timing how long each opcode family takes to execute. The clock is
+    // only read while profiling, so normal runs pay nothing for it.
+    pub fn enable_profiling(&mut self) {
+        self.profile = Some(Profile::default());
+    }
 
-                    self.pc += 2;
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
 
-                    if self.show_debug {
-                        println!("\tV{}=V{}({:#X?}) ^ V{}({:#X?}) -> {:#X?}", x, x, xval, y, yval, result);
-                    }
-                },
+    // Start keeping every random number CXNN uses, see `rng_log`.
+    pub fn enable_rng_log(&mut self) {
+        if self.rng_log.is_none() {
+            self.rng_log = Some(Vec::new());
+        }
+    }
 
-                // 0x8XY4 => VX += VY, set VF to 1 if there is a carry, 0 if not
-                0x0004 => {
-                    let xval = self.registers[x as usize] as u16;
-                    let yval = self.registers[y as usize] as u16;
+    // The random numbers used since `enable_rng_log`, oldest first.
+    pub fn rng_log(&self) -> &[u8] {
+        self.rng_log.as_deref().unwrap_or(&[])
+    }
 
-                    let result = xval + yval;
-                    self.registers[x as usize] = result as u8;
+    // Hand CXNN these numbers, in order, instead of drawing from the seeded
+    // generator. A run fed the numbers another run logged gets the same
+    // results even if the generator changes between versions. Once they run
+    // out the generator takes over again, with a warning.
+    pub fn replay_rng(&mut self, numbers: Vec<u8>) {
+        self.rng_replay = Some(numbers.into_iter());
+    }
 
-                    // Set carry flag appropriately.
-                    self.registers[0xF] = if result > 0xFF { 1 } else { 0 };
+    fn next_random(&mut self) -> u8 {
+        let r = match self.rng_replay.as_mut().map(|numbers| numbers.next()) {
+            Some(Some(r)) => r,
+            replaying => {
+                if replaying.is_some() {
+                    self.rng_replay = None;
+                    self.warn(Warning::RngExhausted { pc: self.pc });
+                }
 
-                    self.pc += 2;
+                self.rng_draws += 1;
+                self.rng.gen()
+            },
+        };
 
-                    if self.show_debug {
-                        println!("\tV{}=V{}({:#X?}) + V{}({:#X?}) -> {:#X?}", x, x, xval, y, yval, result);
-                    }
-                },
+        if let Some(log) = self.rng_log.as_mut() {
+            log.push(r);
+        }
+        r
+    }
 
-                // 0x8XY5 => VX -= VY, set VF to 0 if there is a borrow, 1 if not
-                0x0005 => {
-                    let xval = self.registers[x as usize];
-                    let yval = self.registers[y as usize];
+    // Keep a copy of the display after every timer tick, so fast animations
+    // can be looked at frame by frame no matter how often the frontend renders.
+    pub fn enable_frame_capture(&mut self) {
+        if self.captured_frames.is_none() {
+            self.captured_frames = Some(Vec::new());
+        }
+    }
 
-                    let result = xval.wrapping_sub(yval);
-                    self.registers[x as usize] = result;
+    pub fn captured_frames(&self) -> &[Frame] {
+        self.captured_frames.as_deref().unwrap_or(&[])
+    }
 
-                    // Set borrow flag appropriately.
-                    self.registers[0xF] = if yval > xval { 0 } else { 1 };
+    pub fn take_captured_frames(&mut self) -> Vec<Frame> {
+        self.captured_frames.as_mut().map_or_else(Vec::new, std::mem::take)
+    }
 
-                    self.pc += 2;
+    // The display as it is now, see `FrameSnapshot::from_frame`.
+    pub fn render_to(&self, scale: u32) -> FrameSnapshot {
+        FrameSnapshot::from_frame(&self.gfx, scale)
+    }
 
-                    if self.show_debug {
-                        println!("\tV{}=V{}({:#X?}) - V{}({:#X?}) -> {:#X?}", x, x, xval, y, yval, result);
-                    }
-                },
+    // FNV-1a hash of the display, cheap enough to take every few cycles.
+    pub fn gfx_hash(&self) -> u64 {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for row in self.gfx.iter() {
+            for &pixel in row.iter() {
+                hash ^= pixel as u64;
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+        hash
+    }
 
-                // 0x8XY6 => Store least significant bit of VX in VF, then VX >>= 1
-                0x0006 => {
-                    let xval = self.registers[x as usize];
+    // The display as an SVG, one unit square per lit pixel on a background rect.
+    pub fn to_svg(&self, fg: &str, bg: &str) -> String {
+        let height = self.gfx.len();
+        let width = self.gfx[0].len();
+
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {w} {h}\" shape-rendering=\"crispEdges\">\n\
+             <rect width=\"{w}\" height=\"{h}\" fill=\"{bg}\"/>\n",
+            w = width, h = height, bg = bg
+        );
+
+        for (y, row) in self.gfx.iter().enumerate() {
+            for (x, &pixel) in row.iter().enumerate() {
+                if pixel != 0 {
+                    svg.push_str(&format!("<rect x=\"{}\" y=\"{}\" width=\"1\" height=\"1\" fill=\"{}\"/>\n", x, y, fg));
+                }
+            }
+        }
 
-                    let least_sig_bit = x & 0x1;
-                    let result = xval >> 1;
+        svg.push_str("</svg>\n");
+        svg
+    }
 
-                    self.registers[x as usize] = result;
+    // The display as text, `#` for lit pixels and `.` for dark ones, one line per row.
+    pub fn to_ascii(&self) -> String {
+        let mut text = String::new();
 
-                    // Store least sig in VF
-                    self.registers[0xF] = least_sig_bit;
+        for row in self.gfx.iter() {
+            text.extend(row.iter().map(|&pixel| if pixel != 0 { '#' } else { '.' }));
+            text.push('\n');
+        }
 
-                    self.pc += 2;
+        text
+    }
 
-                    if self.show_debug {
-                        println!("\tV{}=V{}({:#X?}) >> 1) -> {:#X?}", x, x, xval, result);
+    // The display in braille, each character a 2x4 block of pixels, so the
+    // whole screen fits in 32 columns by 8 lines.
+    pub fn gfx_braille(&self) -> String {
+        // Braille dot bits for the pixels of a block, by row then column.
+        const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+        let mut text = String::new();
+
+        for rows in self.gfx.chunks(4) {
+            for x in (0..rows[0].len()).step_by(2) {
+                let mut bits = 0;
+                for (dy, row) in rows.iter().enumerate() {
+                    for dx in 0..2 {
+                        if row[x + dx] != 0 {
+                            bits |= DOTS[dy][dx];
+                        }
                     }
-                },
+                }
+                text.push(std::char::from_u32(0x2800 + bits).unwrap());
+            }
+            text.push('\n');
+        }
 
-                // 0x8XY7 => VX = VY - VX, set VF to to 0 when borrow, 1 if not
-                0x0007 => {
-                    let xval = self.registers[x as usize];
-                    let yval = self.registers[y as usize];
+        text
+    }
 
-                    let result = yval.wrapping_sub(xval);
-                    self.registers[x as usize] = result;
+    // The font glyph FX29 points at for `digit`, as read from memory, in the
+    // same `#`/`.` text as `to_ascii`.
+    pub fn glyph_to_ascii(&self, digit: u8) -> String {
+        let start = (digit & 0xF) as usize * 5;
+        let mut text = String::new();
 
-                    // Set borrow flag appropriately.
-                    self.registers[0xF] = if xval > yval { 0 } else { 1 };
+        for &row in self.memory[start..start + 5].iter() {
+            text.extend((0..4).map(|bit| if row & (0x80 >> bit) != 0 { '#' } else { '.' }));
+            text.push('\n');
+        }
 
-                    self.pc += 2;
+        text
+    }
 
-                    if self.show_debug {
-                        println!("\tV{}=V{}({:#X?}) - V{}({:#X?}) -> {:#X?}", x, y, yval, x, xval, result);
-                    }
-                },
+    // The sprite a DXYN of `height` would draw from I right now, as read from
+    // memory (a peripheral isn't asked), in the same `#`/`.` text as
+    // `to_ascii`. A height of 0 is the 16x16 sprite, like DXY0.
+    pub fn sprite_ascii(&self, height: u8) -> String {
+        let (width, rows) = if height == 0 { (16, 16) } else { (8, height as usize) };
+        let bytes_per_row = width / 8;
+        let mut text = String::new();
+
+        for row in 0..rows {
+            for byte in 0..bytes_per_row {
+                let bits = self.memory[(self.index as usize + row * bytes_per_row + byte) % 4096];
+                text.extend((0..8).map(|bit| if bits & (0x80 >> bit) != 0 { '#' } else { '.' }));
+            }
+            text.push('\n');
+        }
 
-                // 0x8XYE => VX = Store most significant bit of VX in VF, then VX <<= 1
-                0x000E => {
-                    let xval = self.registers[x as usize];
+        text
+    }
 
-                    let most_sig_bit = (x & 0x80) >> 7;
-                    let result = (xval & 0x7F) << 1;
+    // (x, y) of every pixel lit on only one of the two displays, row by row.
+    pub fn gfx_diff(&self, other: &Chip8) -> Vec<(usize, usize)> {
+        let mut diffs = Vec::new();
 
-                    self.registers[x as usize] = result;
+        for (y, (row_a, row_b)) in self.gfx.iter().zip(other.gfx.iter()).enumerate() {
+            for (x, (a, b)) in row_a.iter().zip(row_b.iter()).enumerate() {
+                if a != b {
+                    diffs.push((x, y));
+                }
+            }
+        }
 
-                    // Store most sig in VF
-                    self.registers[0xF] = most_sig_bit;
+        diffs
+    }
 
-                    self.pc += 2;
+    // The display as comma separated run lengths, row by row, alternating
+    // between unlit and lit and starting with unlit (so maybe a 0 first).
+    pub fn gfx_rle(&self) -> String {
+        let mut runs = Vec::new();
+        let mut on = 0;
+        let mut len = 0;
+
+        for &pixel in self.gfx.iter().flatten() {
+            if pixel != on {
+                runs.push(len.to_string());
+                on = pixel;
+                len = 0;
+            }
+            len += 1;
+        }
+        runs.push(len.to_string());
 
-                    if self.show_debug {
-                        println!("\tV{}=V{}({:#X?}) << 1) -> {:#X?}", x, x, xval, result);
-                    }
-                },
+        runs.join(",")
+    }
 
-                _ => println!("NOP"),
-            },
+    // PC, I, the timers and every register, as plain text.
+    pub fn state_report(&self) -> String {
+        let mut report = format!("PC: {:03X}  opcode: {:04X}  I: {:03X}  SP: {}  DT: {:02X}  ST: {:02X}  cycles: {}\n",
+            self.pc, self.opcode, self.index, self.sp, self.delay_timer, self.sound_timer, self.cycles);
+
+        for (row, chunk) in self.registers.chunks(8).enumerate() {
+            let regs: Vec<String> = chunk.iter().enumerate()
+                .map(|(i, value)| format!("V{:X}={:02X}", row * 8 + i, value))
+                .collect();
+            report.push_str(&regs.join(" "));
+            report.push('\n');
+        }
 
-            // 0x9XY0 => skips next instruction if VX != VY
-            0x9000 => {
-                let xval = self.registers[x as usize];
-                let yval = self.registers[y as usize];
+        report
+    }
 
-                if xval != yval {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
-                }
+    // Everything worth knowing when emulation falls over, as plain text.
+    pub fn crash_report(&self) -> String {
+        let mut report = self.state_report();
 
-                if self.show_debug {
-                    println!("\tSkip next if V{}({})!=V{}({})", x, xval, y, yval);
-                }
-            },
+        report.push_str("Calls:\n");
+        for (site, subroutine) in self.call_trace() {
+            report.push_str(&format!("  {:03X}: CALL #{:03X}\n", site, subroutine));
+        }
 
-            // 0xANNN => set index to NNN
-            0xA000 => {
-                self.index = nnn;
+        report.push_str("Recent instructions:\n");
+        for &(pc, opcode) in self.recent_trace() {
+            report.push_str(&format!("  {:03X}: {:04X}  {}\n", pc, opcode, disasm::disassemble(opcode)));
+        }
 
-                self.pc += 2;
+        report.push_str(&format!("Framebuffer hash: {:016X}\n", self.gfx_hash()));
+        report.push_str(&format!("Framebuffer RLE: {}\n", self.gfx_rle()));
+        report
+    }
 
-                if self.show_debug {
-                    println!("\tSetting I(index) to {}.", nnn);
+    // Copy out the machine state, see `SaveState` for what survives a restore.
+    pub fn save_state(&self) -> SaveState {
+        SaveState {
+            memory: self.memory.to_vec(),
+            registers: self.registers,
+            index: self.index,
+            pc: self.pc,
+            stack: self.stack.clone(),
+            sp: self.sp,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            keys: self.keys,
+            held: self.held,
+            vblank: self.vblank,
+            audio_pattern: self.audio_pattern,
+            pitch: self.pitch,
+            gfx: self.gfx,
+            seed: self.seed,
+            rng_draws: self.rng_draws,
+            cycles: self.cycles,
+        }
+    }
+
+    pub fn load_state(&mut self, state: &SaveState) {
+        let was_beeping = self.sound_timer > 0;
+
+        self.memory.copy_from_slice(&state.memory);
+        self.registers = state.registers;
+        self.index = state.index;
+        self.pc = state.pc;
+        self.stack = state.stack.clone();
+        self.sp = state.sp;
+        self.delay_timer = state.delay_timer;
+        self.sound_timer = state.sound_timer;
+        self.sound_ran = state.sound_timer > 0;
+        self.sounding = state.sound_timer > 0;
+        self.keys = state.keys;
+        self.held = state.held;
+        self.sampled = state.held;
+        self.vblank = state.vblank;
+        self.audio_pattern = state.audio_pattern;
+        self.pitch = state.pitch;
+        self.gfx = state.gfx;
+        self.seed = state.seed;
+        self.rng = StdRng::seed_from_u64(state.seed);
+        for _ in 0..state.rng_draws {
+            self.rng.gen::<u8>();
+        }
+        self.rng_draws = state.rng_draws;
+        self.cycles = state.cycles;
+
+        self.paused_on_unknown = None;
+        self.pc_overrun = None;
+        self.trace_len = 0;
+
+        // Whatever is on screen was put there by the ROM.
+        self.drawn = true;
+        // Clearing the events may have dropped a beep change, say where it stands now.
+        self.queue_repaint();
+        if self.audio_enabled {
+            if self.sound_timer > 0 {
+                self.emit(Chip8Event::BeepStart);
+            } else if was_beeping {
+                self.emit(Chip8Event::BeepStop);
+            }
+        }
+    }
+
+    // Whatever was queued belongs to the old picture, replace it with the
+    // events that paint the current one from scratch.
+    fn queue_repaint(&mut self) {
+        self.events.clear();
+        self.emit(Chip8Event::ClearScreen);
+        for (y, row) in self.gfx.iter().enumerate() {
+            for (x, &pixel) in row.iter().enumerate() {
+                if pixel != 0 {
+                    self.events.push(Chip8Event::Draw { x: x as u16, y: y as u16, on: true });
                 }
-            },
+            }
+        }
+    }
+
+    // Just the display, for golden images and display diffs where a whole
+    // `SaveState` is more than needed: DISPLAY_MAGIC, the width and height,
+    // then the pixels row by row, eight to a byte with the leftmost in the
+    // high bit.
+    pub fn save_display(&self) -> Vec<u8> {
+        let width = self.gfx[0].len();
+        let mut bytes = DISPLAY_MAGIC.to_vec();
+        bytes.push(width as u8);
+        bytes.push(self.gfx.len() as u8);
+
+        for row in self.gfx.iter() {
+            for chunk in row.chunks(8) {
+                bytes.push(chunk.iter().fold(0, |byte, &pixel| byte << 1 | (pixel != 0) as u8));
+            }
+        }
+
+        bytes
+    }
 
-            // 0xBNNN => set PC to V0 + NNN
-            0xB000 => {
-                self.pc = self.registers[0] as u16 + nnn;
+    // Put back a display from `save_display` and queue the events to repaint
+    // it. Nothing else about the machine changes.
+    pub fn load_display(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
 
-                if self.show_debug {
-                    println!("\tSetting PC to V0 ({:#?}) + {:X?} = ({:#?})", self.registers[0], nnn, self.pc);
+        let width = self.gfx[0].len();
+        let height = self.gfx.len();
+        if bytes.len() < 6 || &bytes[..4] != DISPLAY_MAGIC {
+            return Err(invalid("not a saved display"));
+        }
+        if (bytes[4] as usize, bytes[5] as usize) != (width, height) {
+            return Err(invalid(&format!("saved display is {}x{}, not {}x{}", bytes[4], bytes[5], width, height)));
+        }
+
+        let pixels = &bytes[6..];
+        if pixels.len() != width / 8 * height {
+            return Err(invalid("saved display is the wrong length"));
+        }
+
+        for (row, packed) in self.gfx.iter_mut().zip(pixels.chunks(width / 8)) {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                *pixel = (packed[x / 8] >> (7 - x % 8)) & 1;
+            }
+        }
+
+        self.queue_repaint();
+        Ok(())
+    }
+
+    pub fn is_key_down(&self, key_index: usize) -> bool {
+        self.keys[key_index] != 0
+    }
+
+    // Every key as the ROM sees it, 1 for down, for comparing input state in bulk.
+    pub fn keys_snapshot(&self) -> [u8; 16] {
+        self.keys
+    }
+
+    // Poke a register directly, out of range indexes are ignored.
+    pub fn set_register(&mut self, index: usize, value: u8) {
+        if let Some(reg) = self.registers.get_mut(index) {
+            *reg = value;
+        }
+    }
+
+    // Point I somewhere directly, masked to the 4K of memory, for setting up a
+    // test of FX33, DXYN or FX55/FX65 without an ANNN first.
+    pub fn set_index(&mut self, index: u16) {
+        self.index = index & 0xFFF;
+    }
+
+    // Set all of V0-VF at once, for putting a test in place without running setup code.
+    pub fn set_registers(&mut self, registers: [u8; 16]) {
+        self.registers = registers;
+    }
+
+    // Takes effect from the next `cycle`, so pressing a key before running an
+    // FX0A satisfies it straight away instead of blocking.
+    pub fn key_pressed(&mut self, key_index: usize) {
+        self.set_key(key_index, 1);
+    }
+
+    pub fn key_released(&mut self, key_index: usize) {
+        self.set_key(key_index, 0);
+    }
+
+    // Call when the window loses keyboard focus, the key ups would never
+    // arrive otherwise. Releases every held key, pauses if `pause_on_focus_lost`
+    // is set, and returns the keys released so they can be recorded.
+    pub fn on_focus_lost(&mut self) -> Vec<usize> {
+        let released: Vec<usize> = (0..self.held.len()).filter(|&key| self.held[key]).collect();
+        for &key in released.iter() {
+            self.key_released(key);
+        }
+
+        if self.pause_on_focus_lost {
+            self.paused = true;
+        }
+
+        released
+    }
+
+    // Observe key changes, e.g. to highlight an on-screen keypad.
+    pub fn set_key_callback<F: FnMut(usize, bool) + Send + 'static>(&mut self, callback: F) {
+        self.key_callback = Some(Box::new(callback));
+    }
+
+    pub fn clear_key_callback(&mut self) {
+        self.key_callback = None;
+    }
+
+    fn set_key(&mut self, key_index: usize, state: u8) {
+        let changed = self.held[key_index] != (state != 0);
+        self.held[key_index] = state != 0;
+
+        // Host key repeat sends the same state again, only report real changes.
+        // Ignoring repeats also keeps a consumed pulse from coming back.
+        if changed {
+            if self.key_sampling == KeySampling::PerCycle {
+                self.keys[key_index] = state;
+                self.sampled[key_index] = state != 0;
+            }
+
+            if let Some(callback) = self.key_callback.as_mut() {
+                callback(key_index, state != 0);
+            }
+        }
+    }
+
+    fn fontset_into_mem(&mut self) {
+        // Load fontset into memory.
+        self.memory[..80].copy_from_slice(&FONTSET);
+    }
+
+    fn clear_screen(&mut self) {
+        self.gfx = [[0; 64]; 32];
+        self.emit(Chip8Event::ClearScreen);
+    }
+
+    // Move the picture up `rows` pixels, the rows coming in at the bottom are blank.
+    fn scroll_up(&mut self, rows: usize) {
+        let before = self.gfx;
+
+        for y in 0..32 {
+            self.gfx[y] = if y + rows < 32 { before[y + rows] } else { [0; 64] };
+        }
+
+        for (y, row) in self.gfx.iter().enumerate() {
+            for (x, &pixel) in row.iter().enumerate() {
+                if pixel != before[y][x] {
+                    self.events.push(Chip8Event::Draw { x: x as u16, y: y as u16, on: pixel != 0 });
                 }
-            },
+            }
+        }
+    }
 
-            // 0xCXNN => set VX to some random number (0-255), R & NN
-            0xC000 => {
-                let r: u8 = rand::thread_rng().gen();
+    fn set_sound_timer(&mut self, value: u8) {
+        self.sound_ran |= value > 0;
 
-                let result = r & nn;
+        // With audio off the timer counts down as usual, just silently.
+        if self.audio_enabled {
+            if self.sound_timer == 0 && value > 0 {
+                self.emit(Chip8Event::BeepStart);
+            } else if self.sound_timer > 0 && value == 0 {
+                self.emit(Chip8Event::BeepStop);
+            }
+        }
+
+        self.sound_timer = value;
+    }
 
-                self.registers[x as usize] = result;
+    // Put the machine back the way `with_seed` made it, as if just switched on:
+    // memory (font reloaded), registers, I, PC, the stack, timers, keys, the
+    // display, the XO-CHIP audio state, the cycle count and the random numbers
+    // (from the same seed). Settings stay, along with anything the frontend
+    // asked for: quirks, speeds, debug output, callbacks, the peripheral,
+    // coverage, profiling, the flag audit, the RNG log and captured frames, and warnings
+    // not yet taken.
+    pub fn reset(&mut self) {
+        self.opcode = 0;
+        self.memory = [0; 4096];
+        self.fontset_into_mem();
+        self.registers = [0; 16];
+        self.index = 0;
+        let was_beeping = self.sound_timer > 0;
+        self.pc = PROGRAM_START as u16;
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.sound_ran = false;
+        self.sounding = false;
+        self.audio_pattern = audio::DEFAULT_PATTERN;
+        self.pitch = audio::DEFAULT_PITCH;
+        self.stack = vec![0; STACK_SIZE];
+        self.sp = 0;
+        self.keys = [0; 16];
+        self.held = [false; 16];
+        self.sampled = [false; 16];
+        self.vblank = false;
+        self.rng = StdRng::seed_from_u64(self.seed);
+        self.rng_draws = 0;
+        self.cycles = 0;
+        self.paused_on_unknown = None;
+        self.pc_overrun = None;
+        self.trace_len = 0;
+
+        self.events.clear();
+        self.clear_screen();
+        if was_beeping && self.audio_enabled {
+            self.emit(Chip8Event::BeepStop);
+        }
+        self.drawn = false;
+        self.scrolled = false;
+    }
 
-                self.pc += 2;
+    // `load_rom` starting from a clean machine, see `reset`. `load_rom` on its
+    // own leaves registers, PC and the rest as the previous ROM left them.
+    pub fn load_rom_and_reset(&mut self, rom: &Rom) {
+        self.reset();
+        self.load_rom(rom);
+    }
 
-                if self.show_debug {
-                    println!("\tSet V{} to random# {}", x, result);
+    // ROMs are loaded at 0x200, the rest of memory after them is cleared.
+    pub fn load_rom(&mut self, rom: &Rom) {
+        let rom = rom.bytes();
+        let len = rom.len();
+
+        if !len.is_multiple_of(2) {
+            // The trailing byte still gets loaded, it pairs with the zero after it.
+            self.warn(Warning::OddRomLength(rom.len()));
+        }
+
+        let program = &mut self.memory[PROGRAM_START..];
+        program[..len].copy_from_slice(&rom[..len]);
+        for byte in program[len..].iter_mut() {
+            *byte = 0;
+        }
+
+        if self.check_targets {
+            self.check_rom_targets(len);
+        }
+    }
+
+    // Only looks at aligned words, so data mixed in with the code can still
+    // set this off and a misaligned jump can slip past.
+    fn check_rom_targets(&mut self, len: usize) {
+        for addr in (PROGRAM_START..PROGRAM_START + len).step_by(2) {
+            let op = Opcode::from_bytes(self.memory[addr], self.memory[addr + 1]);
+
+            let points = matches!(op.family(), 0x1 | 0x2 | 0xA);
+            if points && (op.nnn() as usize) < PROGRAM_START {
+                self.warn(Warning::ReservedTarget { pc: addr as u16, target: op.nnn() });
+            }
+        }
+    }
+
+    // Map `device` over `range`, replacing any device attached before.
+    pub fn attach_peripheral(&mut self, range: RangeInclusive<u16>, device: Box<dyn Peripheral + Send>) {
+        self.peripheral = Some((range, device));
+    }
+
+    pub fn detach_peripheral(&mut self) {
+        self.peripheral = None;
+    }
+
+    // Memory reads and writes made by instructions, which an attached
+    // peripheral gets to see first. Addresses past the end wrap around, so
+    // an I that FX1E pushed past 0xFFF reads from the start of memory.
+    fn read_byte(&mut self, addr: usize) -> u8 {
+        let addr = addr % 4096;
+        if let Some((range, device)) = self.peripheral.as_mut() {
+            if range.contains(&(addr as u16)) {
+                if let Some(value) = device.read(addr as u16) {
+                    return value;
                 }
-            },
+            }
+        }
+        self.memory[addr]
+    }
 
-            // 0xDXYN => Draw sprite at (VX, VY) w/ width 8pixels and height N
-            // See https://en.wikipedia.org/wiki/CHIP-8 for more info.
-            0xD000 => {
+    fn write_byte(&mut self, addr: usize, value: u8) {
+        let addr = addr % 4096;
+        if let Some((range, device)) = self.peripheral.as_mut() {
+            if range.contains(&(addr as u16)) && device.write(addr as u16, value) {
+                return;
+            }
+        }
+        self.memory[addr] = value;
+    }
 
-                let xval = self.registers[x as usize];
-                let yval = self.registers[y as usize];
+    // X is a nibble when it comes from an opcode, but anything past VF is
+    // treated as VF. Addresses wrap around the end of memory like 5XY2.
+    fn reg_dump(&mut self, end: VReg) {
+        self.check_font_write(self.index);
 
-                // Reset VF
-                self.registers[0xF] = 0;
+        let end_index = end.index();
+        let offset = self.index as usize;
+        for (i, &reg) in VReg::ALL[..=end_index].iter().enumerate() {
+            let value = self.read_reg(reg);
+            self.write_byte((offset + i) % 4096, value);
+        }
 
-                for dy in 0..n {
-                    let pixel = self.memory[(self.index + dy as u16) as usize];
+        if self.quirks.load_store_increment {
+            self.index = self.index.wrapping_add(end_index as u16 + 1);
+        }
+    }
 
-                    for dx in 0..8 {
-                        let mask = 0b10000000 >> dx;
+    fn reg_load(&mut self, end: VReg) {
+        let end_index = end.index();
+        let offset = self.index as usize;
+        for (i, &reg) in VReg::ALL[..=end_index].iter().enumerate() {
+            let value = self.read_byte((offset + i) % 4096);
+            self.write_reg(reg, value);
+        }
 
-                        // If pixel bit is set in memory.
-                        if pixel & mask != 0 {
+        if self.quirks.load_store_increment {
+            self.index = self.index.wrapping_add(end_index as u16 + 1);
+        }
+    }
 
-                            let locx = (xval + dx) as u16;
-                            let locy = (yval + dy) as u16;
+    // Let the ROM see the keys changed since the last sample, in PerFrame
+    // sampling. Called at the end of every frame like `notify_vblank`.
+    pub fn sample_keys(&mut self) {
+        for key_index in 0..self.held.len() {
+            if self.sampled[key_index] != self.held[key_index] {
+                self.sampled[key_index] = self.held[key_index];
+                self.keys[key_index] = self.held[key_index] as u8;
+            }
+        }
+    }
 
-                            if locx >= 64 || locy >= 32 {
-                                if self.show_debug {
-                                    println!("\t\tWhile drawing sprite went out of bounds at ({}.{})", locx, locy);
-                                }
-                                continue;
-                            }
+    // Whether the ROM's key number `key` in a register value is down for
+    // EX9E, EXA1 and FX0A, going through `key_order`. In pulse mode seeing it
+    // pressed uses it up. Only the low nibble names the key, like the VIP's
+    // keypad decoder, so 0x1F is key F.
+    fn read_key(&mut self, key: u8) -> bool {
+        let key_index = match self.key_order.iter().position(|&number| number == key & 0xF) {
+            Some(key_index) => key_index,
+            None => return false, // Not a permutation, nothing is numbered this.
+        };
+        let pressed = self.keys[key_index] != 0;
+        if pressed && self.key_repeat == KeyRepeat::Pulse {
+            self.keys[key_index] = 0;
+        }
+        pressed
+    }
 
-                            let data = &mut (self.gfx[locy as usize][locx as usize]);
+    // Skipped like a NOP, unless `pause_on_unknown` holds PC on it.
+    fn unknown_opcode(&mut self, opcode: u16) {
+        self.warn(Warning::UnknownOpcode { pc: self.pc, opcode });
 
-                            // Check if pixel is set on screen.
-                            if *data == 1 {
-                                self.registers[0xF] = 1;
-                            }
+        if self.pause_on_unknown {
+            self.paused_on_unknown = Some((self.pc, opcode));
+        } else {
+            self.pc += 2;
+        }
+    }
 
-                            *data = *data ^ 1;
+    // The opcode at PC, without executing it or moving PC. Like other reads
+    // it wraps around, an opcode at 0xFFF takes its low byte from 0x000.
+    pub fn fetch(&self) -> u16 {
+        let pc = self.pc as usize;
+        Opcode::from_bytes(self.memory[pc % 4096], self.memory[(pc + 1) % 4096]).0
+    }
 
-                            self.draw_queue.push_back((locx, locy, *data));
-                        }
-                    }
+    // A skip near the top of memory, or a jump to an odd address there, left
+    // PC without a whole instruction in front of it. Under `strict_pc` the
+    // machine stops there, like `pause_on_unknown` does.
+    fn pc_past_end(&mut self) {
+        if self.strict_pc {
+            self.pc_overrun = Some(self.pc);
+            return;
+        }
+
+        self.warn(Warning::PcWrap { pc: self.pc });
+        self.pc %= 4096;
+    }
+
+    pub fn execute(&mut self, instruction: Instruction) {
+        match instruction {
+            // 0x00DN => (XO-CHIP) Scroll the display up N pixels
+            //           Only low-res exists here, where SCHIP 1.1 moved half as
+            //           far (by high-res pixels). That behavior is half_pixel_scroll.
+            Instruction::ScrollUp(n) if self.quirks.xo_chip => {
+                if !self.scrolled {
+                    self.warn(Warning::LowResScroll { pc: self.pc });
+                    self.scrolled = true;
                 }
 
+                let rows = if self.quirks.half_pixel_scroll { n / 2 } else { n };
+                self.scroll_up(rows as usize);
+                self.drawn = true;
                 self.pc += 2;
 
                 if self.show_debug {
-                    println!("\tDraw sprite at (V{}({}),V{}({})) with height {}", x, xval, y, yval, n);
+                    self.debug_line(&format!("\tScrolling up {} pixels.", rows));
                 }
             },
 
-            0xE000 => match self.opcode & 0x000F {
+            // 0x00E0 => Clear Screen
+            Instruction::ClearScreen => {
+                self.clear_screen();
+                self.drawn = true;
+                self.pc += 2;
 
-                // 0xEX9E => Skips next instruction if the key stored in VX is pressed
-                0x000E => {
-                    let key = self.registers[x as usize];
+                if self.show_debug {
+                    self.debug_line("\tClearing screen.");
+                }
+            },
 
-                    if self.keys[key as usize] != 0 {
-                        self.pc += 4;
-                    } else {
-                        self.pc += 2;
+            // 0x00EE => Return from a subroutine
+            Instruction::Return => {
+                if self.sp == 0 {
+                    match self.quirks.stack {
+                        StackPolicy::Wrap => self.sp = self.stack.len() as u16,
+                        _ => panic!("stack underflow: return at {:03X} with no call to return from", self.pc),
                     }
-                },
+                }
 
-                // 0xEXA1 => Skips next instruction if the key stored in VX is NOT pressed
-                0x0001 => {
-                    let key = self.registers[x as usize];
+                self.sp -= 1;
+                self.pc = self.stack[self.sp as usize];
 
-                    if self.keys[key as usize] == 0 {
-                        self.pc += 4;
-                    } else {
-                        self.pc += 2;
-                    }
-                },
+                self.pc += 2;
 
-                _ => println!("NOP"),
+                if self.show_debug {
+                    self.debug_line(&format!("\tReturning from subroutine, setting sp={} pc={}+2", self.sp, self.pc - 2));
+                }
             },
 
-            0xF000 => match self.opcode & 0x000F {
+            // 0x1NNN => jump to address NNN
+            Instruction::Jump(nnn) => {
+                self.pc = nnn;
+
+                if self.show_debug {
+                    self.debug_line(&format!("\tJumping to address {}", nnn));
+                }
+            },
 
-                // 0xFX33 => Take decimal representation of VX and store:
-                //           High Digit at index
-                //           Middle Digit at index+1
-                //           Low Digit at index+2
-                0x0003 => {
-                    let val = self.registers[x as usize];
+            // 0x2NNN => call subroutine at NNN
+            Instruction::Call(nnn) => {
+                if self.sp as usize == self.stack.len() {
+                    match self.quirks.stack {
+                        StackPolicy::Error => panic!("stack overflow: call at {:03X} nested {} deep", self.pc, self.sp + 1),
+                        StackPolicy::Wrap => self.sp = 0,
+                        StackPolicy::Grow => self.stack.push(0),
+                    }
+                }
 
-                    let high: u8 = val / 100;
-                    let mid: u8 = (val / 10) % 10;
-                    let lower: u8 = val % 10;
+                self.stack[self.sp as usize] = self.pc;
 
-                    self.memory[self.index as usize] = high;
-                    self.memory[self.index as usize + 1] = mid;
-                    self.memory[self.index as usize + 2] = lower;
+                self.sp += 1;
 
-                    self.pc += 2;
-                },
+                if self.sp > CLASSIC_STACK_DEPTH {
+                    self.warn(Warning::DeepStack { pc: self.pc, depth: self.sp });
+                }
 
-                0x0005 => match self.opcode & 0x00F0 {
+                self.pc = nnn;
 
-                    // 0xFX15 => Set delay timer to VX
-                    0x0010 => {
-                        let xval = self.registers[x as usize];
-                        self.delay_timer = xval;
+                if self.show_debug {
+                    self.debug_line(&format!("\tCalling subroutine at {}", nnn));
+                }
+            },
 
-                        self.pc += 2;
+            // 0x3XNN => skip next instruction if register VX == NN
+            Instruction::SkipEqByte(x, nn) => {
+                let val = self.read_reg(x);
+                if val == nn {
+                    self.pc += 4;
+                } else {
+                    self.pc += 2;
+                }
 
-                        if self.show_debug {
-                            println!("\tDelay Timer set to {}", xval);
-                        }
-                    },
+                if self.show_debug {
+                    self.debug_line(&format!("\tSkip next if {}({})=={}", x, val, nn));
+                }
+            },
 
-                    // 0xFX55 => Stores V0-VX(inclusive) in memory starting at index
-                    //           Offset increases by 1 for each value stored
-                    //           index remains unchanged
-                    0x0050 => {
-                        self.reg_dump(x);
+            // 0x4XNN => skip next if VX != NN
+            Instruction::SkipNeByte(x, nn) => {
+                let val = self.read_reg(x);
+                if val != nn {
+                    self.pc += 4;
+                } else {
+                    self.pc += 2;
+                }
 
-                        self.pc += 2;
+                if self.show_debug {
+                    self.debug_line(&format!("\tSkip next if {}({})!={}", x, val, nn));
+                }
+            },
 
-                        if self.show_debug {
-                            println!("\tStore V0-V{} in mem starting@<{:#X?}>", x, self.index);
-                        }
-                    },
+            // 0x5XY0 => skip next if VX == VY
+            Instruction::SkipEqReg(x, y) => {
+                let valx = self.read_reg(x);
+                let valy = self.read_reg(y);
 
-                    // 0xFX65 => Moves values from memory into V0-VX(inclusive) starting at index
-                    //           Offset increases by 1 for each value loaded
-                    //           index remains unchanged
-                    //
-                    0x0060 => {
-                        self.reg_load(x);
+                if valx == valy {
+                    self.pc += 4;
+                } else {
+                    self.pc += 2;
+                }
 
-                        self.pc += 2;
+                if self.show_debug {
+                    self.debug_line(&format!("\tSkip next if {}({})=={}({})", x, valx, y, valy));
+                }
+            },
 
-                        if self.show_debug {
-                            println!("\tLoad from mem starting@<{:#X?}> into V0-V{}", self.index, x);
-                        }
-                    },
+            // 0x5XY2 => (XO-CHIP) Store VX-VY(inclusive) in memory starting at index
+            //           index remains unchanged, X > Y stores them in reverse
+            Instruction::SaveRange(x, y) if self.quirks.xo_chip => {
+                self.check_font_write(self.index);
 
-                    _ => println!("NOP"),
-                },
+                for (offset, reg) in register_range(x, y).enumerate() {
+                    let value = self.read_reg(reg);
+                    self.write_byte((self.index as usize + offset) % 4096, value);
+                }
 
-                // 0xFX07 => Set VX to value of delay timer
-                0x0007 => {
-                    self.registers[x as usize] = self.delay_timer;
+                self.pc += 2;
 
-                    self.pc += 2;
+                if self.show_debug {
+                    self.debug_line(&format!("\tStore {}-{} in mem starting@<{:#X?}>", x, y, self.index));
+                }
+            },
 
-                    if self.show_debug {
-                        println!("\tSet V{}={} (delay timer)", x, self.delay_timer);
-                    }
-                },
+            // 0x5XY3 => (XO-CHIP) Load VX-VY(inclusive) from memory starting at index
+            //           index remains unchanged, X > Y loads them in reverse
+            Instruction::LoadRange(x, y) if self.quirks.xo_chip => {
+                for (offset, reg) in register_range(x, y).enumerate() {
+                    let value = self.read_byte((self.index as usize + offset) % 4096);
+                    self.write_reg(reg, value);
+                }
 
-                // 0xFX18 => Set sound timer to VX
-                0x0008 => {
-                    let xval = self.registers[x as usize];
+                self.pc += 2;
 
-                    self.sound_timer = xval;
+                if self.show_debug {
+                    self.debug_line(&format!("\tLoad from mem starting@<{:#X?}> into {}-{}", self.index, x, y));
+                }
+            },
 
-                    self.pc += 2;
+            // 0x6XNN => VX = NN
+            Instruction::LoadByte(x, nn) => {
+                self.write_reg(x, nn);
 
-                    if self.show_debug {
-                        println!("\tSound Timer set to {}", xval);
-                    }
-                },
+                self.pc += 2;
 
-                // 0xFX29 => Sets index to the location of the sprite for the character in VX
-                //           Characters 0-F are represented by a 4x5 font
-                0x0009 => {
-                    self.index = self.registers[x as usize] as u16 * 5;
+                if self.show_debug {
+                    self.debug_line(&format!("\tSet {}={}", x, nn));
+                }
+            },
 
-                    self.pc += 2;
+            // 0x7XNN => VX += NN
+            Instruction::AddByte(x, nn) => {
 
-                    if self.show_debug {
-                        println!("\tSet index to loc of sprite for character in V{} = {}", x, self.index);
-                    }
-                },
+                let prev_val = self.read_reg(x);
+                let val = prev_val.wrapping_add(nn);
 
-                // 0xFX0A => Block execution until a key press, then store value in VX
-                0x000A => {
-                    let mut pressed = false;
+                self.write_reg(x, val);
 
-                    for k in 0..16 {
-                        if self.keys[k as usize] != 0 {
-                            self.registers[x as usize] = k;
-                            pressed = true;
-                        }
-                    }
+                self.pc += 2;
 
-                    // Skip cycle if we didn't get a key press
-                    if !pressed {
-                        return;
-                    }
+                if self.show_debug {
+                    self.debug_line(&format!("\t{}={} wrapadd {} = {}", x, prev_val, nn, val));
+                }
+            },
 
-                    self.pc += 2;
-                },
+            // The 8XYN ops that set a flag write VF after VX, so when X is F the
+            // flag is what's left in VF and the result is lost, like on the VIP.
+            // Reading VF as an operand still sees its value from before the op.
 
-                // 0xFX1E => Adds VX to index
-                0x000E => {
-                    let xval = self.registers[x as usize] as u16;
-                    self.index += xval;
+            // 0x8XY0 => VX = VY
+            Instruction::Move(x, y) => {
+                let val = self.read_reg(y);
 
-                    self.pc += 2;
+                self.write_reg(x, val);
 
-                    if self.show_debug {
-                        println!("\tAdd V{}({}) to index = {}", x, xval, self.index);
-                    }
-                },
+                self.pc += 2;
 
-                _ => println!("NOP"),
+                if self.show_debug {
+                    self.debug_line(&format!("\t{}={} ({:#X?})", x, y, val));
+                }
             },
 
-            _ => println!("NOP"),
-        } // End of Opcode matching
-    }
+            // 0x8XY1 => VX = VX | VY
+            Instruction::Or(x, y) => {
+                let xval = self.read_reg(x);
+                let yval = self.read_reg(y);
 
-    pub fn cycle(&mut self) {
+                let result = xval | yval;
+                self.write_reg(x, result);
+                if self.quirks.vf_reset {
+                    self.set_flag(0);
+                }
 
-        // Decode and perform the current opcode.
-        self.perform_opcode();
+                self.pc += 2;
 
-        // Update timers
-        if self.delay_timer > 0 {
-            self.delay_timer -= 1;
-        }
-        if self.sound_timer > 0 {
-            if self.sound_timer == 1 {
-                // Make a beep
-                println!("BEEP");
-            }
-            self.sound_timer -= 1;
+                if self.show_debug {
+                    self.debug_line(&format!("\t{}={}({:#X?}) | {}({:#X?}) -> {:#X?}", x, x, xval, y, yval, result));
+                }
+            },
+
+            // 0x8XY2 => VX = VX & VY
+            Instruction::And(x, y) => {
+                let xval = self.read_reg(x);
+                let yval = self.read_reg(y);
+
+                let result = xval & yval;
+                self.write_reg(x, result);
+                if self.quirks.vf_reset {
+                    self.set_flag(0);
+                }
+
+                self.pc += 2;
+
+                if self.show_debug {
+                    self.debug_line(&format!("\t{}={}({:#X?}) & {}({:#X?}) -> {:#X?}", x, x, xval, y, yval, result));
+                }
+            },
+
+            // 0x8XY3 => VX = VX ^(bitwise xor) VY
+            Instruction::Xor(x, y) => {
+                let xval = self.read_reg(x);
+                let yval = self.read_reg(y);
+
+                let result = xval ^ yval;
+                self.write_reg(x, result);
+                if self.quirks.vf_reset {
+                    self.set_flag(0);
+                }
+
+                self.pc += 2;
+
+                if self.show_debug {
+                    self.debug_line(&format!("\t{}={}({:#X?}) ^ {}({:#X?}) -> {:#X?}", x, x, xval, y, yval, result));
+                }
+            },
+
+            // 0x8XY4 => VX += VY, set VF to 1 if there is a carry, 0 if not
+            Instruction::AddReg(x, y) => {
+                let xval = self.read_reg(x) as u16;
+                let yval = self.read_reg(y) as u16;
+
+                let result = xval + yval;
+                self.write_reg(x, result as u8);
+
+                // Set carry flag appropriately, overwriting the result if X is F.
+                self.set_flag(if result > 0xFF { 1 } else { 0 });
+
+                self.pc += 2;
+
+                if self.show_debug {
+                    self.debug_line(&format!("\t{}={}({:#X?}) + {}({:#X?}) -> {:#X?}", x, x, xval, y, yval, result));
+                }
+            },
+
+            // 0x8XY5 => VX -= VY, set VF to 0 if there is a borrow, 1 if not
+            Instruction::Sub(x, y) => {
+                let xval = self.read_reg(x);
+                let yval = self.read_reg(y);
+
+                let result = xval.wrapping_sub(yval);
+                self.write_reg(x, result);
+
+                // Set borrow flag appropriately.
+                self.set_flag(if yval > xval { 0 } else { 1 });
+
+                self.pc += 2;
+
+                if self.show_debug {
+                    self.debug_line(&format!("\t{}={}({:#X?}) - {}({:#X?}) -> {:#X?}", x, x, xval, y, yval, result));
+                }
+            },
+
+            // 0x8XY6 => Store least significant bit of VX in VF, then VX >>= 1
+            Instruction::ShiftRight(x, y) => {
+                // The VIP shifted VY into VX, later interpreters shift VX in place.
+                let source = if self.quirks.shift_vy { y } else { x };
+                let xval = self.read_reg(source);
+
+                let least_sig_bit = xval & 0x1;
+                let result = xval >> 1;
+
+                self.write_reg(x, result);
+
+                // Store least sig in VF
+                self.set_flag(least_sig_bit);
+
+                self.pc += 2;
+
+                if self.show_debug {
+                    self.debug_line(&format!("\t{}={}({:#X?}) >> 1) -> {:#X?}", x, source, xval, result));
+                }
+            },
+
+            // 0x8XY7 => VX = VY - VX, set VF to to 0 when borrow, 1 if not
+            Instruction::SubReverse(x, y) => {
+                let xval = self.read_reg(x);
+                let yval = self.read_reg(y);
+
+                let result = yval.wrapping_sub(xval);
+                self.write_reg(x, result);
+
+                // Set borrow flag appropriately.
+                self.set_flag(if xval > yval { 0 } else { 1 });
+
+                self.pc += 2;
+
+                if self.show_debug {
+                    self.debug_line(&format!("\t{}={}({:#X?}) - {}({:#X?}) -> {:#X?}", x, y, yval, x, xval, result));
+                }
+            },
+
+            // 0x8XYE => VX = Store most significant bit of VX in VF, then VX <<= 1
+            Instruction::ShiftLeft(x, y) => {
+                let source = if self.quirks.shift_vy { y } else { x };
+                let xval = self.read_reg(source);
+
+                let most_sig_bit = (xval & 0x80) >> 7;
+                let result = (xval & 0x7F) << 1;
+
+                self.write_reg(x, result);
+
+                // Store most sig in VF
+                self.set_flag(most_sig_bit);
+
+                self.pc += 2;
+
+                if self.show_debug {
+                    self.debug_line(&format!("\t{}={}({:#X?}) << 1) -> {:#X?}", x, source, xval, result));
+                }
+            },
+
+            // 0x9XY0 => skips next instruction if VX != VY
+            Instruction::SkipNeReg(x, y) => {
+                let xval = self.read_reg(x);
+                let yval = self.read_reg(y);
+
+                if xval != yval {
+                    self.pc += 4;
+                } else {
+                    self.pc += 2;
+                }
+
+                if self.show_debug {
+                    self.debug_line(&format!("\tSkip next if {}({})!={}({})", x, xval, y, yval));
+                }
+            },
+
+            // 0xANNN => set index to NNN
+            Instruction::LoadIndex(nnn) => {
+                self.index = nnn;
+
+                self.pc += 2;
+
+                if self.show_debug {
+                    self.debug_line(&format!("\tSetting I(index) to {}.", nnn));
+                }
+            },
+
+            // 0xBNNN => set PC to V0 + NNN, or VX + XNN with the jump quirk
+            Instruction::JumpOffset(nnn) => {
+                let reg = if self.quirks.jump_vx { Opcode(nnn).vx() } else { VReg::V0 };
+                let base = self.read_reg(reg);
+                self.pc = base as u16 + nnn;
+
+                if self.show_debug {
+                    self.debug_line(&format!("\tSetting PC to {} ({:#?}) + {:X?} = ({:#?})", reg, base, nnn, self.pc));
+                }
+            },
+
+            // 0xCXNN => set VX to some random number (0-255), R & NN
+            Instruction::Random(x, nn) => {
+                let r = self.next_random();
+
+                let result = r & nn;
+
+                self.write_reg(x, result);
+
+                self.pc += 2;
+
+                if self.show_debug {
+                    self.debug_line(&format!("\tSet {} to random# {}", x, result));
+                }
+            },
+
+            // 0xDXYN => Draw sprite at (VX, VY) w/ width 8pixels and height N
+            //           With N=0 SCHIP draws a 16x16 sprite, two bytes per row.
+            //           The original interpreter doesn't define DXY0, so it's treated as unknown
+            // See https://en.wikipedia.org/wiki/CHIP-8 for more info.
+            Instruction::Draw(x, y, 0) if !self.quirks.schip => {
+                self.unknown_opcode(0xD000 | (x as u16) << 8 | (y as u16) << 4);
+            },
+
+            // Under display_wait nothing is drawn until the next vblank, the
+            // instruction runs again each cycle until then.
+            Instruction::Draw(..) if self.quirks.display_wait && !self.vblank => {},
+
+            Instruction::Draw(x, y, n) => {
+                self.vblank = false;
+                self.drawn = true;
+
+                let xval = self.read_reg(x);
+                let yval = self.read_reg(y);
+
+                let (width, height) = if n == 0 { (16, 16) } else { (8, n) };
+
+                // The reads wrap around (see `read_byte`), but a ROM doing this is probably broken.
+                if self.index as usize + height as usize * (width / 8) > 4096 {
+                    self.warn(Warning::SpriteWrap { pc: self.pc, index: self.index });
+                }
+
+                // Reset VF
+                self.set_flag(0);
+
+                for dy in 0..height {
+                    let pixel = if width == 16 {
+                        let row = self.index as usize + dy as usize * 2;
+                        (self.read_byte(row) as u16) << 8 | self.read_byte(row + 1) as u16
+                    } else {
+                        (self.read_byte(self.index as usize + dy as usize) as u16) << 8
+                    };
+
+                    for dx in 0..width {
+                        let mask = 0x8000 >> dx;
+
+                        // If pixel bit is set in memory.
+                        if pixel & mask != 0 {
+
+                            let mut locx = xval as u16 + dx as u16;
+                            let mut locy = yval as u16 + dy as u16;
+
+                            // Pixels past an edge reappear on the opposite side when wrapping.
+                            if self.quirks.wrap_x {
+                                locx %= 64;
+                            }
+                            if self.quirks.wrap_y {
+                                locy %= 32;
+                            }
+
+                            if locx >= 64 || locy >= 32 {
+                                if self.show_debug {
+                                    self.debug_line(&format!("\t\tWhile drawing sprite went out of bounds at ({}.{})", locx, locy));
+                                }
+                                continue;
+                            }
+
+                            // Check if pixel is set on screen.
+                            if self.gfx[locy as usize][locx as usize] == 1 {
+                                self.set_flag(1);
+                            }
+
+                            let data = &mut (self.gfx[locy as usize][locx as usize]);
+                            *data ^= 1;
+
+                            let on = *data == 1;
+                            self.emit(Chip8Event::Draw { x: locx, y: locy, on });
+                        }
+                    }
+                }
+
+                self.pc += 2;
+
+                if self.show_debug {
+                    self.debug_line(&format!("\tDraw {}x{} sprite at ({}({}),{}({}))", width, height, x, xval, y, yval));
+                }
+            },
+
+            // 0xEX9E => Skips next instruction if the key stored in VX is pressed
+            Instruction::SkipKey(x) => {
+                let key = self.read_reg(x);
+
+                if self.read_key(key) {
+                    self.pc += 4;
+                } else {
+                    self.pc += 2;
+                }
+            },
+
+            // 0xEXA1 => Skips next instruction if the key stored in VX is NOT pressed
+            Instruction::SkipNotKey(x) => {
+                let key = self.read_reg(x);
+
+                if !self.read_key(key) {
+                    self.pc += 4;
+                } else {
+                    self.pc += 2;
+                }
+            },
+
+            // 0xF002 => (XO-CHIP) Load the 16 byte audio pattern buffer from memory at index
+            Instruction::LoadAudio if self.quirks.xo_chip => {
+                for i in 0..16 {
+                    self.audio_pattern[i] = self.read_byte((self.index as usize + i) % 4096);
+                }
+
+                self.pc += 2;
+
+                if self.show_debug {
+                    self.debug_line(&format!("\tLoad audio pattern from mem starting@<{:#X?}>", self.index));
+                }
+            },
+
+            // 0xFX3A => (XO-CHIP) Set the audio pitch to VX, see `audio::playback_rate`
+            Instruction::SetPitch(x) if self.quirks.xo_chip => {
+                self.pitch = self.read_reg(x);
+
+                self.pc += 2;
+
+                if self.show_debug {
+                    self.debug_line(&format!("\tSet pitch to {} ({:.0} bits/s)", self.pitch, audio::playback_rate(self.pitch)));
+                }
+            },
+
+            // 0xFX33 => Take decimal representation of VX and store:
+            //           High Digit at index
+            //           Middle Digit at index+1
+            //           Low Digit at index+2
+            Instruction::StoreBcd(x) => {
+                self.check_font_write(self.index);
+
+                let val = self.read_reg(x);
+
+                let high: u8 = val / 100;
+                let mid: u8 = (val / 10) % 10;
+                let lower: u8 = val % 10;
+
+                self.write_byte(self.index as usize, high);
+                self.write_byte(self.index as usize + 1, mid);
+                self.write_byte(self.index as usize + 2, lower);
+
+                self.pc += 2;
+            },
+
+            // 0xFX15 => Set delay timer to VX
+            Instruction::SetDelay(x) => {
+                let xval = self.read_reg(x);
+                self.delay_timer = xval;
+
+                self.pc += 2;
+
+                if self.show_debug {
+                    self.debug_line(&format!("\tDelay Timer set to {}", xval));
+                }
+            },
+
+            // 0xFX55 => Stores V0-VX(inclusive) in memory starting at index
+            //           Offset increases by 1 for each value stored
+            //           index remains unchanged unless load_store_increment is set
+            Instruction::StoreRegs(x) => {
+                self.reg_dump(x);
+
+                self.pc += 2;
+
+                if self.show_debug {
+                    self.debug_line(&format!("\tStore V0-{} in mem starting@<{:#X?}>", x, self.index));
+                }
+            },
+
+            // 0xFX65 => Moves values from memory into V0-VX(inclusive) starting at index
+            //           Offset increases by 1 for each value loaded
+            //           index remains unchanged unless load_store_increment is set
+            //
+            Instruction::LoadRegs(x) => {
+                self.reg_load(x);
+
+                self.pc += 2;
+
+                if self.show_debug {
+                    self.debug_line(&format!("\tLoad from mem starting@<{:#X?}> into V0-{}", self.index, x));
+                }
+            },
+
+            // 0xFX07 => Set VX to value of delay timer
+            Instruction::GetDelay(x) => {
+                self.write_reg(x, self.delay_timer);
+
+                self.pc += 2;
+
+                if self.show_debug {
+                    self.debug_line(&format!("\tSet {}={} (delay timer)", x, self.delay_timer));
+                }
+            },
+
+            // 0xFX18 => Set sound timer to VX
+            Instruction::SetSound(x) => {
+                let xval = self.read_reg(x);
+
+                self.set_sound_timer(xval);
+
+                self.pc += 2;
+
+                if self.show_debug {
+                    self.debug_line(&format!("\tSound Timer set to {}", xval));
+                }
+            },
+
+            // 0xFX29 => Sets index to the location of the sprite for the character in VX
+            //           Characters 0-F are represented by a 4x5 font
+            Instruction::LoadFont(x) => {
+                self.index = self.read_reg(x) as u16 * 5;
+
+                self.pc += 2;
+
+                if self.show_debug {
+                    self.debug_line(&format!("\tSet index to loc of sprite for character in {} = {}", x, self.index));
+                }
+            },
+
+            // 0xFX0A => Block execution until a key press, then store value in VX
+            //           PC stays put while no key is down, so every cycle retries it.
+            //           Only the key stored is used up in pulse mode, others stay pressed.
+            //           With several keys down the one with the lowest number in
+            //           `key_order` is taken, so replays and tests don't depend on
+            //           the order keys went down.
+            Instruction::WaitKey(x) => {
+                // Skip cycle if we didn't get a key press
+                let pressed = self.keys.iter().zip(self.key_order.iter())
+                    .filter(|&(&state, _)| state != 0)
+                    .map(|(_, &number)| number & 0xF)
+                    .min();
+                let key = match pressed {
+                    Some(key) => key,
+                    None => return,
+                };
+
+                self.write_reg(x, key);
+                self.read_key(key);
+
+                self.pc += 2;
+            },
+
+            // 0xFX1E => Adds VX to index
+            Instruction::AddIndex(x) => {
+                let xval = self.read_reg(x) as u16;
+                self.index = self.index.wrapping_add(xval);
+
+                if self.index > 0xFFF {
+                    self.warn(Warning::IndexOverflow { pc: self.pc, index: self.index });
+
+                    if self.quirks.clamp_index {
+                        self.index = 0xFFF;
+                    }
+                }
+
+                self.pc += 2;
+
+                if self.show_debug {
+                    self.debug_line(&format!("\tAdd {}({}) to index = {}", x, xval, self.index));
+                }
+            },
+
+            Instruction::Unknown(opcode) => self.unknown_opcode(opcode),
+
+            // XO-CHIP instructions while XO-CHIP is off.
+            _ => self.unknown_opcode(self.opcode),
+        } // End of Opcode matching
+    }
+
+    // Execute a single instruction. Timers are left alone, see `tick_timers`.
+    pub fn cycle(&mut self) {
+
+        // Nothing runs until the developer has looked at the unknown opcode.
+        if self.paused || self.stopped() {
+            return;
+        }
+
+        if self.pc as usize + 1 >= self.memory.len() {
+            self.pc_past_end();
+            if self.stopped() {
+                return;
+            }
+        }
+
+        // Get next opcode.
+        self.opcode = self.fetch();
+
+        if let Some(counts) = self.coverage.as_mut() {
+            counts[self.pc as usize] += 1;
+        }
+
+        self.push_trace();
+
+        if self.show_debug {
+            self.debug_line(&format!("PC: {}, opcode: <{:#X?}>", self.pc, self.opcode));
+        }
+
+        // What the instruction started from, for the JSON trace to compare against.
+        let before = if self.json_trace.is_some() { Some((self.pc, self.registers, self.index)) } else { None };
+
+        // Decode and perform the current opcode.
+        let instruction = instruction::decode(self.opcode);
+        if self.profile.is_some() {
+            let start = Instant::now();
+            self.execute(instruction);
+            let elapsed = start.elapsed();
+            if let Some(profile) = self.profile.as_mut() {
+                profile.record(self.opcode, elapsed);
+            }
+        } else {
+            self.execute(instruction);
+        }
+        if self.paused_on_unknown.is_some() {
+            return;
+        }
+        if let Some((pc, registers, index)) = before {
+            self.write_trace_record(pc, registers, index);
+        }
+        self.cycles += 1;
+
+    } // End of fn cycle()
+
+    // Count the timers down once, call this `timer_hz` times a second.
+    pub fn tick_timers(&mut self) {
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
+        }
+        if self.sound_timer > 0 {
+            self.set_sound_timer(self.sound_timer - 1);
+        }
+        self.sounding = self.sound_ran;
+        self.sound_ran = self.sound_timer > 0;
+
+        if let Some(frames) = self.captured_frames.as_mut() {
+            frames.push(self.gfx);
+        }
+    }
+
+    // The display finished a frame, which lets a DXYN held by the display_wait
+    // quirk go ahead. `advance_frame` and `cycle_in_frame` call this at the end
+    // of every frame, frontends calling `cycle` themselves should too.
+    pub fn notify_vblank(&mut self) {
+        self.vblank = true;
+    }
+
+    // The 60Hz display interrupt on its own, without a timer tick: vblank and,
+    // in PerFrame sampling, the keys. Frames end with this after `tick_timers`,
+    // calling it directly lets a test say exactly when a held DXYN may draw.
+    pub fn tick_display(&mut self) {
+        self.notify_vblank();
+        self.sample_keys();
+    }
+
+    // How many `cycle` calls belong between two `tick_timers` calls at the
+    // configured speed, this is the `ipf` to hand to `advance_frame`.
+    pub fn cycles_per_timer_tick(&self) -> u32 {
+        self.cycles_per_tick_at(self.ips)
+    }
+
+    fn cycles_per_tick_at(&self, ips: u32) -> u32 {
+        let hz = self.timer_hz.max(1);
+        ((ips + hz / 2) / hz).max(1)
+    }
+
+    // Wall clock time between two `tick_timers` calls.
+    pub fn timer_interval(&self) -> Duration {
+        Duration::from_nanos(1_000_000_000 / self.timer_hz.max(1) as u64)
+    }
+
+    // Run one timer frame: `ipf` instructions first, then a single timer tick.
+    // Timers therefore change only between frames, never in the middle of one,
+    // no matter how many instructions a frame holds.
+    pub fn advance_frame(&mut self, ipf: u32) {
+        for _ in 0..ipf {
+            self.cycle();
+        }
+
+        // Time stands still while paused.
+        if !self.paused && !self.stopped() {
+            self.tick_timers();
+            self.tick_display();
+        }
+    }
+
+    // Exactly `frames` timer frames at `ips` instructions per second, each one
+    // a frame's worth of instructions and then a single timer tick, see
+    // `advance_frame`. Wall clock time plays no part, so headless runs and
+    // tests of anything timer driven come out the same every time.
+    pub fn run_frames(&mut self, frames: usize, ips: u32) {
+        let ipf = self.cycles_per_tick_at(ips);
+
+        for _ in 0..frames {
+            self.advance_frame(ipf);
+        }
+    }
+
+    // One instruction of a frame made of `ipf` instructions, ticking the timers
+    // after every `ipf`th. Stepping through a run from the start like this ends
+    // up in exactly the same state as calling `advance_frame` repeatedly.
+    pub fn cycle_in_frame(&mut self, ipf: u32) {
+        let before = self.cycles;
+        self.cycle();
+
+        if self.cycles != before && self.cycles.is_multiple_of(ipf.max(1) as u64) {
+            self.tick_timers();
+            self.tick_display();
+        }
+    }
+
+    // Execute until the screen is drawn to or cleared, for stepping through a
+    // ROM one picture at a time. Stops right after the drawing instruction.
+    pub fn run_to_next_draw(&mut self, max_cycles: usize) -> RunResult {
+        let ipf = self.cycles_per_timer_tick();
+
+        for _ in 0..max_cycles {
+            // Only what this instruction did counts, not a DXYN waiting for
+            // vblank or one skipped as unknown.
+            let drawn_before = std::mem::replace(&mut self.drawn, false);
+            self.cycle_in_frame(ipf);
+            let drew = self.drawn;
+            self.drawn |= drawn_before;
+
+            if let Some(result) = self.stop_result() {
+                return result;
+            }
+            if drew {
+                return RunResult::Drew;
+            }
+        }
+
+        RunResult::CycleLimit
+    }
+
+    // Run until a beep starts, that is the sound timer going from zero to
+    // something else. A beep that was already going doesn't count.
+    pub fn run_until_beep(&mut self, max_cycles: usize) -> RunResult {
+        let ipf = self.cycles_per_timer_tick();
+
+        for _ in 0..max_cycles {
+            let silent = self.sound_timer == 0;
+            self.cycle_in_frame(ipf);
+
+            if let Some(result) = self.stop_result() {
+                return result;
+            }
+            if silent && self.sound_timer > 0 {
+                return RunResult::Beeped;
+            }
+        }
+
+        RunResult::CycleLimit
+    }
+
+    // Run one instruction, or for a 2NNN the whole subroutine until it returns.
+    pub fn step_over(&mut self, max_cycles: usize) -> RunResult {
+        let ipf = self.cycles_per_timer_tick();
+        let next = Opcode(self.fetch());
+        let sp = self.sp;
+
+        for _ in 0..max_cycles {
+            self.cycle_in_frame(ipf);
+
+            if let Some(result) = self.stop_result() {
+                return result;
+            }
+            if next.family() != 0x2 || self.sp <= sp {
+                return RunResult::Stepped;
+            }
+        }
+
+        RunResult::CycleLimit
+    }
+}
+
+impl Default for Chip8 {
+    fn default() -> Chip8 {
+        Chip8::new()
+    }
+}
+
+impl fmt::Display for Chip8 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Chip8 {{opcode: {}, index: {}, pc: {}, sp: {}}}",
+            self.opcode,
+            self.index,
+            self.pc,
+            self.sp
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emu::testing;
+    use std::convert::TryFrom;
+
+    fn load(program: &[u8]) -> Chip8 {
+        let mut c8 = Chip8::with_seed(0);
+        c8.load_rom(&Rom::try_from(program).unwrap());
+        c8
+    }
+
+    fn run(c8: &mut Chip8, cycles: usize) {
+        for _ in 0..cycles {
+            c8.cycle();
+        }
+    }
+
+    #[test]
+    fn odd_length_rom() {
+        let mut c8 = Chip8::with_seed(0);
+        c8.load_rom(&Rom::try_from(&[0x60, 0x0A, 0x61][..]).unwrap());
+
+        assert_eq!(c8.take_warnings(), vec![Warning::OddRomLength(3)]);
+        assert_eq!(&c8.memory()[0x200..0x204], &[0x60, 0x0A, 0x61, 0x00]);
+
+        // The last byte runs as 6100.
+        c8.set_reg(VReg::V1, 7);
+        run(&mut c8, 2);
+        assert_eq!(c8.reg(VReg::V1), 0);
+    }
+
+    #[test]
+    fn key_callback_reports_changes() {
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut c8 = Chip8::with_seed(0);
+        let log = seen.clone();
+        c8.set_key_callback(move |key_index, pressed| log.lock().unwrap().push((key_index, pressed)));
+
+        c8.key_pressed(0xA);
+        c8.key_pressed(0xA); // Host key repeat, not a change.
+        c8.key_released(0xA);
+        c8.clear_key_callback();
+        c8.key_pressed(0x3);
+
+        assert_eq!(*seen.lock().unwrap(), vec![(0xA, true), (0xA, false)]);
+    }
+
+    #[test]
+    fn draw_events_consumed_each_frame() {
+        // Draw the 0 glyph again every frame, for three frames.
+        let mut c8 = load(&crate::chip8_program![0xD015, 0x1200]);
+        for _ in 0..3 {
+            c8.advance_frame(2);
+            let drawn = c8.drain_events().filter(|event| matches!(event, Chip8Event::Draw { .. })).count();
+            assert_eq!(drawn, 14);
+            assert_eq!(c8.drain_events().count(), 0);
+        }
+    }
+
+    #[test]
+    fn pause_on_unknown_opcode() {
+        let mut c8 = load(&crate::chip8_program![0x6001, 0x0123, 0x6002]);
+        c8.pause_on_unknown = true;
+        run(&mut c8, 3);
+
+        assert_eq!(c8.paused_on_unknown(), Some((0x202, 0x0123)));
+        assert!(c8.stopped());
+        assert_eq!(c8.pc(), 0x202);
+        assert_eq!(c8.reg(VReg::V0), 1);
+
+        c8.resume_from_unknown();
+        c8.cycle();
+        assert_eq!(c8.paused_on_unknown(), None);
+        assert_eq!(c8.reg(VReg::V0), 2);
+    }
+
+    #[test]
+    fn find_bytes_in_memory() {
+        let c8 = load(&[0x12, 0x00, 0xDE, 0xAD, 0xBE, 0xEF, 0xDE, 0xAD]);
+
+        assert_eq!(c8.find_bytes(&[0xDE, 0xAD, 0xBE, 0xEF]), vec![0x202]);
+        assert_eq!(c8.find_bytes(&[0xDE, 0xAD]), vec![0x202, 0x206]);
+        assert!(c8.find_bytes(&[0xCA, 0xFE, 0xBA, 0xBE]).is_empty());
+    }
+
+    #[test]
+    fn recent_trace_after_crash() {
+        let mut c8 = load(&crate::chip8_program![0x6001, 0x7002, 0xA300, 0x00EE]);
+        let crashed = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| run(&mut c8, 4)));
+        assert!(crashed.is_err());
+
+        assert_eq!(c8.recent_trace(), &[(0x200, 0x6001), (0x202, 0x7002), (0x204, 0xA300), (0x206, 0x00EE)]);
+    }
+
+    #[test]
+    fn recent_trace_keeps_the_last_few() {
+        let mut c8 = load(&crate::chip8_program![0x7001, 0x1200]);
+        run(&mut c8, TRACE_LEN * 2 + 1);
+
+        let trace = c8.recent_trace();
+        assert_eq!(trace.len(), TRACE_LEN);
+        assert_eq!(trace[TRACE_LEN - 1], (0x200, 0x7001));
+        assert_eq!(trace[TRACE_LEN - 2], (0x202, 0x1200));
+    }
+
+    // Counts in V1 how many times EX9E sees key 0 down.
+    fn count_key_presses(repeat: KeyRepeat) -> Chip8 {
+        let mut c8 = load(&crate::chip8_program![0xE09E, 0x1200, 0x7101, 0x1200]);
+        c8.key_repeat = repeat;
+        c8.key_pressed(0x0);
+        run(&mut c8, 30);
+        c8
+    }
+
+    #[test]
+    fn pulse_key_seen_once_per_press() {
+        let mut c8 = count_key_presses(KeyRepeat::Pulse);
+        assert_eq!(c8.reg(VReg::V1), 1);
+
+        c8.key_released(0x0);
+        c8.key_pressed(0x0);
+        run(&mut c8, 30);
+        assert_eq!(c8.reg(VReg::V1), 2);
+    }
+
+    #[test]
+    fn held_key_seen_every_time() {
+        assert_eq!(count_key_presses(KeyRepeat::Hold).reg(VReg::V1), 10);
+    }
+
+    #[test]
+    fn sound_timer_beeps() {
+        let mut c8 = load(&crate::chip8_program![0x6002, 0xF018, 0x1204]);
+        for _ in 0..4 {
+            c8.advance_frame(10);
+        }
+
+        assert_eq!(c8.take_events(), vec![Chip8Event::BeepStart, Chip8Event::BeepStop]);
+    }
+
+    #[test]
+    fn sound_timer_of_1_beeps_one_tick() {
+        let mut c8 = load(&crate::chip8_program![0x6001, 0xF018, 0x1204]);
+
+        // Set and run out again within the first frame, the events follow the timer.
+        c8.advance_frame(10);
+        assert_eq!(c8.sound_timer(), 0);
+        assert_eq!(c8.take_events(), vec![Chip8Event::BeepStart, Chip8Event::BeepStop]);
+
+        // But the buzzer stays on until the next tick.
+        assert!(c8.sound_active());
+        c8.advance_frame(10);
+        assert!(!c8.sound_active());
+        c8.advance_frame(10);
+        assert!(!c8.sound_active());
+    }
+
+    #[test]
+    fn timers_tick_once_per_frame() {
+        for &ipf in [1, 10, 100].iter() {
+            let mut c8 = load(&crate::chip8_program![0x600A, 0xF015, 0x1204]);
+            c8.advance_frame(2);
+            assert_eq!(c8.delay_timer(), 9);
+
+            for _ in 0..3 {
+                c8.advance_frame(ipf);
+            }
+            assert_eq!(c8.delay_timer(), 6, "ipf {}", ipf);
+        }
+    }
+
+    #[test]
+    fn cycles_per_timer_tick_from_ips() {
+        let mut c8 = Chip8::with_seed(0);
+        assert_eq!(c8.ips, 600);
+        assert_eq!(c8.cycles_per_timer_tick(), 10);
+
+        c8.ips = 1000;
+        assert_eq!(c8.cycles_per_timer_tick(), 17);
+        c8.ips = 1;
+        assert_eq!(c8.cycles_per_timer_tick(), 1);
+    }
+
+    #[test]
+    fn svg_of_single_pixel() {
+        let mut c8 = Chip8::with_seed(0);
+        c8.gfx[7][12] = 1;
+        let svg = c8.to_svg("#fff", "#000");
+
+        let foreground: Vec<&str> = svg.lines().filter(|line| line.contains("fill=\"#fff\"")).collect();
+        assert_eq!(foreground, vec!["<rect x=\"12\" y=\"7\" width=\"1\" height=\"1\" fill=\"#fff\"/>"]);
+        assert!(svg.contains("viewBox=\"0 0 64 32\""));
+        assert!(svg.contains("<rect width=\"64\" height=\"32\" fill=\"#000\"/>"));
+    }
+
+    #[test]
+    fn skip_if_registers_equal() {
+        let mut c8 = load(&crate::chip8_program![0x6005, 0x6105, 0x5010, 0x6201, 0x5020]);
+        run(&mut c8, 3);
+        assert_eq!(c8.pc(), 0x208);
+
+        run(&mut c8, 1);
+        assert_eq!(c8.pc(), 0x20A);
+        // 5XY1 isn't an instruction.
+        assert_eq!(instruction::decode(0x5011), Instruction::Unknown(0x5011));
+    }
+
+    #[test]
+    fn save_and_load_register_range() {
+        let mut c8 = Chip8::with_seed(0);
+        c8.quirks.xo_chip = true;
+        c8.set_registers([0, 0, 0xA, 0xB, 0xC, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        c8.set_index(0x300);
+
+        c8.execute(Instruction::SaveRange(VReg::V2, VReg::V4));
+        assert_eq!(&c8.memory()[0x300..0x304], &[0xA, 0xB, 0xC, 0]);
+        assert_eq!(c8.index(), 0x300);
+
+        // Backwards loads them in reverse.
+        c8.execute(Instruction::LoadRange(VReg::V7, VReg::V5));
+        assert_eq!(&c8.registers()[5..8], &[0xC, 0xB, 0xA]);
+        assert_eq!(c8.index(), 0x300);
+    }
+
+    #[test]
+    fn register_range_needs_xo_chip() {
+        let mut c8 = load(&crate::chip8_program![0x5242]);
+        c8.set_reg(VReg::V2, 0xA);
+        c8.set_index(0x300);
+        c8.cycle();
+
+        assert_eq!(c8.memory()[0x300], 0);
+        assert_eq!(c8.take_warnings(), vec![Warning::UnknownOpcode { pc: 0x200, opcode: 0x5242 }]);
+    }
+
+    #[test]
+    fn disabled_audio_still_counts_down() {
+        let is_beep = |event: &Chip8Event| matches!(event, Chip8Event::BeepStart | Chip8Event::BeepStop);
+
+        let mut c8 = load(&crate::chip8_program![0x6003, 0xF018, 0x1204]);
+        c8.set_audio_enabled(false);
+        run(&mut c8, 2);
+        assert_eq!(c8.sound_timer(), 3);
+
+        for left in (0..3).rev() {
+            c8.tick_timers();
+            assert_eq!(c8.sound_timer(), left);
+            assert!(!c8.sound_active());
         }
+        assert!(!c8.take_events().iter().any(is_beep));
+
+        // Turning it back on mid-beep starts the beep where it stands.
+        let mut c8 = load(&crate::chip8_program![0x6003, 0xF018, 0x1204]);
+        c8.set_audio_enabled(false);
+        run(&mut c8, 2);
+        c8.tick_timers();
+        c8.set_audio_enabled(true);
+        assert_eq!(c8.take_events().into_iter().filter(is_beep).collect::<Vec<_>>(), vec![Chip8Event::BeepStart]);
+        assert_eq!(c8.sound_timer(), 2);
+    }
 
-    } // End of fn cycle()
-}
+    #[test]
+    fn sixty_frames_drain_a_timer_of_sixty() {
+        let mut c8 = load(&crate::chip8_program![0x603C, 0xF015, 0x1204]);
+        c8.run_frames(1, 600);
+        assert_eq!(c8.delay_timer(), 59);
+        assert_eq!(c8.cycles(), 10);
+
+        c8.run_frames(58, 600);
+        assert_eq!(c8.delay_timer(), 1);
+        c8.run_frames(1, 600);
+        assert_eq!(c8.delay_timer(), 0);
+        assert_eq!(c8.cycles(), 600);
+    }
 
-impl fmt::Display for Chip8 {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Chip8 {{opcode: {}, index: {}, pc: {}, sp: {}}}",
-            self.opcode,
-            self.index,
-            self.pc,
-            self.sp
-        )
+    #[test]
+    fn frame_capture_per_timer_tick() {
+        let mut c8 = load(&crate::chip8_program![0x7001, 0xD015, 0x1200]);
+        c8.enable_frame_capture();
+        c8.run_frames(25, 600);
+        assert_eq!(c8.captured_frames().len(), 25);
+
+        c8.tick_timers();
+        assert_eq!(c8.take_captured_frames().len(), 26);
+        assert!(c8.captured_frames().is_empty());
+    }
+
+    #[test]
+    fn drain_events_yields_queued_events() {
+        // Two pixels at (3, 1) and (4, 1), then a clear.
+        let mut c8 = load(&crate::chip8_program![0x6003, 0x6101, 0xA208, 0xD011, 0xC000]);
+        run(&mut c8, 4);
+        assert_eq!(c8.drain_events().collect::<Vec<_>>(), vec![
+            Chip8Event::Draw { x: 3, y: 1, on: true },
+            Chip8Event::Draw { x: 4, y: 1, on: true },
+        ]);
+        assert_eq!(c8.drain_events().count(), 0);
+
+        // Dropping the iterator early still empties the queue.
+        c8.execute(Instruction::ClearScreen);
+        c8.drain_events().next();
+        assert!(c8.take_events().is_empty());
+    }
+
+    #[test]
+    fn fetch_leaves_pc_alone() {
+        let c8 = load(&crate::chip8_program![0x6A42, 0x1234]);
+
+        assert_eq!(c8.fetch(), 0x6A42);
+        assert_eq!(c8.fetch(), 0x6A42);
+        assert_eq!(c8.pc(), 0x200);
+        assert_eq!(c8.cycles(), 0);
+    }
+
+    #[test]
+    fn execute_decoded_instruction() {
+        let mut c8 = load(&crate::chip8_program![0x6A42]);
+        let instruction = instruction::decode(c8.fetch());
+        assert_eq!(instruction, Instruction::LoadByte(VReg::VA, 0x42));
+
+        c8.execute(instruction);
+        assert_eq!(c8.reg(VReg::VA), 0x42);
+        assert_eq!(c8.pc(), 0x202);
+    }
+
+    #[test]
+    fn wait_key_blocks_until_pressed() {
+        let mut c8 = load(&crate::chip8_program![0xF30A]);
+        run(&mut c8, 5);
+        assert_eq!(c8.pc(), 0x200);
+
+        c8.key_pressed(0x7);
+        c8.cycle();
+        assert_eq!(c8.reg(VReg::V3), 0x7);
+        assert_eq!(c8.pc(), 0x202);
+    }
+
+    #[test]
+    fn draw_height_zero_is_unknown_in_classic_mode() {
+        let mut c8 = load(&crate::chip8_program![0xA300, 0xD010]);
+        c8.memory[0x300..0x320].iter_mut().for_each(|byte| *byte = 0xFF);
+
+        run(&mut c8, 2);
+        assert_eq!(c8.take_warnings(), vec![Warning::UnknownOpcode { pc: 0x202, opcode: 0xD010 }]);
+        testing::assert_framebuffer(&c8, "");
+    }
+
+    #[test]
+    fn draw_height_zero_is_16x16_in_schip_mode() {
+        let mut c8 = load(&crate::chip8_program![0xA300, 0xD010]);
+        c8.quirks.schip = true;
+        c8.memory[0x300..0x320].iter_mut().for_each(|byte| *byte = 0xFF);
+
+        run(&mut c8, 2);
+        assert!(c8.take_warnings().is_empty());
+        testing::assert_framebuffer(&c8, &"################\n".repeat(16));
+    }
+
+    #[test]
+    fn index_overflow_warns() {
+        let mut c8 = load(&crate::chip8_program![0xAFFF, 0x6001, 0xF01E]);
+        run(&mut c8, 3);
+        assert_eq!(c8.take_warnings(), vec![Warning::IndexOverflow { pc: 0x204, index: 0x1000 }]);
+    }
+
+    #[test]
+    fn font_write_warns() {
+        let mut c8 = load(&crate::chip8_program![0xA010, 0xF055]);
+        run(&mut c8, 2);
+        assert_eq!(c8.take_warnings(), vec![Warning::FontWrite { pc: 0x202, addr: 0x010 }]);
+    }
+
+    #[test]
+    fn deep_stack_warns() {
+        // Each instruction calls the next one.
+        let program: Vec<u8> = (0..13).flat_map(|i| (0x2202u16 + i * 2).to_be_bytes().to_vec()).collect();
+        let mut c8 = load(&program);
+
+        run(&mut c8, 12);
+        assert!(c8.take_warnings().is_empty());
+        run(&mut c8, 1);
+        assert!(matches!(c8.take_warnings()[..], [Warning::DeepStack { depth: 13, .. }]));
+    }
+
+    #[test]
+    fn reserved_target_warns() {
+        let mut c8 = Chip8::with_seed(0);
+        c8.check_targets = true;
+        c8.load_rom(&Rom::try_from(&crate::chip8_program![0x6001, 0x1100][..]).unwrap());
+        assert_eq!(c8.take_warnings(), vec![Warning::ReservedTarget { pc: 0x202, target: 0x100 }]);
+    }
+
+    #[test]
+    fn sprite_wrap_warns() {
+        let mut c8 = load(&crate::chip8_program![0xAFFE, 0xD005]);
+        run(&mut c8, 2);
+        assert_eq!(c8.take_warnings(), vec![Warning::SpriteWrap { pc: 0x202, index: 0xFFE }]);
+    }
+
+    #[test]
+    fn replayed_rng_gives_same_results() {
+        let program = crate::chip8_program![0xC0FF, 0xC10F, 0xC2F0, 0xC3FF];
+        let mut original = load(&program);
+        original.enable_rng_log();
+        run(&mut original, 4);
+        assert_eq!(original.rng_log().len(), 4);
+
+        // A different seed would give different numbers, the replay decides.
+        let mut replayed = Chip8::with_seed(99);
+        replayed.load_rom(&Rom::try_from(&program[..]).unwrap());
+        replayed.replay_rng(original.rng_log().to_vec());
+        run(&mut replayed, 4);
+        assert_eq!(replayed.registers[..4], original.registers[..4]);
+        assert!(replayed.take_warnings().is_empty());
+    }
+
+    #[test]
+    fn rng_exhausted_warns() {
+        let mut c8 = load(&crate::chip8_program![0xC0FF, 0xC1FF]);
+        c8.replay_rng(vec![7]);
+
+        run(&mut c8, 2);
+        assert_eq!(c8.registers[0], 7);
+        assert_eq!(c8.take_warnings(), vec![Warning::RngExhausted { pc: 0x202 }]);
+    }
+
+    #[test]
+    fn low_res_scroll_warns_once() {
+        let mut c8 = load(&crate::chip8_program![0x00D1, 0x00D1]);
+        c8.quirks.xo_chip = true;
+
+        run(&mut c8, 2);
+        assert_eq!(c8.take_warnings(), vec![Warning::LowResScroll { pc: 0x200 }]);
+    }
+
+    #[test]
+    fn profile_times_executed_families() {
+        let mut c8 = load(&crate::chip8_program![0x6000, 0xD005, 0x1202]);
+        assert!(c8.profile().is_none());
+        c8.enable_profiling();
+
+        run(&mut c8, 1001);
+        let profile = c8.profile().unwrap();
+        for (family, counts) in [(0x1, 500), (0x6, 1), (0xD, 500)].iter() {
+            assert_eq!(profile.counts[*family], *counts);
+            assert!(profile.time[*family] > Duration::from_nanos(0));
+        }
+        for family in [0x0, 0x2, 0x7, 0x8, 0xA, 0xF].iter() {
+            assert_eq!(profile.counts[*family], 0);
+            assert_eq!(profile.time[*family], Duration::from_nanos(0));
+        }
+    }
+
+    // A `Write` the test can still read after handing it to the machine.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    #[test]
+    fn json_trace_lines() {
+        let mut c8 = load(&crate::chip8_program![0x600A, 0xA2F0, 0x610F, 0x8014]);
+        let buffer = SharedBuffer::default();
+        c8.set_json_trace(Box::new(buffer.clone()));
+
+        run(&mut c8, 4);
+        let text = buffer.text();
+        assert_eq!(text.lines().collect::<Vec<_>>(), vec![
+            r#"{"cycle":0,"pc":512,"opcode":24586,"mnemonic":"LD V0, #0A","changes":{"V0":10}}"#,
+            r#"{"cycle":1,"pc":514,"opcode":41712,"mnemonic":"LD I, #2F0","changes":{"I":752}}"#,
+            r#"{"cycle":2,"pc":516,"opcode":24847,"mnemonic":"LD V1, #0F","changes":{"V1":15}}"#,
+            r#"{"cycle":3,"pc":518,"opcode":32788,"mnemonic":"ADD V0, V1","changes":{"V0":25}}"#,
+        ]);
+        assert!(text.ends_with('\n'));
+    }
+
+    #[test]
+    fn debug_output_into_buffer() {
+        let mut c8 = load(&crate::chip8_program![0x6005, 0x7003]);
+        let buffer = SharedBuffer::default();
+        c8.show_debug = true;
+        c8.set_debug_output(Box::new(buffer.clone()));
+
+        run(&mut c8, 2);
+        assert_eq!(buffer.text(), "PC: 512, opcode: <0x6005>\n\tSet V0=5\nPC: 514, opcode: <0x7003>\n\tV0=5 wrapadd 3 = 8\n");
+    }
+
+    #[test]
+    fn set_register_then_skip() {
+        let mut c8 = load(&crate::chip8_program![0x3510, 0x3510]);
+        c8.set_register(5, 0x10);
+        c8.set_register(16, 0xFF);
+
+        run(&mut c8, 1);
+        assert_eq!(c8.pc(), 0x204);
+
+        let mut registers = [0; 16];
+        registers[5] = 0x11;
+        c8.set_registers(registers);
+        c8.execute(Instruction::Jump(0x202));
+        run(&mut c8, 1);
+        assert_eq!(c8.pc(), 0x204);
+    }
+
+    #[test]
+    fn focus_lost_releases_held_keys() {
+        let mut c8 = load(&crate::chip8_program![0x1200]);
+        c8.key_pressed(0x3);
+        c8.key_pressed(0xC);
+
+        assert_eq!(c8.on_focus_lost(), vec![0x3, 0xC]);
+        assert_eq!(c8.keys_snapshot(), [0; 16]);
+        assert!(!c8.paused);
+
+        c8.pause_on_focus_lost = true;
+        c8.key_pressed(0x7);
+        assert_eq!(c8.on_focus_lost(), vec![0x7]);
+        assert_eq!(c8.keys_snapshot(), [0; 16]);
+        assert!(c8.paused);
+    }
+
+    #[test]
+    fn display_wait_draws_after_vblank() {
+        let mut c8 = load(&crate::chip8_program![0xD005, 0x1202]);
+        c8.quirks.display_wait = true;
+
+        run(&mut c8, 3);
+        assert_eq!(c8.pc(), 0x200);
+        testing::assert_framebuffer(&c8, "");
+
+        c8.notify_vblank();
+        run(&mut c8, 1);
+        assert_eq!(c8.pc(), 0x202);
+        testing::assert_framebuffer(&c8, "
+            ####
+            #..#
+            #..#
+            #..#
+            ####
+        ");
+    }
+
+    #[test]
+    fn saved_display_round_trips() {
+        let mut c8 = load(&crate::chip8_program![0x6A3C, 0x6B1D, 0xDAB5, 0xF029, 0xD005]);
+        run(&mut c8, 5);
+        let saved = c8.save_display();
+        assert_eq!(saved.len(), 6 + 8 * 32);
+
+        // Into a machine that has never run, with nothing else carried over.
+        let mut other = Chip8::with_seed(1);
+        other.load_display(&saved).unwrap();
+        assert_eq!(other.gfx, c8.gfx);
+        assert_eq!(other.save_display(), saved);
+        assert_eq!(other.pc(), 0x200);
+
+        // Repainting it is a clear and the lit pixels.
+        let events = other.take_events();
+        assert_eq!(events[0], Chip8Event::ClearScreen);
+        assert_eq!(events.len() - 1, c8.gfx.iter().flatten().filter(|&&pixel| pixel != 0).count());
+
+        assert!(other.load_display(&saved[..saved.len() - 1]).is_err());
+        assert!(other.load_display(b"C8ST").is_err());
+    }
+
+    #[test]
+    fn shift_vy_reads_vy_and_keeps_it() {
+        for &(opcode, vx, vf) in [(0x8126, 0x40, 1), (0x812E, 0x02, 1)].iter() {
+            let mut c8 = load(&crate::chip8_program![0x61F0, 0x6281, opcode]);
+            c8.quirks.shift_vy = true;
+            run(&mut c8, 3);
+
+            // VX's own 0xF0 played no part, VY is the source and stays as it was.
+            assert_eq!(c8.registers[1], vx, "{:04X}", opcode);
+            assert_eq!(c8.registers[2], 0x81, "{:04X}", opcode);
+            assert_eq!(c8.registers[0xF], vf, "{:04X}", opcode);
+        }
+
+        // Without the quirk VX shifts itself.
+        let mut c8 = load(&crate::chip8_program![0x61F0, 0x6281, 0x8126]);
+        run(&mut c8, 3);
+        assert_eq!((c8.registers[1], c8.registers[2], c8.registers[0xF]), (0x78, 0x81, 0));
+    }
+
+    #[test]
+    fn carry_is_audited() {
+        let mut c8 = load(&crate::chip8_program![0x60F0, 0x6120, 0x8014]);
+        c8.enable_flag_audit();
+        run(&mut c8, 3);
+
+        assert_eq!(c8.registers[0], 0x10);
+        assert_eq!(c8.take_flag_audit(), vec![
+            FlagAccess { cycle: 2, pc: 0x204, opcode: 0x8014, write: true, value: 1 },
+        ]);
+        assert!(c8.flag_audit().is_empty());
+    }
+
+    #[test]
+    fn bcd_at_set_index() {
+        let mut c8 = load(&crate::chip8_program![0xF033]);
+        c8.set_registers([254, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        c8.set_index(0x1400);
+        assert_eq!(c8.index(), 0x400);
+
+        run(&mut c8, 1);
+        assert_eq!(c8.memory[0x400..0x403], [2, 5, 4]);
+    }
+
+    #[test]
+    fn nothing_drawn_before_first_draw() {
+        let mut c8 = load(&crate::chip8_program![0x6005, 0xF015, 0x7001, 0xA000, 0xD005]);
+        assert!(!c8.has_drawn());
+
+        run(&mut c8, 4);
+        assert!(!c8.has_drawn());
+        assert!(!c8.take_events().iter().any(|event| matches!(event, Chip8Event::Draw { .. } | Chip8Event::ClearScreen)));
+
+        run(&mut c8, 1);
+        assert!(c8.has_drawn());
+        assert!(c8.take_events().iter().any(|event| matches!(event, Chip8Event::Draw { .. })));
+
+        // Starting over is blank again, clearing counts as drawing.
+        c8.load_rom_and_reset(&Rom::try_from(&crate::chip8_program![0x00E0][..]).unwrap());
+        assert!(!c8.has_drawn());
+        run(&mut c8, 1);
+        assert!(c8.has_drawn());
+    }
+
+    #[test]
+    fn second_rom_starts_clean() {
+        let mut c8 = load(&crate::chip8_program![0x6A42, 0x6110, 0xF115, 0xD005, 0xA123, 0x2300]);
+        run(&mut c8, 6);
+        assert_eq!(c8.pc(), 0x300);
+        assert_ne!(c8.gfx_hash(), Chip8::with_seed(0).gfx_hash());
+
+        c8.load_rom_and_reset(&Rom::try_from(&crate::chip8_program![0x1200][..]).unwrap());
+        assert_eq!(c8.pc(), 0x200);
+        assert_eq!(c8.registers, [0; 16]);
+        assert_eq!(c8.index(), 0);
+        assert_eq!(c8.delay_timer(), 0);
+        assert_eq!(c8.sp, 0);
+        assert_eq!(c8.cycles(), 0);
+        testing::assert_framebuffer(&c8, "");
+    }
+
+    #[test]
+    fn display_wait_draws_on_display_tick() {
+        let mut c8 = load(&crate::chip8_program![0x6105, 0xF115, 0xD005, 0x1206]);
+        c8.quirks.display_wait = true;
+
+        // However long it sits, the draw waits for the interrupt.
+        run(&mut c8, 10);
+        assert_eq!(c8.pc(), 0x204);
+        testing::assert_framebuffer(&c8, "");
+
+        // And only the interrupt, not the timers.
+        c8.tick_display();
+        assert_eq!(c8.delay_timer(), 5);
+        run(&mut c8, 1);
+        assert_eq!(c8.pc(), 0x206);
+        testing::assert_framebuffer(&c8, "
+            ####
+            #..#
+            #..#
+            #..#
+            ####
+        ");
+    }
+
+    #[test]
+    fn sprite_ascii_of_font_glyph() {
+        let mut c8 = load(&crate::chip8_program![0x1200]);
+        c8.set_index(0xA * 5);
+        assert_eq!(c8.sprite_ascii(5), "\
+            ####....\n\
+            #..#....\n\
+            ####....\n\
+            #..#....\n\
+            #..#....\n");
+
+        // DXY0's 16x16 takes two bytes a row, here the glyph's first two rows side by side.
+        let rows = c8.sprite_ascii(0);
+        assert_eq!(rows.lines().count(), 16);
+        assert_eq!(rows.lines().next(), Some("####....#..#...."));
+    }
+
+    #[test]
+    fn film_strip_of_last_frames() {
+        let mut frames: Vec<Frame> = vec![[[0; 64]; 32]; 5];
+        for (i, frame) in frames.iter_mut().enumerate() {
+            frame[0][i] = 1;
+        }
+
+        let strip = film_strip(&frames, 3);
+        let separators: Vec<&str> = strip.lines().filter(|line| line.starts_with("---")).collect();
+        assert_eq!(separators.len(), 3);
+        assert_eq!(separators[0], format!("--- frame 2 {}", "-".repeat(52)));
+        assert!(separators.iter().all(|line| line.len() == 64));
+        assert_eq!(strip.lines().count(), 3 * 33);
+
+        // Each frame's rows follow its separator.
+        assert!(strip.lines().nth(1).unwrap().starts_with("..#."));
+        assert!(strip.lines().nth(67).unwrap().starts_with("....#"));
+
+        // Asking for more than were captured gives them all.
+        assert_eq!(film_strip(&frames, 10).lines().count(), 5 * 33);
+        assert_eq!(film_strip(&[], 3), "");
+    }
+
+    #[test]
+    fn snapshot_of_known_frame() {
+        let mut frame: Frame = [[0; 64]; 32];
+        frame[0][0] = 1;
+        frame[31][63] = 1;
+        frame[10][20] = 1;
+
+        let snapshot = FrameSnapshot::from_frame(&frame, 3);
+        assert_eq!((snapshot.width, snapshot.height), (192, 96));
+        assert_eq!(snapshot.pixels.len(), 192 * 96 * 4);
+
+        let white = Some([255, 255, 255, 255]);
+        let black = Some([0, 0, 0, 255]);
+        assert_eq!(snapshot.pixel(0, 0), white);
+        assert_eq!(snapshot.pixel(2, 2), white);
+        assert_eq!(snapshot.pixel(3, 0), black);
+        assert_eq!(snapshot.pixel(61, 31), white);
+        assert_eq!(snapshot.pixel(191, 95), white);
+        assert_eq!(snapshot.pixel(189, 92), black);
+        assert_eq!(snapshot.pixel(192, 0), None);
+
+        assert_eq!(FrameSnapshot::from_frame(&frame, 0), FrameSnapshot::from_frame(&frame, 1));
+    }
+
+    #[test]
+    fn glyph_0_as_braille() {
+        let mut c8 = load(&crate::chip8_program![0xD005]);
+        run(&mut c8, 1);
+
+        let braille = c8.gfx_braille();
+        let lines: Vec<&str> = braille.lines().collect();
+        assert_eq!(lines.len(), 8);
+        assert!(lines.iter().all(|line| line.chars().count() == 32));
+
+        // The left and right halves of the glyph's top four rows, then its bottom row.
+        assert!(lines[0].starts_with("\u{284F}\u{28B9}\u{2800}"));
+        assert!(lines[1].starts_with("\u{2809}\u{2809}\u{2800}"));
+        assert!(lines[2..].iter().all(|line| line.chars().all(|c| c == '\u{2800}')));
+    }
+
+    #[test]
+    fn glyph_0_as_ascii() {
+        let c8 = Chip8::with_seed(0);
+        assert_eq!(c8.glyph_to_ascii(0x0), "####\n#..#\n#..#\n#..#\n####\n");
+        assert_eq!(c8.glyph_to_ascii(0x10), c8.glyph_to_ascii(0x0));
+    }
+
+    #[test]
+    fn stack_holds_nested_calls() {
+        // 0x200 calls 0x204, which calls 0x208.
+        let mut c8 = load(&crate::chip8_program![0x2204, 0x0000, 0x2208, 0x0000, 0x1208]);
+        assert!(c8.stack().is_empty());
+
+        run(&mut c8, 2);
+        assert_eq!(c8.stack(), &[0x200, 0x204]);
+        assert_eq!(c8.call_trace(), vec![(0x200, 0x204), (0x204, 0x208)]);
+
+        c8.execute(Instruction::Return);
+        assert_eq!(c8.stack(), &[0x200]);
+    }
+
+    #[test]
+    fn clear_draw_and_beep_events_in_order() {
+        // A one pixel sprite at (0, 0), the 0x80 of the 0x6080 below.
+        let mut c8 = load(&crate::chip8_program![0x00E0, 0xA20B, 0xD001, 0x6002, 0xF018, 0x6080, 0x120A]);
+        run(&mut c8, 5);
+        assert_eq!(c8.take_events(), vec![
+            Chip8Event::ClearScreen,
+            Chip8Event::Draw { x: 0, y: 0, on: true },
+            Chip8Event::BeepStart,
+        ]);
+
+        c8.tick_timers();
+        assert!(c8.take_events().is_empty());
+        c8.tick_timers();
+        assert_eq!(c8.take_events(), vec![Chip8Event::BeepStop]);
+    }
+
+    #[test]
+    fn flag_wins_over_vf_as_destination() {
+        // 0xFF + 0x01 carries, the sum (0x00) is lost.
+        let mut c8 = load(&crate::chip8_program![0x6FFF, 0x6001, 0x8F04]);
+        run(&mut c8, 3);
+        assert_eq!(c8.registers()[0xF], 1);
+
+        // 0x05 + 0x10 doesn't, so VF ends up 0 rather than 0x15.
+        let mut c8 = load(&crate::chip8_program![0x6F05, 0x6110, 0x8F14]);
+        run(&mut c8, 3);
+        assert_eq!(c8.registers()[0xF], 0);
+
+        // 0x15 - 0x10 doesn't borrow, so VF is 1 rather than 0x05.
+        let mut c8 = load(&crate::chip8_program![0x6F15, 0x6110, 0x8F15]);
+        run(&mut c8, 3);
+        assert_eq!(c8.registers()[0xF], 1);
+    }
+
+    #[test]
+    fn toggling_wrap_applies_to_next_draw() {
+        // The 0 glyph at (62, 0), drawn again after clearing.
+        let mut c8 = load(&crate::chip8_program![0x603E, 0x6100, 0xD015, 0x00E0, 0xD015]);
+        run(&mut c8, 3);
+        assert_eq!(c8.gfx[0][62..], [1, 1]);
+        assert_eq!(c8.gfx[0][..2], [0, 0]);
+
+        // What F3 does in the window.
+        let wrap = !c8.quirks.wraps_sprites();
+        c8.quirks.set_wrap_sprites(wrap);
+        assert!(c8.quirks.wrap_x && c8.quirks.wrap_y);
+
+        run(&mut c8, 2);
+        assert_eq!(c8.gfx[0][62..], [1, 1]);
+        assert_eq!(c8.gfx[0][..2], [1, 1]);
+
+        c8.quirks.set_wrap_sprites(!c8.quirks.wraps_sprites());
+        assert!(!c8.quirks.wrap_x && !c8.quirks.wrap_y);
+    }
+
+    // Reads count up from 0x40, writes are kept for the test to look at.
+    struct Counter {
+        next: u8,
+        written: std::sync::Arc<std::sync::Mutex<Vec<(u16, u8)>>>,
+    }
+
+    impl Peripheral for Counter {
+        fn read(&mut self, _addr: u16) -> Option<u8> {
+            self.next += 1;
+            Some(self.next - 1)
+        }
+
+        fn write(&mut self, addr: u16, value: u8) -> bool {
+            self.written.lock().unwrap().push((addr, value));
+            true
+        }
+    }
+
+    #[test]
+    fn rom_sees_peripheral() {
+        // Read two bytes from the device, add them, and write the sum back.
+        let mut c8 = load(&crate::chip8_program![0xAF00, 0xF165, 0x8014, 0xAF00, 0xF055]);
+        let written = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        c8.attach_peripheral(0xF00..=0xF01, Box::new(Counter { next: 0x40, written: written.clone() }));
+
+        run(&mut c8, 5);
+        assert_eq!(c8.registers()[..2], [0x81, 0x41]);
+        assert_eq!(*written.lock().unwrap(), vec![(0xF00, 0x81)]);
+        assert_eq!(c8.memory[0xF00], 0);
+
+        // Outside the range, and once detached, it's plain RAM.
+        c8.set_index(0xF02);
+        c8.execute(Instruction::StoreRegs(VReg::V0));
+        assert_eq!(c8.memory[0xF02], 0x81);
+        c8.detach_peripheral();
+        c8.set_index(0xF00);
+        c8.execute(Instruction::StoreRegs(VReg::V0));
+        assert_eq!(c8.memory[0xF00], 0x81);
+        assert_eq!(written.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn key_sampling_per_cycle_and_per_frame() {
+        // Waits in a loop until key 5 is down, then halts at 0x206.
+        let program = crate::chip8_program![0x6105, 0xE19E, 0x1202, 0x1206];
+
+        let mut c8 = load(&program);
+        run(&mut c8, 3);
+        c8.key_pressed(5);
+        run(&mut c8, 3);
+        assert_eq!(c8.pc(), 0x206);
+
+        let mut c8 = load(&program);
+        c8.key_sampling = KeySampling::PerFrame;
+        run(&mut c8, 3);
+        c8.key_pressed(5);
+        run(&mut c8, 6);
+        assert_ne!(c8.pc(), 0x206);
+        c8.tick_display();
+        run(&mut c8, 3);
+        assert_eq!(c8.pc(), 0x206);
+    }
+
+    #[test]
+    fn gfx_diff_finds_the_one_pixel() {
+        let a = Chip8::with_seed(0);
+        let mut b = Chip8::with_seed(0);
+        assert!(a.gfx_diff(&b).is_empty());
+
+        b.gfx[17][40] = 1;
+        assert_eq!(a.gfx_diff(&b), vec![(40, 17)]);
+        assert_eq!(b.gfx_diff(&a), vec![(40, 17)]);
+    }
+
+    #[test]
+    fn sprite_edges_per_axis() {
+        // An 8x2 block at (60, 31), over the right and bottom edges.
+        for &(wrap_x, wrap_y) in [(false, false), (true, false), (false, true), (true, true)].iter() {
+            let mut c8 = load(&crate::chip8_program![0x603C, 0x611F, 0xA300, 0xD012]);
+            c8.quirks.wrap_x = wrap_x;
+            c8.quirks.wrap_y = wrap_y;
+            c8.memory[0x300..0x302].copy_from_slice(&[0xFF, 0xFF]);
+            run(&mut c8, 4);
+
+            let lit = |xs: std::ops::Range<usize>, y: usize| xs.map(|x| c8.gfx[y][x]).collect::<Vec<_>>();
+            let all = |on: bool| vec![on as u8; 4];
+            assert_eq!(lit(60..64, 31), all(true));
+            assert_eq!(lit(0..4, 31), all(wrap_x), "right edge, wrap_x {}", wrap_x);
+            assert_eq!(lit(60..64, 0), all(wrap_y), "bottom edge, wrap_y {}", wrap_y);
+            assert_eq!(lit(0..4, 0), all(wrap_x && wrap_y), "corner, wrap_x {} wrap_y {}", wrap_x, wrap_y);
+
+            let expected = 4 + 4 * wrap_x as usize + 4 * wrap_y as usize + 4 * (wrap_x && wrap_y) as usize;
+            assert_eq!(c8.gfx.iter().flatten().filter(|&&pixel| pixel != 0).count(), expected);
+        }
+    }
+
+    #[test]
+    fn scroll_up_moves_rows_and_clears_bottom() {
+        let mut c8 = load(&crate::chip8_program![0x00D2]);
+        c8.quirks.xo_chip = true;
+        c8.gfx[0][0] = 1;
+        c8.gfx[2][1] = 1;
+        c8.gfx[5][2] = 1;
+        c8.gfx[31][63] = 1;
+
+        run(&mut c8, 1);
+        assert_eq!(c8.pc(), 0x202);
+
+        // Row 0 is gone, the bottom row moved up leaving two blank rows under it.
+        let lit: Vec<(usize, usize)> = (0..32).flat_map(|y| (0..64).map(move |x| (x, y)))
+            .filter(|&(x, y)| c8.gfx[y][x] != 0)
+            .collect();
+        assert_eq!(lit, vec![(1, 0), (2, 3), (63, 29)]);
+    }
+
+    #[test]
+    fn low_res_scroll_by_setting() {
+        for &(half, to_row) in [(false, 6), (true, 8)].iter() {
+            let mut c8 = load(&crate::chip8_program![0x00D4]);
+            c8.quirks.xo_chip = true;
+            c8.quirks.half_pixel_scroll = half;
+            c8.gfx[10][7] = 1;
+
+            run(&mut c8, 1);
+            assert_eq!(c8.gfx[to_row][7], 1, "half_pixel_scroll: {}", half);
+            assert_eq!(c8.gfx.iter().flatten().filter(|&&pixel| pixel != 0).count(), 1);
+            assert_eq!(c8.take_warnings(), vec![Warning::LowResScroll { pc: 0x200 }]);
+        }
+    }
+
+    #[test]
+    fn store_and_load_through_vf() {
+        let mut c8 = Chip8::with_seed(0);
+        let mut registers = [0; 16];
+        registers.iter_mut().zip(1..).for_each(|(reg, value)| *reg = value);
+        c8.set_registers(registers);
+        c8.set_index(0x300);
+
+        c8.execute(Instruction::StoreRegs(VReg::VF));
+        assert_eq!(&c8.memory()[0x300..0x311], &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 0]);
+
+        c8.set_registers([0; 16]);
+        c8.execute(Instruction::LoadRegs(VReg::VF));
+        assert_eq!(c8.registers(), &registers);
+    }
+
+    #[test]
+    fn store_and_load_past_end_of_memory() {
+        // Anything past VF doesn't make a register at all.
+        assert_eq!(VReg::from_nibble(16), None);
+        assert_eq!(VReg::from_nibble(255), None);
+
+        let mut c8 = Chip8::with_seed(0);
+        c8.quirks.load_store_increment = true;
+        c8.set_registers([0xAB; 16]);
+        c8.set_index(0xFF8);
+
+        c8.execute(Instruction::StoreRegs(VReg::VF));
+        assert_eq!(&c8.memory()[0xFF8..], &[0xAB; 8]);
+        assert_eq!(&c8.memory()[..8], &[0xAB; 8]);
+        assert_eq!(c8.index(), 0x1008);
+
+        c8.set_index(0xFFC);
+        c8.execute(Instruction::LoadRegs(VReg::V7));
+        assert_eq!(&c8.registers()[..8], &[0xAB; 8]);
+        assert_eq!(c8.index(), 0x1004);
+    }
+
+    #[test]
+    fn gfx_rle_round_trip() {
+        let mut c8 = Chip8::with_seed(0);
+        assert_eq!(c8.gfx_rle(), "2048");
+        assert_eq!(decode_gfx_rle("2048"), Some(c8.gfx));
+
+        // Starting lit needs an empty first run.
+        c8.gfx[0][0] = 1;
+        c8.gfx[0][1] = 1;
+        c8.gfx[1][63] = 1;
+        c8.gfx[31][63] = 1;
+        assert_eq!(c8.gfx_rle(), "0,2,125,1,1919,1");
+        assert_eq!(decode_gfx_rle(&c8.gfx_rle()), Some(c8.gfx));
+
+        assert_eq!(decode_gfx_rle("2047"), None);
+        assert_eq!(decode_gfx_rle("2048,1"), None);
+        assert_eq!(decode_gfx_rle("10,x"), None);
+    }
+
+    #[test]
+    fn step_over_runs_the_whole_call() {
+        // 0x202 calls a subroutine at 0x208 that sets V1 and V2, including a nested call.
+        let mut c8 = load(&crate::chip8_program![0x6001, 0x2208, 0x6303, 0x1206, 0x6101, 0x220E, 0x00EE, 0x6202, 0x00EE]);
+
+        assert_eq!(c8.step_over(100), RunResult::Stepped);
+        assert_eq!(c8.pc(), 0x202);
+
+        assert_eq!(c8.step_over(100), RunResult::Stepped);
+        assert_eq!(c8.pc(), 0x204);
+        assert_eq!(c8.registers()[..4], [1, 1, 2, 0]);
+        assert!(c8.stack().is_empty());
+        assert_eq!(c8.cycles(), 7);
+
+        // A call that never returns runs into the cap.
+        let mut c8 = load(&crate::chip8_program![0x2202, 0x1202]);
+        assert_eq!(c8.step_over(50), RunResult::CycleLimit);
+        assert_eq!(c8.cycles(), 50);
+    }
+
+    #[test]
+    fn check_targets_flags_jumps_into_reserved_area() {
+        let checked = |program: &[u8]| {
+            let mut c8 = Chip8::with_seed(0);
+            c8.check_targets = true;
+            c8.load_rom(&Rom::try_from(program).unwrap());
+            c8.take_warnings()
+        };
+
+        assert_eq!(checked(&crate::chip8_program![0x1000]), vec![Warning::ReservedTarget { pc: 0x200, target: 0x000 }]);
+        assert_eq!(checked(&crate::chip8_program![0x1202, 0x2300, 0xA200]), vec![]);
+        assert_eq!(checked(&crate::chip8_program![0x21FE, 0xA050, 0x6000]), vec![
+            Warning::ReservedTarget { pc: 0x200, target: 0x1FE },
+            Warning::ReservedTarget { pc: 0x202, target: 0x050 },
+        ]);
+
+        // Off unless asked for.
+        assert!(load(&crate::chip8_program![0x1000]).take_warnings().is_empty());
+    }
+
+    #[test]
+    fn draw_after_index_overflow() {
+        // I = 0xFFD + 7, then a 5 row sprite at (0, 0).
+        let rows = |clamp_index: bool| {
+            let mut c8 = load(&crate::chip8_program![0xAFFD, 0x6207, 0xF21E, 0xD005]);
+            c8.quirks.clamp_index = clamp_index;
+            c8.memory[0xFFF] = 0xAA;
+            run(&mut c8, 4);
+            assert_eq!(c8.pc(), 0x208);
+            (0..5).map(|y| c8.gfx[y][..8].iter().fold(0, |byte, &pixel| byte << 1 | pixel)).collect::<Vec<u8>>()
+        };
+
+        // Wrapped to 0x004: the end of the 0 glyph and most of the 1.
+        assert_eq!(rows(false), vec![0xF0, 0x20, 0x60, 0x20, 0x20]);
+        // Held at 0xFFF, running on into the 0 glyph.
+        assert_eq!(rows(true), vec![0xAA, 0xF0, 0x90, 0x90, 0x90]);
+    }
+
+    #[test]
+    fn frame_as_ppm() {
+        let mut frame: Frame = [[0; 64]; 32];
+        frame[0][1] = 1;
+        frame[31][63] = 1;
+
+        let ppm = frame_to_ppm(&frame);
+        let header = b"P6\n64 32\n255\n";
+        assert_eq!(&ppm[..header.len()], header);
+
+        let pixels = &ppm[header.len()..];
+        assert_eq!(pixels.len(), 64 * 32 * 3);
+        assert_eq!(&pixels[..6], &[0, 0, 0, 255, 255, 255]);
+        assert_eq!(&pixels[pixels.len() - 3..], &[255, 255, 255]);
+        assert_eq!(pixels.iter().filter(|&&level| level == 255).count(), 2 * 3);
+    }
+
+    #[test]
+    fn tall_sprite_at_top_of_memory() {
+        // 15 rows from 0xFFA, the last 9 come from the font at the start of memory.
+        let mut c8 = load(&crate::chip8_program![0xAFFA, 0xD00F]);
+        run(&mut c8, 2);
+        assert_eq!(c8.pc(), 0x204);
+        assert_eq!(c8.take_warnings(), vec![Warning::SpriteWrap { pc: 0x202, index: 0xFFA }]);
+        assert_eq!(c8.gfx[6][..4], [1, 1, 1, 1]);
+
+        // SCHIP's 16x16 reads 32 bytes.
+        let mut c8 = load(&crate::chip8_program![0xAFF0, 0xD000]);
+        c8.quirks.schip = true;
+        run(&mut c8, 2);
+        assert_eq!(c8.pc(), 0x204);
+        assert_eq!(c8.take_warnings(), vec![Warning::SpriteWrap { pc: 0x202, index: 0xFF0 }]);
+        assert_eq!(c8.gfx[8][..4], [1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn keys_snapshot_has_the_pressed_keys() {
+        let mut c8 = Chip8::with_seed(0);
+        c8.key_pressed(0x2);
+        c8.key_pressed(0xE);
+
+        let mut expected = [0; 16];
+        expected[0x2] = 1;
+        expected[0xE] = 1;
+        assert_eq!(c8.keys_snapshot(), expected);
+
+        c8.key_released(0x2);
+        expected[0x2] = 0;
+        assert_eq!(c8.keys_snapshot(), expected);
+    }
+
+    #[test]
+    fn run_until_beep_stops_on_the_sound_timer_set() {
+        let mut c8 = load(&crate::chip8_program![0x6003, 0x7001, 0x7001, 0xF018, 0xF018, 0x120A]);
+        assert_eq!(c8.run_until_beep(100), RunResult::Beeped);
+        assert_eq!(c8.cycles(), 4);
+        assert_eq!(c8.sound_timer(), 5);
+
+        // Setting it again while it's going isn't a new beep.
+        assert_eq!(c8.run_until_beep(20), RunResult::CycleLimit);
+        assert_eq!(c8.cycles(), 24);
+    }
+
+    // 17 calls deep, each instruction calling the next one.
+    #[test]
+    fn random_roms_do_not_panic() {
+        // A short run of what the fuzz target does, random programs under
+        // random quirks, with the one stack policy that never panics on purpose.
+        let mut rng = StdRng::seed_from_u64(721);
+
+        for _ in 0..300 {
+            let len = rng.gen_range(2, 257);
+            let program: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+
+            let mut c8 = load(&program);
+            c8.quirks = Quirks {
+                wrap_x: rng.gen(),
+                wrap_y: rng.gen(),
+                xo_chip: rng.gen(),
+                schip: rng.gen(),
+                shift_vy: rng.gen(),
+                jump_vx: rng.gen(),
+                load_store_increment: rng.gen(),
+                vf_reset: rng.gen(),
+                display_wait: rng.gen(),
+                clamp_index: rng.gen(),
+                half_pixel_scroll: rng.gen(),
+                stack: StackPolicy::Wrap,
+            };
+
+            let ipf = c8.cycles_per_timer_tick();
+            for _ in 0..3000 {
+                c8.cycle_in_frame(ipf);
+            }
+        }
+    }
+
+    fn deep_calls(stack: StackPolicy) -> Chip8 {
+        let program: Vec<u8> = (0..17).flat_map(|i| (0x2202u16 + i * 2).to_be_bytes().to_vec()).collect();
+        let mut c8 = load(&program);
+        c8.quirks.stack = stack;
+        run(&mut c8, 17);
+        c8
+    }
+
+    #[test]
+    #[should_panic(expected = "stack overflow: call at 220 nested 17 deep")]
+    fn stack_overflow_errors() {
+        deep_calls(StackPolicy::Error);
+    }
+
+    #[test]
+    #[should_panic(expected = "stack underflow: return at 200 with no call to return from")]
+    fn stack_underflow_errors() {
+        let mut c8 = load(&crate::chip8_program![0x00EE]);
+        c8.quirks.stack = StackPolicy::Error;
+        run(&mut c8, 1);
+    }
+
+    #[test]
+    fn stack_underflow_wraps() {
+        // Once back from the one call, the next return goes round to the top
+        // slot, which has never been written.
+        let mut c8 = load(&crate::chip8_program![0x2204, 0x00EE, 0x00EE]);
+        c8.quirks.stack = StackPolicy::Wrap;
+        run(&mut c8, 2);
+        assert_eq!(c8.pc(), 0x202);
+        assert!(c8.stack().is_empty());
+
+        run(&mut c8, 1);
+        assert_eq!(c8.pc(), 0x002);
+        assert_eq!(c8.stack().len(), STACK_SIZE - 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "stack underflow: return at 202 with no call to return from")]
+    fn stack_underflow_grows_panics() {
+        let mut c8 = load(&crate::chip8_program![0x2202, 0x00EE, 0x00EE]);
+        c8.quirks.stack = StackPolicy::Grow;
+        run(&mut c8, 3);
+    }
+
+    #[test]
+    fn stack_overflow_wraps() {
+        let c8 = deep_calls(StackPolicy::Wrap);
+        assert_eq!(c8.pc(), 0x222);
+        // The oldest return address was overwritten.
+        assert_eq!(c8.stack(), &[0x220]);
+    }
+
+    #[test]
+    fn stack_overflow_grows() {
+        let mut c8 = deep_calls(StackPolicy::Grow);
+        assert_eq!(c8.pc(), 0x222);
+        assert_eq!(c8.stack().len(), 17);
+        assert_eq!(c8.stack()[16], 0x220);
+
+        // And unwinds all the way.
+        for _ in 0..17 {
+            c8.execute(Instruction::Return);
+        }
+        assert_eq!(c8.pc(), 0x202);
+        assert!(c8.stack().is_empty());
+    }
+
+    #[test]
+    fn sprite_wraps_horizontally() {
+        // A solid 8 wide row at x=60.
+        let mut c8 = load(&crate::chip8_program![0x603C, 0xA208, 0xD011, 0x1206, 0xFF00]);
+        c8.quirks.wrap_x = true;
+        run(&mut c8, 3);
+
+        let lit: Vec<usize> = (0..64).filter(|&x| c8.gfx[0][x] != 0).collect();
+        assert_eq!(lit, vec![0, 1, 2, 3, 60, 61, 62, 63]);
+    }
+
+    #[test]
+    fn sprite_clips_without_wrap() {
+        let mut c8 = load(&crate::chip8_program![0x603C, 0xA208, 0xD011, 0x1206, 0xFF00]);
+        run(&mut c8, 3);
+
+        let lit: Vec<usize> = (0..64).filter(|&x| c8.gfx[0][x] != 0).collect();
+        assert_eq!(lit, vec![60, 61, 62, 63]);
+    }
+
+    #[test]
+    fn wait_key_picks_lowest_of_3_and_10() {
+        // Pressed in either order, through a ROM, 3 wins.
+        for &(first, second) in [(0x3, 0xA), (0xA, 0x3)].iter() {
+            let mut c8 = load(&crate::chip8_program![0xF50A, 0x1202]);
+            c8.key_pressed(first);
+            c8.key_pressed(second);
+
+            run(&mut c8, 1);
+            assert_eq!(c8.registers()[5], 0x3);
+            assert_eq!(c8.pc(), 0x202);
+        }
+    }
+
+    #[test]
+    fn pc_advances_by_two_or_skips_by_four() {
+        let straight = [
+            0x00E0, 0x6123, 0x7123, 0x8120, 0x8121, 0x8122, 0x8123, 0x8124, 0x8125, 0x8126, 0x8127, 0x812E,
+            0xA123, 0xC1FF, 0xD125, 0xF107, 0xF115, 0xF118, 0xF11E, 0xF129, 0xF133, 0xF155, 0xF165,
+        ];
+        for &opcode in straight.iter() {
+            let mut c8 = load(&crate::chip8_program![opcode]);
+            run(&mut c8, 1);
+            assert_eq!(c8.pc(), 0x202, "{:04X}", opcode);
+        }
+
+        // Skips, as V0, V1, whether key 5 is down and where PC ends up.
+        let skips = [
+            (0x3005, 5, 0, false, 0x204), (0x3005, 4, 0, false, 0x202),
+            (0x4005, 5, 0, false, 0x202), (0x4005, 4, 0, false, 0x204),
+            (0x5010, 5, 5, false, 0x204), (0x5010, 5, 6, false, 0x202),
+            (0x9010, 5, 5, false, 0x202), (0x9010, 5, 6, false, 0x204),
+            (0xE09E, 5, 0, true, 0x204), (0xE09E, 5, 0, false, 0x202),
+            (0xE0A1, 5, 0, true, 0x202), (0xE0A1, 5, 0, false, 0x204),
+        ];
+        for &(opcode, v0, v1, key_down, pc) in skips.iter() {
+            let mut c8 = load(&crate::chip8_program![opcode]);
+            c8.set_register(0, v0);
+            c8.set_register(1, v1);
+            if key_down {
+                c8.key_pressed(5);
+            }
+            run(&mut c8, 1);
+            assert_eq!(c8.pc(), pc, "{:04X} with V0={} V1={} key down: {}", opcode, v0, v1, key_down);
+        }
+    }
+
+    #[test]
+    fn key_opcodes_mask_to_15() {
+        // EX9E skips with key 15 down, EXA1 doesn't.
+        let mut c8 = load(&crate::chip8_program![0x601F, 0xE09E, 0x0000, 0xE0A1, 0x1208]);
+        c8.key_pressed(0xF);
+        run(&mut c8, 3);
+        assert_eq!(c8.pc(), 0x208);
+
+        // And the other way round with it up.
+        let mut c8 = load(&crate::chip8_program![0x601F, 0xE09E, 0xE0A1, 0x0000, 0x1208]);
+        run(&mut c8, 3);
+        assert_eq!(c8.pc(), 0x208);
+
+        // FX0A stores the masked number too.
+        let mut c8 = load(&crate::chip8_program![0xF00A]);
+        c8.key_order[0xF] = 0x1F;
+        c8.key_pressed(0xF);
+        run(&mut c8, 1);
+        assert_eq!(c8.registers()[0], 0xF);
+    }
+
+    #[test]
+    fn wait_key_takes_lowest_key_number() {
+        let mut c8 = Chip8::with_seed(0);
+        c8.key_order = [0xF, 0xE, 0xD, 0xC, 0xB, 0xA, 0x9, 0x8, 0x7, 0x6, 0x5, 0x4, 0x3, 0x2, 0x1, 0x0];
+        c8.key_pressed(0x1);
+        c8.key_pressed(0x3);
+
+        c8.execute(Instruction::WaitKey(VReg::V0));
+        assert_eq!(c8.reg(VReg::V0), 0xC);
+    }
+
+    #[test]
+    fn key_order_renumbers_skips() {
+        let mut c8 = Chip8::with_seed(0);
+        c8.key_order[0x1] = 0x4;
+        c8.key_order[0x4] = 0x1;
+        c8.key_pressed(0x4);
+
+        c8.set_reg(VReg::V0, 0x1);
+        let pc = c8.pc();
+        c8.execute(Instruction::SkipKey(VReg::V0));
+        assert_eq!(c8.pc(), pc + 4);
+    }
+
+    #[test]
+    fn crash_report_fields() {
+        let mut c8 = Chip8::with_seed(0);
+        c8.load_rom(&Rom::try_from(&crate::chip8_program![0x600A, 0xA123, 0x2208, 0x0000, 0x00E0][..]).unwrap());
+        for _ in 0..4 {
+            c8.cycle();
+        }
+
+        let report = c8.crash_report();
+        assert!(report.starts_with("PC: 20A  opcode: 00E0  I: 123  SP: 1  DT: 00  ST: 00  cycles: 4
+"), "{}", report);
+        assert!(report.contains("V0=0A V1=00"));
+        assert!(report.contains("Calls:\n  204: CALL #208\n"));
+        assert!(report.contains("  204: 2208  CALL #208\n"));
+        assert!(report.contains(&format!("Framebuffer hash: {:016X}\n", c8.gfx_hash())));
+    }
+
+    #[test]
+    fn load_audio_pattern() {
+        let mut c8 = Chip8::with_seed(0);
+        c8.quirks.xo_chip = true;
+        for i in 0..16 {
+            c8.memory[0x300 + i] = i as u8 * 3;
+        }
+        c8.index = 0x300;
+
+        c8.execute(Instruction::LoadAudio);
+        assert_eq!(c8.audio_pattern(), &[0, 3, 6, 9, 12, 15, 18, 21, 24, 27, 30, 33, 36, 39, 42, 45]);
+    }
+
+    #[test]
+    fn load_audio_needs_xo_chip() {
+        let mut c8 = Chip8::with_seed(0);
+        c8.memory[0x300] = 0xFF;
+        c8.index = 0x300;
+        c8.opcode = 0xF002;
+
+        c8.execute(Instruction::LoadAudio);
+        assert_eq!(c8.audio_pattern(), &audio::DEFAULT_PATTERN);
+        assert_eq!(c8.take_warnings(), vec![Warning::UnknownOpcode { pc: 0x200, opcode: 0xF002 }]);
+    }
+
+    #[test]
+    fn run_to_next_draw_stops_after_draw() {
+        let mut c8 = Chip8::with_seed(0);
+        c8.load_rom(&Rom::try_from(&crate::chip8_program![0x6A01, 0xD015, 0x00E0][..]).unwrap());
+
+        assert_eq!(c8.run_to_next_draw(100), RunResult::Drew);
+        assert_eq!(c8.pc(), 0x204);
+        testing::assert_framebuffer(&c8, "
+            ####
+            #..#
+            #..#
+            #..#
+            ####
+        ");
+
+        assert_eq!(c8.run_to_next_draw(100), RunResult::Drew);
+        assert_eq!(c8.pc(), 0x206);
+        testing::assert_framebuffer(&c8, "");
+    }
+
+    #[test]
+    fn run_to_next_draw_skips_unknown_draws() {
+        let mut c8 = Chip8::with_seed(0);
+        c8.load_rom(&Rom::try_from(&crate::chip8_program![0xD010, 0x00D1, 0x1204][..]).unwrap());
+
+        assert_eq!(c8.run_to_next_draw(10), RunResult::CycleLimit);
+        assert!(!c8.has_drawn());
+    }
+
+    #[test]
+    fn load_state_stops_beep() {
+        let mut c8 = Chip8::with_seed(0);
+        let silent = c8.save_state();
+        c8.set_reg(VReg::V0, 30);
+        c8.execute(Instruction::SetSound(VReg::V0));
+        assert_eq!(c8.take_events(), vec![Chip8Event::BeepStart]);
+
+        c8.load_state(&silent);
+        let events = c8.take_events();
+        assert_eq!(events.last(), Some(&Chip8Event::BeepStop));
+        assert!(!events.contains(&Chip8Event::BeepStart));
+    }
+
+    #[test]
+    fn load_state_starts_beep() {
+        let mut c8 = Chip8::with_seed(0);
+        c8.set_reg(VReg::V0, 30);
+        c8.execute(Instruction::SetSound(VReg::V0));
+        let beeping = c8.save_state();
+
+        c8.reset();
+        assert_eq!(c8.take_events().last(), Some(&Chip8Event::BeepStop));
+
+        c8.load_state(&beeping);
+        assert_eq!(c8.take_events().last(), Some(&Chip8Event::BeepStart));
+    }
+
+    #[test]
+    fn timer_tick_at_50_and_60_hz() {
+        let mut c8 = Chip8::with_seed(0);
+        c8.ips = 600;
+
+        assert_eq!(c8.timer_interval(), Duration::from_nanos(16_666_666));
+        assert_eq!(c8.cycles_per_timer_tick(), 10);
+
+        c8.timer_hz = 50;
+        assert_eq!(c8.timer_interval(), Duration::from_millis(20));
+        assert_eq!(c8.cycles_per_timer_tick(), 12);
+    }
+
+    // SE V0, #00 at 0xFFC skips to 0x1000, past the last whole instruction.
+    fn skip_at_top(strict_pc: bool) -> Chip8 {
+        let mut c8 = Chip8::with_seed(0);
+        c8.strict_pc = strict_pc;
+        c8.memory[0xFFC] = 0x30;
+        c8.memory[0xFFD] = 0x00;
+        c8.pc = 0xFFC;
+        c8.cycle();
+        assert_eq!(c8.pc(), 0x1000);
+        c8
+    }
+
+    #[test]
+    fn skip_past_end_wraps() {
+        let mut c8 = skip_at_top(false);
+        c8.cycle();
+
+        // Address 0 holds the font, which runs (as unknown) from there.
+        assert_eq!(c8.take_warnings()[0], Warning::PcWrap { pc: 0x1000 });
+        assert_eq!(c8.pc(), 0x002);
+        assert_eq!(c8.pc_overrun(), None);
+        assert!(!c8.stopped());
+    }
+
+    #[test]
+    fn skip_past_end_stops_when_strict() {
+        let mut c8 = skip_at_top(true);
+        c8.cycle();
+        c8.cycle();
+
+        assert_eq!(c8.pc_overrun(), Some(0x1000));
+        assert!(c8.stopped());
+        assert_eq!(c8.pc(), 0x1000);
+        assert_eq!(c8.run_to_next_draw(10), RunResult::PcOverrun);
+        assert!(c8.take_warnings().is_empty());
+
+        c8.reset();
+        assert!(!c8.stopped());
     }
 }
@@ -0,0 +1,101 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use super::rom::RomError;
+
+// Anything that can go wrong getting files into the emulator.
+#[derive(Debug)]
+pub enum Chip8Error {
+    NotFound(PathBuf),
+    IsDirectory(PathBuf),
+    PermissionDenied(PathBuf),
+    Io(io::Error), // Any other I/O problem.
+    Rom(RomError),
+}
+
+impl Chip8Error {
+    // Name the usual mistakes with a path, or keep the I/O error as it is.
+    pub fn from_io(e: io::Error, path: PathBuf) -> Chip8Error {
+        match e.kind() {
+            io::ErrorKind::NotFound => Chip8Error::NotFound(path),
+            io::ErrorKind::PermissionDenied => Chip8Error::PermissionDenied(path),
+            _ => Chip8Error::Io(e),
+        }
+    }
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chip8Error::NotFound(path) => write!(f, "{} doesn't exist", path.display()),
+            Chip8Error::IsDirectory(path) =>
+                write!(f, "{} is a directory, give the path of a ROM file in it", path.display()),
+            Chip8Error::PermissionDenied(path) =>
+                write!(f, "{} can't be read, check its permissions", path.display()),
+            Chip8Error::Io(e) => write!(f, "{}", e),
+            Chip8Error::Rom(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {}
+
+impl From<io::Error> for Chip8Error {
+    fn from(e: io::Error) -> Chip8Error {
+        Chip8Error::Io(e)
+    }
+}
+
+impl From<RomError> for Chip8Error {
+    fn from(e: RomError) -> Chip8Error {
+        Chip8Error::Rom(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emu::Rom;
+
+    #[test]
+    fn missing_rom_is_not_found() {
+        let path = std::env::temp_dir().join("chip8rs-no-such-rom.ch8");
+        match Rom::from_path(&path) {
+            Err(Chip8Error::NotFound(missing)) => assert_eq!(missing, path),
+            other => panic!("expected NotFound, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn directory_is_not_a_rom() {
+        let dir = std::env::temp_dir();
+        match Rom::from_path(&dir) {
+            Err(e @ Chip8Error::IsDirectory(_)) => {
+                assert!(matches!(&e, Chip8Error::IsDirectory(path) if *path == dir));
+                assert_eq!(e.to_string(), format!("{} is a directory, give the path of a ROM file in it", dir.display()));
+            },
+            other => panic!("expected IsDirectory, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn io_errors_convert() {
+        let path = PathBuf::from("rom.ch8");
+        let denied = io::Error::new(io::ErrorKind::PermissionDenied, "denied");
+        assert!(matches!(Chip8Error::from_io(denied, path.clone()), Chip8Error::PermissionDenied(p) if p == path));
+
+        let other = io::Error::new(io::ErrorKind::UnexpectedEof, "short read");
+        assert!(matches!(Chip8Error::from_io(other, path), Chip8Error::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof));
+
+        // With `?`, no path to name.
+        let read = || -> Result<(), Chip8Error> { Err(io::Error::new(io::ErrorKind::NotFound, "gone"))? };
+        assert!(matches!(read(), Err(Chip8Error::Io(_))));
+    }
+
+    #[test]
+    fn rom_errors_convert() {
+        let read = || -> Result<(), Chip8Error> { Err(RomError::Empty)? };
+        assert!(matches!(read(), Err(Chip8Error::Rom(RomError::Empty))));
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+
+use super::Chip8;
+
+// Everything a frontend may want to react to, in the order it happened.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Chip8Event {
+    ClearScreen,
+    Draw { x: u16, y: u16, on: bool }, // A single pixel changed.
+    BeepStart, // The sound timer was set while silent.
+    BeepStop, // The sound timer ran out (or was set to zero).
+}
+
+// A frontend that only watches, like a debugger window next to the game
+// window. `Chip8::publish_events` hands every observer the same events, along
+// with the machine to read anything else from.
+pub trait Observer {
+    fn observe(&mut self, c8: &Chip8, events: &[Chip8Event]);
+}
+
+// The same changes with the redundant ones dropped: draws before the last
+// ClearScreen, and pixels drawn more than once that end up as they started.
+// A pixel that did change is reported once, where it last changed. Anything
+// VF saw has already happened, so collisions aren't affected.
+pub fn coalesce(events: &[Chip8Event]) -> Vec<Chip8Event> {
+    let last_clear = events.iter().rposition(|&event| event == Chip8Event::ClearScreen);
+    let cleared = |i: usize| last_clear.is_some_and(|clear| i > clear);
+
+    // For each pixel drawn, its state before the first draw and the index of the last.
+    let mut pixels: HashMap<(u16, u16), (bool, usize)> = HashMap::new();
+    for (i, &event) in events.iter().enumerate() {
+        if let Chip8Event::Draw { x, y, on } = event {
+            if last_clear.is_some_and(|clear| i < clear) {
+                continue;
+            }
+            let before = if cleared(i) { false } else { !on };
+            pixels.entry((x, y)).or_insert((before, i)).1 = i;
+        }
+    }
+
+    events.iter().enumerate().filter(|&(i, &event)| match event {
+        Chip8Event::ClearScreen => Some(i) == last_clear,
+        Chip8Event::Draw { x, y, on } => match pixels.get(&(x, y)) {
+            Some(&(before, last)) => i == last && on != before,
+            None => false,
+        },
+        _ => true,
+    }).map(|(_, &event)| event).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emu::Rom;
+    use std::convert::TryFrom;
+
+    // Keeps everything it was shown, and the PC it was shown at.
+    #[derive(Default)]
+    struct Recorder {
+        seen: Vec<(u16, Vec<Chip8Event>)>,
+    }
+
+    impl Observer for Recorder {
+        fn observe(&mut self, c8: &Chip8, events: &[Chip8Event]) {
+            self.seen.push((c8.pc(), events.to_vec()));
+        }
+    }
+
+    #[test]
+    fn observers_see_the_same_events() {
+        let mut c8 = Chip8::with_seed(0);
+        c8.load_rom(&Rom::try_from(&crate::chip8_program![0xA20A, 0xD011, 0x1204, 0, 0, 0xC000][..]).unwrap());
+        c8.cycle();
+        c8.cycle();
+
+        let (mut window, mut debugger) = (Recorder::default(), Recorder::default());
+        c8.publish_events(&mut [&mut window, &mut debugger]);
+        let expected = vec![(0x204, vec![Chip8Event::Draw { x: 0, y: 0, on: true }, Chip8Event::Draw { x: 1, y: 0, on: true }])];
+        assert_eq!(window.seen, expected);
+        assert_eq!(debugger.seen, expected);
+
+        // Gone once published.
+        assert!(c8.take_events().is_empty());
+    }
+
+    #[test]
+    fn redundant_draws_coalesce_but_collide() {
+        // The same pixel at (0, 0) drawn three times, VF kept after each draw.
+        let program = crate::chip8_program![0xA20E, 0xD001, 0x8AF0, 0xD001, 0x8BF0, 0xD001, 0x120C, 0x8000];
+        let mut c8 = Chip8::with_seed(0);
+        c8.coalesce_events = true;
+        c8.load_rom(&Rom::try_from(&program[..]).unwrap());
+        for _ in 0..6 {
+            c8.cycle();
+        }
+
+        assert_eq!(c8.take_events(), vec![Chip8Event::Draw { x: 0, y: 0, on: true }]);
+        assert_eq!((c8.registers()[0xA], c8.registers()[0xB], c8.registers()[0xF]), (0, 1, 0));
+    }
+
+    #[test]
+    fn coalesce_drops_what_cancels_out() {
+        let on = |x| Chip8Event::Draw { x, y: 0, on: true };
+        let off = |x| Chip8Event::Draw { x, y: 0, on: false };
+
+        // Drawn and erased again, nothing changed.
+        assert_eq!(coalesce(&[on(1), off(1), Chip8Event::BeepStart]), vec![Chip8Event::BeepStart]);
+        // Only the last change of a pixel is kept.
+        assert_eq!(coalesce(&[on(1), on(2), off(1), on(1)]), vec![on(2), on(1)]);
+        // Nothing before a clear matters, and erasing after one changes nothing.
+        assert_eq!(coalesce(&[on(1), Chip8Event::ClearScreen, on(2), off(3), on(3)]),
+            vec![Chip8Event::ClearScreen, on(2), on(3)]);
+    }
+}
@@ -0,0 +1,465 @@
+use std::fmt;
+
+// One of the sixteen general purpose registers. VF doubles as the flag
+// register: carry, borrow, shifted out bit and sprite collision all land
+// there, overwriting whatever the ROM kept in it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum VReg {
+    V0, V1, V2, V3, V4, V5, V6, V7,
+    V8, V9, VA, VB, VC, VD, VE,
+    VF, // Flag
+}
+
+impl VReg {
+    pub const ALL: [VReg; 16] = [
+        VReg::V0, VReg::V1, VReg::V2, VReg::V3, VReg::V4, VReg::V5, VReg::V6, VReg::V7,
+        VReg::V8, VReg::V9, VReg::VA, VReg::VB, VReg::VC, VReg::VD, VReg::VE, VReg::VF,
+    ];
+
+    // None past 15, a nibble pulled out of an opcode always fits.
+    pub fn from_nibble(n: u8) -> Option<VReg> {
+        VReg::ALL.get(n as usize).copied()
+    }
+
+    // Where it lives in the register file.
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl fmt::Display for VReg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "V{:X}", self.index())
+    }
+}
+
+// A raw two byte opcode. CHIP-8 stores opcodes big-endian, the first byte in
+// memory is the high one, so 0x1234 reads as nibbles 1, 2, 3, 4 left to right.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Opcode(pub u16);
+
+impl Opcode {
+    // The opcode at `memory[addr]`, `memory[addr + 1]`.
+    pub fn from_bytes(hi: u8, lo: u8) -> Opcode {
+        Opcode(u16::from_be_bytes([hi, lo]))
+    }
+
+    // First nibble, which picks the instruction group.
+    pub fn family(self) -> u8 {
+        (self.0 >> 12) as u8
+    }
+
+    // Second nibble, usually the VX register.
+    pub fn x(self) -> u8 {
+        ((self.0 & 0x0F00) >> 8) as u8
+    }
+
+    // Third nibble, usually the VY register.
+    pub fn y(self) -> u8 {
+        ((self.0 & 0x00F0) >> 4) as u8
+    }
+
+    // The second nibble as a register.
+    pub fn vx(self) -> VReg {
+        VReg::ALL[self.x() as usize]
+    }
+
+    // The third nibble as a register.
+    pub fn vy(self) -> VReg {
+        VReg::ALL[self.y() as usize]
+    }
+
+    // Last nibble.
+    pub fn n(self) -> u8 {
+        (self.0 & 0x000F) as u8
+    }
+
+    // Last byte.
+    pub fn nn(self) -> u8 {
+        (self.0 & 0x00FF) as u8
+    }
+
+    // Last three nibbles, an address.
+    pub fn nnn(self) -> u16 {
+        self.0 & 0x0FFF
+    }
+}
+
+// A decoded opcode. Register operands are the register numbers (X, Y), not
+// their contents, so decoding never needs the machine state.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instruction {
+    ScrollUp(u8), // 00DN (XO-CHIP)
+    ClearScreen, // 00E0
+    Return, // 00EE
+    Jump(u16), // 1NNN
+    Call(u16), // 2NNN
+    SkipEqByte(VReg, u8), // 3XNN
+    SkipNeByte(VReg, u8), // 4XNN
+    SkipEqReg(VReg, VReg), // 5XY0
+    SaveRange(VReg, VReg), // 5XY2 (XO-CHIP)
+    LoadRange(VReg, VReg), // 5XY3 (XO-CHIP)
+    LoadByte(VReg, u8), // 6XNN
+    AddByte(VReg, u8), // 7XNN
+    Move(VReg, VReg), // 8XY0
+    Or(VReg, VReg), // 8XY1
+    And(VReg, VReg), // 8XY2
+    Xor(VReg, VReg), // 8XY3
+    AddReg(VReg, VReg), // 8XY4
+    Sub(VReg, VReg), // 8XY5
+    ShiftRight(VReg, VReg), // 8XY6
+    SubReverse(VReg, VReg), // 8XY7
+    ShiftLeft(VReg, VReg), // 8XYE
+    SkipNeReg(VReg, VReg), // 9XY0
+    LoadIndex(u16), // ANNN
+    JumpOffset(u16), // BNNN
+    Random(VReg, u8), // CXNN
+    Draw(VReg, VReg, u8), // DXYN
+    SkipKey(VReg), // EX9E
+    SkipNotKey(VReg), // EXA1
+    LoadAudio, // F002 (XO-CHIP)
+    StoreBcd(VReg), // FX33
+    SetDelay(VReg), // FX15
+    StoreRegs(VReg), // FX55
+    LoadRegs(VReg), // FX65
+    GetDelay(VReg), // FX07
+    SetSound(VReg), // FX18
+    LoadFont(VReg), // FX29
+    WaitKey(VReg), // FX0A
+    SetPitch(VReg), // FX3A (XO-CHIP)
+    AddIndex(VReg), // FX1E
+    Unknown(u16),
+}
+
+// Work out which instruction an opcode is. Like the interpreter always has,
+// only the nibbles needed to tell instructions apart are looked at.
+pub fn decode(opcode: u16) -> Instruction {
+    let op = Opcode(opcode);
+    let (x, y, n, nn, nnn) = (op.vx(), op.vy(), op.n(), op.nn(), op.nnn());
+
+    match op.family() {
+        0x0 => match (op.y(), n) {
+            (0xD, _) => Instruction::ScrollUp(n),
+            (_, 0x0) => Instruction::ClearScreen,
+            (_, 0xE) => Instruction::Return,
+            _ => Instruction::Unknown(opcode),
+        },
+        0x1 => Instruction::Jump(nnn),
+        0x2 => Instruction::Call(nnn),
+        0x3 => Instruction::SkipEqByte(x, nn),
+        0x4 => Instruction::SkipNeByte(x, nn),
+        0x5 => match n {
+            0x0 => Instruction::SkipEqReg(x, y),
+            0x2 => Instruction::SaveRange(x, y),
+            0x3 => Instruction::LoadRange(x, y),
+            _ => Instruction::Unknown(opcode),
+        },
+        0x6 => Instruction::LoadByte(x, nn),
+        0x7 => Instruction::AddByte(x, nn),
+        0x8 => match n {
+            0x0 => Instruction::Move(x, y),
+            0x1 => Instruction::Or(x, y),
+            0x2 => Instruction::And(x, y),
+            0x3 => Instruction::Xor(x, y),
+            0x4 => Instruction::AddReg(x, y),
+            0x5 => Instruction::Sub(x, y),
+            0x6 => Instruction::ShiftRight(x, y),
+            0x7 => Instruction::SubReverse(x, y),
+            0xE => Instruction::ShiftLeft(x, y),
+            _ => Instruction::Unknown(opcode),
+        },
+        0x9 => Instruction::SkipNeReg(x, y),
+        0xA => Instruction::LoadIndex(nnn),
+        0xB => Instruction::JumpOffset(nnn),
+        0xC => Instruction::Random(x, nn),
+        0xD => Instruction::Draw(x, y, n),
+        0xE => match n {
+            0xE => Instruction::SkipKey(x),
+            0x1 => Instruction::SkipNotKey(x),
+            _ => Instruction::Unknown(opcode),
+        },
+        0xF => match n {
+            0x2 => Instruction::LoadAudio,
+            0x3 => Instruction::StoreBcd(x),
+            0x5 => match op.y() {
+                0x1 => Instruction::SetDelay(x),
+                0x5 => Instruction::StoreRegs(x),
+                0x6 => Instruction::LoadRegs(x),
+                _ => Instruction::Unknown(opcode),
+            },
+            0x7 => Instruction::GetDelay(x),
+            0x8 => Instruction::SetSound(x),
+            0x9 => Instruction::LoadFont(x),
+            0xA => match op.y() {
+                0x3 => Instruction::SetPitch(x),
+                _ => Instruction::WaitKey(x),
+            },
+            0xE => Instruction::AddIndex(x),
+            _ => Instruction::Unknown(opcode),
+        },
+        _ => Instruction::Unknown(opcode),
+    }
+}
+
+impl Instruction {
+    // The opcode this is normally written as. Opcodes `decode` only looked at
+    // some of the nibbles of come back with the rest filled in, so comparing
+    // with the original tells a canonical opcode from a lookalike.
+    pub fn encode(self) -> u16 {
+        let xy = |x: VReg, y: VReg| (x.index() as u16) << 8 | (y.index() as u16) << 4;
+        let xnn = |x: VReg, nn: u8| (x.index() as u16) << 8 | nn as u16;
+
+        match self {
+            Instruction::ScrollUp(n) => 0x00D0 | n as u16,
+            Instruction::ClearScreen => 0x00E0,
+            Instruction::Return => 0x00EE,
+            Instruction::Jump(nnn) => 0x1000 | nnn,
+            Instruction::Call(nnn) => 0x2000 | nnn,
+            Instruction::SkipEqByte(x, nn) => 0x3000 | xnn(x, nn),
+            Instruction::SkipNeByte(x, nn) => 0x4000 | xnn(x, nn),
+            Instruction::SkipEqReg(x, y) => 0x5000 | xy(x, y),
+            Instruction::SaveRange(x, y) => 0x5002 | xy(x, y),
+            Instruction::LoadRange(x, y) => 0x5003 | xy(x, y),
+            Instruction::LoadByte(x, nn) => 0x6000 | xnn(x, nn),
+            Instruction::AddByte(x, nn) => 0x7000 | xnn(x, nn),
+            Instruction::Move(x, y) => 0x8000 | xy(x, y),
+            Instruction::Or(x, y) => 0x8001 | xy(x, y),
+            Instruction::And(x, y) => 0x8002 | xy(x, y),
+            Instruction::Xor(x, y) => 0x8003 | xy(x, y),
+            Instruction::AddReg(x, y) => 0x8004 | xy(x, y),
+            Instruction::Sub(x, y) => 0x8005 | xy(x, y),
+            Instruction::ShiftRight(x, y) => 0x8006 | xy(x, y),
+            Instruction::SubReverse(x, y) => 0x8007 | xy(x, y),
+            Instruction::ShiftLeft(x, y) => 0x800E | xy(x, y),
+            Instruction::SkipNeReg(x, y) => 0x9000 | xy(x, y),
+            Instruction::LoadIndex(nnn) => 0xA000 | nnn,
+            Instruction::JumpOffset(nnn) => 0xB000 | nnn,
+            Instruction::Random(x, nn) => 0xC000 | xnn(x, nn),
+            Instruction::Draw(x, y, n) => 0xD000 | xy(x, y) | n as u16,
+            Instruction::SkipKey(x) => 0xE09E | xnn(x, 0),
+            Instruction::SkipNotKey(x) => 0xE0A1 | xnn(x, 0),
+            Instruction::LoadAudio => 0xF002,
+            Instruction::StoreBcd(x) => 0xF033 | xnn(x, 0),
+            Instruction::SetDelay(x) => 0xF015 | xnn(x, 0),
+            Instruction::StoreRegs(x) => 0xF055 | xnn(x, 0),
+            Instruction::LoadRegs(x) => 0xF065 | xnn(x, 0),
+            Instruction::GetDelay(x) => 0xF007 | xnn(x, 0),
+            Instruction::SetSound(x) => 0xF018 | xnn(x, 0),
+            Instruction::LoadFont(x) => 0xF029 | xnn(x, 0),
+            Instruction::WaitKey(x) => 0xF00A | xnn(x, 0),
+            Instruction::SetPitch(x) => 0xF03A | xnn(x, 0),
+            Instruction::AddIndex(x) => 0xF01E | xnn(x, 0),
+            Instruction::Unknown(opcode) => opcode,
+        }
+    }
+}
+
+// The usual CHIP-8 assembly, numbers in hex with a `#` except the sprite
+// height and scroll amount. Anything unknown is shown as the two data bytes.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::ScrollUp(n) => write!(f, "SCU {}", n),
+            Instruction::ClearScreen => write!(f, "CLS"),
+            Instruction::Return => write!(f, "RET"),
+            Instruction::Jump(nnn) => write!(f, "JP #{:03X}", nnn),
+            Instruction::Call(nnn) => write!(f, "CALL #{:03X}", nnn),
+            Instruction::SkipEqByte(x, nn) => write!(f, "SE {}, #{:02X}", x, nn),
+            Instruction::SkipNeByte(x, nn) => write!(f, "SNE {}, #{:02X}", x, nn),
+            Instruction::SkipEqReg(x, y) => write!(f, "SE {}, {}", x, y),
+            Instruction::SaveRange(x, y) => write!(f, "SAVE {} - {}", x, y),
+            Instruction::LoadRange(x, y) => write!(f, "LOAD {} - {}", x, y),
+            Instruction::LoadByte(x, nn) => write!(f, "LD {}, #{:02X}", x, nn),
+            Instruction::AddByte(x, nn) => write!(f, "ADD {}, #{:02X}", x, nn),
+            Instruction::Move(x, y) => write!(f, "LD {}, {}", x, y),
+            Instruction::Or(x, y) => write!(f, "OR {}, {}", x, y),
+            Instruction::And(x, y) => write!(f, "AND {}, {}", x, y),
+            Instruction::Xor(x, y) => write!(f, "XOR {}, {}", x, y),
+            Instruction::AddReg(x, y) => write!(f, "ADD {}, {}", x, y),
+            Instruction::Sub(x, y) => write!(f, "SUB {}, {}", x, y),
+            Instruction::ShiftRight(x, y) => write!(f, "SHR {}, {}", x, y),
+            Instruction::SubReverse(x, y) => write!(f, "SUBN {}, {}", x, y),
+            Instruction::ShiftLeft(x, y) => write!(f, "SHL {}, {}", x, y),
+            Instruction::SkipNeReg(x, y) => write!(f, "SNE {}, {}", x, y),
+            Instruction::LoadIndex(nnn) => write!(f, "LD I, #{:03X}", nnn),
+            Instruction::JumpOffset(nnn) => write!(f, "JP V0, #{:03X}", nnn),
+            Instruction::Random(x, nn) => write!(f, "RND {}, #{:02X}", x, nn),
+            Instruction::Draw(x, y, n) => write!(f, "DRW {}, {}, {}", x, y, n),
+            Instruction::SkipKey(x) => write!(f, "SKP {}", x),
+            Instruction::SkipNotKey(x) => write!(f, "SKNP {}", x),
+            Instruction::LoadAudio => write!(f, "AUDIO"),
+            Instruction::StoreBcd(x) => write!(f, "LD B, {}", x),
+            Instruction::SetDelay(x) => write!(f, "LD DT, {}", x),
+            Instruction::StoreRegs(x) => write!(f, "LD [I], {}", x),
+            Instruction::LoadRegs(x) => write!(f, "LD {}, [I]", x),
+            Instruction::GetDelay(x) => write!(f, "LD {}, DT", x),
+            Instruction::SetSound(x) => write!(f, "LD ST, {}", x),
+            Instruction::LoadFont(x) => write!(f, "LD F, {}", x),
+            Instruction::WaitKey(x) => write!(f, "LD {}, K", x),
+            Instruction::SetPitch(x) => write!(f, "LD PITCH, {}", x),
+            Instruction::AddIndex(x) => write!(f, "ADD I, {}", x),
+            Instruction::Unknown(opcode) => write!(f, "DB #{:02X}, #{:02X}", opcode >> 8, opcode & 0xFF),
+        }
+    }
+}
+
+// An opcode from one of the CHIP-8 family specs and whether this build runs it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OpcodeSupport {
+    pub pattern: &'static str, // As written in the spec, like "8XY4".
+    pub platform: &'static str, // "chip-8", "schip" or "xo-chip", matching the preset names.
+    pub implemented: bool,
+}
+
+const fn support(pattern: &'static str, platform: &'static str, implemented: bool) -> OpcodeSupport {
+    OpcodeSupport { pattern, platform, implemented }
+}
+
+// Every opcode the specs define, kept up to date as instructions are added.
+pub const OPCODE_SUPPORT: &[OpcodeSupport] = &[
+    support("0NNN", "chip-8", false),
+    support("00E0", "chip-8", true),
+    support("00EE", "chip-8", true),
+    support("1NNN", "chip-8", true),
+    support("2NNN", "chip-8", true),
+    support("3XNN", "chip-8", true),
+    support("4XNN", "chip-8", true),
+    support("5XY0", "chip-8", true),
+    support("6XNN", "chip-8", true),
+    support("7XNN", "chip-8", true),
+    support("8XY0", "chip-8", true),
+    support("8XY1", "chip-8", true),
+    support("8XY2", "chip-8", true),
+    support("8XY3", "chip-8", true),
+    support("8XY4", "chip-8", true),
+    support("8XY5", "chip-8", true),
+    support("8XY6", "chip-8", true),
+    support("8XY7", "chip-8", true),
+    support("8XYE", "chip-8", true),
+    support("9XY0", "chip-8", true),
+    support("ANNN", "chip-8", true),
+    support("BNNN", "chip-8", true),
+    support("CXNN", "chip-8", true),
+    support("DXYN", "chip-8", true),
+    support("EX9E", "chip-8", true),
+    support("EXA1", "chip-8", true),
+    support("FX07", "chip-8", true),
+    support("FX0A", "chip-8", true),
+    support("FX15", "chip-8", true),
+    support("FX18", "chip-8", true),
+    support("FX1E", "chip-8", true),
+    support("FX29", "chip-8", true),
+    support("FX33", "chip-8", true),
+    support("FX55", "chip-8", true),
+    support("FX65", "chip-8", true),
+    support("00CN", "schip", false),
+    support("00FB", "schip", false),
+    support("00FC", "schip", false),
+    support("00FD", "schip", false),
+    support("00FE", "schip", false),
+    support("00FF", "schip", false),
+    support("DXY0", "schip", true),
+    support("FX30", "schip", false),
+    support("FX75", "schip", false),
+    support("FX85", "schip", false),
+    support("00DN", "xo-chip", true),
+    support("5XY2", "xo-chip", true),
+    support("5XY3", "xo-chip", true),
+    support("F000", "xo-chip", false),
+    support("FN01", "xo-chip", false),
+    support("F002", "xo-chip", true),
+    support("FX3A", "xo-chip", true),
+];
+
+// The opcodes of `platform` this build doesn't run yet.
+pub fn unimplemented(platform: &str) -> Vec<OpcodeSupport> {
+    OPCODE_SUPPORT.iter()
+        .filter(|op| op.platform == platform && !op.implemented)
+        .copied()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opcode_accessors() {
+        let op = Opcode(0xD12A);
+        assert_eq!(op.family(), 0xD);
+        assert_eq!(op.x(), 0x1);
+        assert_eq!(op.y(), 0x2);
+        assert_eq!(op.n(), 0xA);
+        assert_eq!(op.nn(), 0x2A);
+        assert_eq!(op.nnn(), 0x12A);
+        assert_eq!(op.vx(), VReg::V1);
+        assert_eq!(op.vy(), VReg::V2);
+
+        let op = Opcode(0x8FE6);
+        assert_eq!((op.family(), op.x(), op.y(), op.n()), (0x8, 0xF, 0xE, 0x6));
+        assert_eq!((op.vx(), op.vy()), (VReg::VF, VReg::VE));
+    }
+
+    #[test]
+    fn core_set_is_implemented() {
+        let missing: Vec<_> = unimplemented("chip-8").iter().map(|op| op.pattern).collect();
+        assert_eq!(missing, vec!["0NNN"]);
+
+        // And each one listed as implemented decodes to something, operands filled in with 1s.
+        for op in OPCODE_SUPPORT.iter().filter(|op| op.platform == "chip-8" && op.implemented) {
+            let opcode = u16::from_str_radix(&op.pattern.replace(|c| "XYN".contains(c), "1"), 16).unwrap();
+            assert!(!matches!(decode(opcode), Instruction::Unknown(_)), "{} decodes as unknown", op.pattern);
+        }
+        assert_eq!(OPCODE_SUPPORT.iter().filter(|op| op.platform == "chip-8").count(), 35);
+    }
+
+    #[test]
+    fn vreg_nibbles_and_accessors() {
+        for n in 0..16 {
+            assert_eq!(VReg::from_nibble(n), Some(VReg::ALL[n as usize]));
+        }
+        assert_eq!(VReg::from_nibble(16), None);
+        assert_eq!(VReg::from_nibble(0xFF), None);
+
+        let mut c8 = crate::emu::Chip8::with_seed(0);
+        for (i, &reg) in VReg::ALL.iter().enumerate() {
+            c8.set_reg(reg, 0x10 + i as u8);
+        }
+        for (i, &reg) in VReg::ALL.iter().enumerate() {
+            assert_eq!(c8.reg(reg), 0x10 + i as u8);
+            assert_eq!(c8.registers()[i], 0x10 + i as u8);
+        }
+    }
+
+    #[test]
+    fn display_for_each_instruction() {
+        let cases = [
+            (0x00D3, "SCU 3"), (0x00E0, "CLS"), (0x00EE, "RET"),
+            (0x1234, "JP #234"), (0x2ABC, "CALL #ABC"),
+            (0x3A0A, "SE VA, #0A"), (0x4B10, "SNE VB, #10"), (0x5120, "SE V1, V2"),
+            (0x5122, "SAVE V1 - V2"), (0x5123, "LOAD V1 - V2"),
+            (0x630A, "LD V3, #0A"), (0x7FFF, "ADD VF, #FF"),
+            (0x8120, "LD V1, V2"), (0x8121, "OR V1, V2"), (0x8122, "AND V1, V2"), (0x8123, "XOR V1, V2"),
+            (0x8124, "ADD V1, V2"), (0x8125, "SUB V1, V2"), (0x8126, "SHR V1, V2"), (0x8127, "SUBN V1, V2"),
+            (0x812E, "SHL V1, V2"), (0x9120, "SNE V1, V2"),
+            (0xA2F0, "LD I, #2F0"), (0xB300, "JP V0, #300"), (0xC70F, "RND V7, #0F"),
+            (0xD125, "DRW V1, V2, 5"), (0xE49E, "SKP V4"), (0xE4A1, "SKNP V4"),
+            (0xF002, "AUDIO"), (0xF533, "LD B, V5"), (0xF515, "LD DT, V5"),
+            (0xF555, "LD [I], V5"), (0xF565, "LD V5, [I]"), (0xF507, "LD V5, DT"),
+            (0xF518, "LD ST, V5"), (0xF529, "LD F, V5"), (0xF50A, "LD V5, K"),
+            (0xF53A, "LD PITCH, V5"), (0xF51E, "ADD I, V5"),
+            (0x8128, "DB #81, #28"),
+        ];
+
+        for &(opcode, text) in cases.iter() {
+            assert_eq!(decode(opcode).to_string(), text, "{:04X}", opcode);
+        }
+        assert_eq!(cases.len(), 40);
+    }
+
+    #[test]
+    fn opcode_from_bytes_is_big_endian() {
+        assert_eq!(Opcode::from_bytes(0x12, 0x34), Opcode(0x1234));
+        assert_eq!(Opcode::from_bytes(0xA2, 0xF0).nnn(), 0x2F0);
+    }
+}
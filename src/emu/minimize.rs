@@ -0,0 +1,107 @@
+// Shrinking a ROM that crashes the emulator, say one the fuzzer found, down
+// to the few instructions it takes. Instructions are taken out where the crash
+// survives it, and blanked to 0000 (skipped as unknown) where moving the rest
+// up would lose it.
+
+// The smallest program found that `fails` still holds for, `fails(program)`
+// has to be true to start with and give the same answer every time. An odd
+// last byte is treated as the instruction it forms with the zero after it.
+pub fn minimize<F: FnMut(&[u8]) -> bool>(program: &[u8], mut fails: F) -> Vec<u8> {
+    let mut words: Vec<u16> = program.chunks(2)
+        .map(|pair| (pair[0] as u16) << 8 | pair.get(1).copied().unwrap_or(0) as u16)
+        .collect();
+
+    loop {
+        let before = words.clone();
+
+        // Take out runs of instructions, long ones first.
+        let mut run = (words.len() / 2).max(1);
+        while run > 0 {
+            let mut start = 0;
+            while start < words.len() {
+                let end = (start + run).min(words.len());
+                let candidate: Vec<u16> = words[..start].iter().chain(words[end..].iter()).copied().collect();
+
+                if !candidate.is_empty() && fails(&to_bytes(&candidate)) {
+                    words = candidate;
+                } else {
+                    start += run;
+                }
+            }
+            run /= 2;
+        }
+
+        // Blank the ones that have to stay where they are for the rest to work.
+        for i in 0..words.len() {
+            if words[i] != 0 {
+                let mut candidate = words.clone();
+                candidate[i] = 0;
+                if fails(&to_bytes(&candidate)) {
+                    words = candidate;
+                }
+            }
+        }
+
+        // Memory after the ROM is zeroed anyway.
+        while words.len() > 1 && words.last() == Some(&0) {
+            words.pop();
+        }
+
+        if words == before {
+            return to_bytes(&words);
+        }
+    }
+}
+
+// A program as a `chip8_program!` invocation, to paste into a bug report.
+pub fn program_snippet(program: &[u8]) -> String {
+    let opcodes: Vec<String> = program.chunks(2)
+        .map(|pair| format!("0x{:02X}{:02X}", pair[0], pair.get(1).copied().unwrap_or(0)))
+        .collect();
+
+    format!("chip8_program![{}]", opcodes.join(", "))
+}
+
+fn to_bytes(words: &[u16]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_be_bytes().to_vec()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emu::{Chip8, Rom};
+    use std::convert::TryFrom;
+    use std::panic;
+
+    // Whether running `program` for a while panics, like the fuzzer checks.
+    fn crashes(program: &[u8]) -> bool {
+        let rom = match Rom::try_from(program) {
+            Ok(rom) => rom,
+            Err(_) => return false,
+        };
+
+        panic::catch_unwind(|| {
+            let mut c8 = Chip8::with_seed(0);
+            c8.load_rom(&rom);
+            for _ in 0..100 {
+                c8.cycle();
+            }
+        }).is_err()
+    }
+
+    #[test]
+    fn reduces_to_the_crashing_instruction() {
+        // Only the return with nothing to return to matters.
+        let program = crate::chip8_program![0x6005, 0x7003, 0xA300, 0x6110, 0xD015, 0x00EE, 0x1200];
+        assert!(crashes(&program));
+
+        let minimized = minimize(&program, crashes);
+        assert_eq!(minimized, crate::chip8_program![0x00EE]);
+        assert_eq!(program_snippet(&minimized), "chip8_program![0x00EE]");
+    }
+
+    #[test]
+    fn snippet_pads_an_odd_byte() {
+        assert_eq!(program_snippet(&[0x12, 0x34, 0x05]), "chip8_program![0x1234, 0x0500]");
+    }
+}
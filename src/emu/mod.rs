@@ -1,5 +1,35 @@
+#[allow(clippy::module_inception)]
 pub mod emu;
+pub mod audio;
+pub mod audit;
+pub mod clock;
+pub mod disasm;
+pub mod error;
+pub mod events;
+pub mod instruction;
+pub mod minimize;
+pub mod peripheral;
+pub mod profile;
+pub mod quirks;
+pub mod replay;
+pub mod repro;
+pub mod rom;
+pub mod script;
+pub mod state;
+pub mod testing;
+pub mod trace;
+pub mod warnings;
+pub mod worker;
 
-extern crate rand;
-
-pub use emu::Chip8;
+pub use emu::{decode_gfx_rle, film_strip, frame_to_ppm, Chip8, Frame, FrameSnapshot, KeyCallback, KeyRepeat, KeySampling, RunResult, DEFAULT_IPF, DEFAULT_IPS, MAX_ROM_SIZE, MAX_WARNINGS, PROGRAM_START, STANDARD_KEY_ORDER, TIMER_HZ, TRACE_LEN};
+pub use audit::FlagAccess;
+pub use error::Chip8Error;
+pub use events::{Chip8Event, Observer};
+pub use instruction::{Instruction, Opcode, OpcodeSupport, VReg, OPCODE_SUPPORT};
+pub use profile::Profile;
+pub use peripheral::Peripheral;
+pub use quirks::{Quirks, StackPolicy};
+pub use rom::{Rom, RomError};
+pub use state::SaveState;
+pub use trace::TraceRecord;
+pub use warnings::Warning;
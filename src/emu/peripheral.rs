@@ -0,0 +1,10 @@
+// A virtual device mapped over part of memory, see `Chip8::attach_peripheral`.
+// Only data accesses made by instructions (FX33, FX55, FX65, DXYN, ...) go
+// through it, opcodes are always fetched from RAM.
+pub trait Peripheral {
+    // Return a value to hide what RAM holds at `addr`, or None to read RAM.
+    fn read(&mut self, addr: u16) -> Option<u8>;
+
+    // Return true if the device took the write, false to store it in RAM.
+    fn write(&mut self, addr: u16, value: u8) -> bool;
+}
@@ -0,0 +1,82 @@
+use std::fmt;
+use std::time::Duration;
+
+use super::instruction::Opcode;
+
+// Opcode families by their first nibble.
+pub const FAMILIES: [&str; 16] = [
+    "0NNN", "1NNN", "2NNN", "3XNN", "4XNN", "5XYN", "6XNN", "7XNN",
+    "8XYN", "9XY0", "ANNN", "BNNN", "CXNN", "DXYN", "EXNN", "FXNN"
+];
+
+// How often each opcode family ran and how long executing it took.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+    pub counts: [u64; 16],
+    pub time: [Duration; 16],
+}
+
+impl Profile {
+    pub fn record(&mut self, opcode: u16, elapsed: Duration) {
+        let family = Opcode(opcode).family() as usize;
+        self.counts[family] += 1;
+        self.time[family] += elapsed;
+    }
+
+    pub fn total_time(&self) -> Duration {
+        self.time.iter().sum()
+    }
+
+    // The families that ran as CSV, in opcode order, times in nanoseconds.
+    pub fn to_csv(&self) -> String {
+        let mut csv = "family,count,total_ns,avg_ns\n".to_string();
+
+        for i in (0..16).filter(|&i| self.counts[i] > 0) {
+            let nanos = self.time[i].as_nanos();
+            csv.push_str(&format!("{},{},{},{}\n", FAMILIES[i], self.counts[i], nanos, nanos / self.counts[i] as u128));
+        }
+
+        csv
+    }
+}
+
+// A table of the families that ran, most time consuming first.
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.total_time().as_secs_f64().max(f64::MIN_POSITIVE);
+
+        let mut families: Vec<usize> = (0..16).filter(|&i| self.counts[i] > 0).collect();
+        families.sort_by(|&a, &b| self.time[b].cmp(&self.time[a]));
+
+        writeln!(f, "{:<6} {:>12} {:>12} {:>10} {:>6}", "family", "count", "time (us)", "ns/op", "time%")?;
+        for i in families {
+            let nanos = self.time[i].as_nanos();
+            writeln!(f, "{:<6} {:>12} {:>12} {:>10} {:>5.1}%",
+                FAMILIES[i],
+                self.counts[i],
+                nanos / 1000,
+                nanos / self.counts[i] as u128,
+                self.time[i].as_secs_f64() / total * 100.0
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_has_a_row_per_family_run() {
+        let mut profile = Profile::default();
+        assert_eq!(profile.to_csv(), "family,count,total_ns,avg_ns\n");
+
+        profile.record(0xD005, Duration::from_nanos(300));
+        profile.record(0x6000, Duration::from_nanos(40));
+        profile.record(0xD125, Duration::from_nanos(100));
+
+        assert_eq!(profile.to_csv(), "family,count,total_ns,avg_ns\n6XNN,1,40,40\nDXYN,2,400,200\n");
+    }
+}
@@ -0,0 +1,221 @@
+use super::emu::DEFAULT_IPS;
+
+// What a 2NNN does with the stack already full, and a 00EE with it empty,
+// see `Quirks::stack`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum StackPolicy {
+    #[default]
+    Error, // Panic either way, which frontends turn into a crash report.
+    Wrap, // Start again from the bottom, overwriting the oldest return address. A 00EE wraps the other way, to the top slot.
+    Grow, // Make room, nesting as deep as memory allows. There's still nothing to return to from empty, so that panics.
+}
+
+impl StackPolicy {
+    pub fn from_name(name: &str) -> Option<StackPolicy> {
+        match name {
+            "error" => Some(StackPolicy::Error),
+            "wrap" => Some(StackPolicy::Wrap),
+            "grow" => Some(StackPolicy::Grow),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StackPolicy::Error => "error",
+            StackPolicy::Wrap => "wrap",
+            StackPolicy::Grow => "grow",
+        }
+    }
+}
+
+// Behaviors that differ between CHIP-8 interpreters, ROMs written for one
+// may rely on them.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Quirks {
+    pub wrap_x: bool, // Wrap sprites around the left and right edges instead of clipping them.
+    pub wrap_y: bool, // Same for the top and bottom edges.
+    pub xo_chip: bool, // Enable the XO-CHIP extensions to the instruction set.
+    pub schip: bool, // Enable the SUPER-CHIP extensions, so far only DXY0 16x16 sprites.
+    pub shift_vy: bool, // 8XY6/8XYE shift VY into VX like the original COSMAC VIP.
+    pub jump_vx: bool, // BXNN jumps to XNN + VX like CHIP-48, instead of NNN + V0.
+    pub load_store_increment: bool, // FX55/FX65 leave I pointing past the last register.
+    pub vf_reset: bool, // 8XY1/8XY2/8XY3 clear VF afterwards like the COSMAC VIP.
+    pub display_wait: bool, // DXYN waits for the next vblank, limiting draws to one per frame.
+    pub clamp_index: bool, // FX1E stops I at 0xFFF, instead of letting accesses through it wrap around memory.
+    pub half_pixel_scroll: bool, // Low-res scrolls move half as far, like SCHIP 1.1 scrolling by high-res pixels.
+    pub stack: StackPolicy, // What happens on the 17th nested call, and on a 00EE with nothing to return to.
+}
+
+impl Quirks {
+    // Each behavior by name with its setting, in the order `summary` lists them.
+    pub fn settings(&self) -> [(&'static str, &'static str); 11] {
+        [
+            ("sprites", match (self.wrap_x, self.wrap_y) {
+                (true, true) => "wrap",
+                (true, false) => "wrap x",
+                (false, true) => "wrap y",
+                (false, false) => "clip",
+            }),
+            ("shift", if self.shift_vy { "VY" } else { "VX" }),
+            ("jump", if self.jump_vx { "VX" } else { "V0" }),
+            ("load/store", if self.load_store_increment { "increment I" } else { "keep I" }),
+            ("vf reset", if self.vf_reset { "on" } else { "off" }),
+            ("display wait", if self.display_wait { "on" } else { "off" }),
+            ("index", if self.clamp_index { "clamp" } else { "wrap" }),
+            ("scroll", if self.half_pixel_scroll { "half" } else { "full" }),
+            ("stack", self.stack.name()),
+            ("schip", if self.schip { "on" } else { "off" }),
+            ("xo-chip", if self.xo_chip { "on" } else { "off" }),
+        ]
+    }
+
+    // One line description of the active behaviors, for banners and overlays.
+    pub fn summary(&self) -> String {
+        let settings: Vec<_> = self.settings().iter().map(|(name, value)| format!("{}: {}", name, value)).collect();
+        settings.join(", ")
+    }
+
+    // The behaviors set differently in `other`, as (name, ours, theirs).
+    pub fn differences(&self, other: &Quirks) -> Vec<(&'static str, &'static str, &'static str)> {
+        self.settings().iter().zip(other.settings().iter())
+            .filter(|(ours, theirs)| ours.1 != theirs.1)
+            .map(|(&(name, ours), &(_, theirs))| (name, ours, theirs))
+            .collect()
+    }
+
+    pub fn wraps_sprites(&self) -> bool {
+        self.wrap_x && self.wrap_y
+    }
+
+    pub fn set_wrap_sprites(&mut self, wrap: bool) {
+        self.wrap_x = wrap;
+        self.wrap_y = wrap;
+    }
+
+    // Settings matching the well known interpreters, by name.
+    pub fn presets() -> Vec<(&'static str, Quirks)> {
+        vec![
+            ("default", Quirks::default()),
+            ("vip", Quirks {
+                shift_vy: true,
+                load_store_increment: true,
+                vf_reset: true,
+                display_wait: true,
+                ..Quirks::default()
+            }),
+            ("schip", Quirks {
+                schip: true,
+                jump_vx: true,
+                ..Quirks::default()
+            }),
+            ("xo-chip", Quirks {
+                xo_chip: true,
+                wrap_x: true,
+                wrap_y: true,
+                shift_vy: true,
+                load_store_increment: true,
+                ..Quirks::default()
+            }),
+        ]
+    }
+
+    pub fn preset(name: &str) -> Option<Quirks> {
+        Quirks::presets().into_iter().find(|&(preset, _)| preset == name).map(|(_, quirks)| quirks)
+    }
+
+    // A speed that suits ROMs written for a preset, in instructions per second.
+    // The VIP ran somewhere around 500-700, SCHIP and XO-CHIP ROMs expect the
+    // faster machines (or Octo's defaults of 30 and 1000 per frame) they were
+    // written on.
+    pub fn preset_ips(name: &str) -> Option<u32> {
+        match name {
+            "default" => Some(DEFAULT_IPS),
+            "vip" => Some(600),
+            "schip" => Some(1800),
+            "xo-chip" => Some(60000),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emu::{Chip8, Rom};
+    use std::convert::TryFrom;
+
+    // The quirks that change arithmetic results, each run on and off.
+    // New ones go here and in the `quirk` column of the rows they affect.
+    const ARITHMETIC_QUIRKS: [&str; 2] = ["vf_reset", "shift_vy"];
+
+    fn set_quirk(quirks: &mut Quirks, name: &str, on: bool) {
+        match name {
+            "vf_reset" => quirks.vf_reset = on,
+            "shift_vy" => quirks.shift_vy = on,
+            _ => unreachable!(),
+        }
+    }
+
+    struct Row {
+        opcode: u16, // Always with X=0 and Y=1.
+        vx: u8,
+        vy: u8,
+        quirk: Option<&'static str>, // The quirk the result depends on, if any.
+        off: (u8, u8), // VX and VF with that quirk off.
+        on: (u8, u8), // And with it on.
+    }
+
+    // Known good results from Timendus' chip8-test-suite (flags and quirks
+    // tests). VF starts at 0x55, so an untouched flag shows up.
+    const MATRIX: [Row; 9] = [
+        Row { opcode: 0x8011, vx: 0x0F, vy: 0xF0, quirk: Some("vf_reset"), off: (0xFF, 0x55), on: (0xFF, 0x00) },
+        Row { opcode: 0x8012, vx: 0x3C, vy: 0x0F, quirk: Some("vf_reset"), off: (0x0C, 0x55), on: (0x0C, 0x00) },
+        Row { opcode: 0x8013, vx: 0x3C, vy: 0x0F, quirk: Some("vf_reset"), off: (0x33, 0x55), on: (0x33, 0x00) },
+        Row { opcode: 0x8014, vx: 0xFF, vy: 0x01, quirk: None, off: (0x00, 0x01), on: (0x00, 0x01) },
+        Row { opcode: 0x8014, vx: 0x10, vy: 0x20, quirk: None, off: (0x30, 0x00), on: (0x30, 0x00) },
+        Row { opcode: 0x8015, vx: 0x01, vy: 0x02, quirk: None, off: (0xFF, 0x00), on: (0xFF, 0x00) },
+        Row { opcode: 0x8017, vx: 0x01, vy: 0x02, quirk: None, off: (0x01, 0x01), on: (0x01, 0x01) },
+        Row { opcode: 0x8016, vx: 0x02, vy: 0x81, quirk: Some("shift_vy"), off: (0x01, 0x00), on: (0x40, 0x01) },
+        Row { opcode: 0x801E, vx: 0x81, vy: 0x02, quirk: Some("shift_vy"), off: (0x02, 0x01), on: (0x04, 0x00) },
+    ];
+
+    #[test]
+    fn arithmetic_quirk_matrix() {
+        for row in MATRIX.iter() {
+            for combination in 0..1 << ARITHMETIC_QUIRKS.len() {
+                let mut quirks = Quirks::default();
+                for (i, name) in ARITHMETIC_QUIRKS.iter().enumerate() {
+                    set_quirk(&mut quirks, name, combination & 1 << i != 0);
+                }
+
+                let mut c8 = Chip8::with_seed(0);
+                c8.quirks = quirks;
+                c8.load_rom(&Rom::try_from(&row.opcode.to_be_bytes()[..]).unwrap());
+                c8.set_register(0, row.vx);
+                c8.set_register(1, row.vy);
+                c8.set_register(0xF, 0x55);
+                c8.cycle();
+
+                let quirk_on = row.quirk.is_some_and(|name| {
+                    combination & 1 << ARITHMETIC_QUIRKS.iter().position(|&quirk| quirk == name).unwrap() != 0
+                });
+                let expected = if quirk_on { row.on } else { row.off };
+                assert_eq!((c8.registers()[0], c8.registers()[0xF]), expected,
+                    "{:04X} with V0={:02X} V1={:02X} under {}", row.opcode, row.vx, row.vy, quirks.summary());
+            }
+        }
+    }
+
+    #[test]
+    fn summary_lists_settings() {
+        assert_eq!(Quirks::default().summary(),
+            "sprites: clip, shift: VX, jump: V0, load/store: keep I, vf reset: off, display wait: off, \
+             index: wrap, scroll: full, stack: error, schip: off, xo-chip: off");
+
+        let quirks = Quirks { wrap_x: true, shift_vy: true, stack: StackPolicy::Grow, xo_chip: true, ..Quirks::default() };
+        assert_eq!(quirks.summary(),
+            "sprites: wrap x, shift: VY, jump: V0, load/store: keep I, vf reset: off, display wait: off, \
+             index: wrap, scroll: full, stack: grow, schip: off, xo-chip: on");
+    }
+}
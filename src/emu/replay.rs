@@ -0,0 +1,358 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use super::state::SaveState;
+use super::{Chip8, STANDARD_KEY_ORDER};
+
+const MAGIC: &[u8; 4] = b"C8RC";
+const VERSION: u8 = 4;
+
+// How often (in cycles) a framebuffer hash is stored while recording.
+pub const DEFAULT_CHECKPOINT_INTERVAL: u64 = 60;
+
+// How often (in cycles) a full save state is stored to seek from.
+pub const DEFAULT_SNAPSHOT_INTERVAL: u64 = 1200;
+
+// A key state change, applied right before cycle number `cycle` runs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InputEvent {
+    pub cycle: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+// Framebuffer hash taken after `cycle` cycles have executed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Checkpoint {
+    pub cycle: u64,
+    pub hash: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recording {
+    pub seed: u64,
+    pub ipf: u32, // Instructions per frame, decides when the timers tick.
+    pub key_order: [u8; 16], // `Chip8::key_order` of the recorded machine, the inputs are keypad keys.
+    pub checkpoint_interval: u64,
+    pub snapshot_interval: u64,
+    pub inputs: Vec<InputEvent>,
+    pub checkpoints: Vec<Checkpoint>,
+    pub snapshots: Vec<SaveState>, // Oldest first, the first one is where recording started.
+}
+
+// First checkpoint at which a replay no longer matched the recording.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Divergence {
+    pub cycle: u64,
+    pub last_match: u64, // Cycle of the last checkpoint that still matched.
+    pub expected: u64,
+    pub found: u64,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Replay diverged at cycle {} (last match at cycle {}): expected framebuffer {:#018X}, found {:#018X}",
+            self.cycle,
+            self.last_match,
+            self.expected,
+            self.found
+        )
+    }
+}
+
+impl Recording {
+    pub fn new(seed: u64, ipf: u32, checkpoint_interval: u64) -> Recording {
+        Recording {
+            seed,
+            ipf: ipf.max(1),
+            key_order: STANDARD_KEY_ORDER,
+            checkpoint_interval: checkpoint_interval.max(1),
+            snapshot_interval: DEFAULT_SNAPSHOT_INTERVAL,
+            inputs: Vec::new(),
+            checkpoints: Vec::new(),
+            snapshots: Vec::new(),
+        }
+    }
+
+    // Start recording a machine that's ready to run, keeping its state so
+    // seeking never has to be told how it was set up.
+    pub fn start(c8: &Chip8, ipf: u32, checkpoint_interval: u64) -> Recording {
+        let mut recording = Recording::new(c8.seed(), ipf, checkpoint_interval);
+        recording.key_order = c8.key_order;
+        recording.snapshots.push(c8.save_state());
+        recording
+    }
+
+    pub fn record_key(&mut self, cycle: u64, key: usize, pressed: bool) {
+        self.inputs.push(InputEvent { cycle, key: key as u8, pressed });
+    }
+
+    // Call after every cycle or frame, stores a checkpoint whenever one is due.
+    pub fn checkpoint(&mut self, c8: &Chip8) {
+        let cycle = c8.cycles();
+        let last = self.checkpoints.last().map_or(0, |c| c.cycle);
+
+        if cycle >= last + self.checkpoint_interval {
+            self.checkpoints.push(Checkpoint { cycle, hash: c8.gfx_hash() });
+        }
+
+        let last_snapshot = self.snapshots.last().map_or(0, |s| s.cycles);
+        if !self.snapshots.is_empty() && cycle >= last_snapshot + self.snapshot_interval {
+            self.snapshots.push(c8.save_state());
+        }
+    }
+
+    // Number of cycles a replay has to run to cover every input and checkpoint.
+    pub fn length(&self) -> u64 {
+        let last_input = self.inputs.last().map_or(0, |i| i.cycle);
+        let last_checkpoint = self.checkpoints.last().map_or(0, |c| c.cycle);
+        last_input.max(last_checkpoint)
+    }
+
+    // Re-run the recorded session on a freshly loaded machine (see `Chip8::with_seed`),
+    // stopping at the first checkpoint whose framebuffer hash doesn't match.
+    pub fn replay(&self, c8: &mut Chip8) -> Result<(), Divergence> {
+        let mut inputs = self.inputs.iter().peekable();
+        let mut checkpoints = self.checkpoints.iter().peekable();
+        let mut last_match = 0;
+
+        while c8.cycles() < self.length() {
+            while let Some(input) = inputs.peek() {
+                if input.cycle > c8.cycles() {
+                    break;
+                }
+
+                if input.pressed {
+                    c8.key_pressed(input.key as usize);
+                } else {
+                    c8.key_released(input.key as usize);
+                }
+                inputs.next();
+            }
+
+            c8.cycle_in_frame(self.ipf);
+
+            if let Some(checkpoint) = checkpoints.peek() {
+                if checkpoint.cycle == c8.cycles() {
+                    let found = c8.gfx_hash();
+                    if found != checkpoint.hash {
+                        return Err(Divergence {
+                            cycle: checkpoint.cycle,
+                            last_match,
+                            expected: checkpoint.hash,
+                            found,
+                        });
+                    }
+
+                    last_match = checkpoint.cycle;
+                    checkpoints.next();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Bring `c8` to `target_cycle` of the recorded session. Runs forward from where
+    // the machine is when that's on the way, otherwise restores the latest
+    // snapshot before the target first. Without snapshots (see `start`) the
+    // machine has to be at or before the target already.
+    pub fn seek_to(&self, c8: &mut Chip8, target_cycle: u64) {
+        let snapshot = self.snapshots.iter().rev().find(|s| s.cycles <= target_cycle);
+
+        if let Some(snapshot) = snapshot {
+            if c8.cycles() > target_cycle || c8.cycles() < snapshot.cycles {
+                c8.load_state(snapshot);
+            }
+        }
+
+        // Snapshots are taken before the inputs of their cycle were applied.
+        let mut inputs = self.inputs[self.inputs.partition_point(|i| i.cycle < c8.cycles())..].iter().peekable();
+
+        while c8.cycles() < target_cycle && !c8.stopped() {
+            while let Some(input) = inputs.next_if(|input| input.cycle <= c8.cycles()) {
+                if input.pressed {
+                    c8.key_pressed(input.key as usize);
+                } else {
+                    c8.key_released(input.key as usize);
+                }
+            }
+
+            c8.cycle_in_frame(self.ipf);
+        }
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&[VERSION])?;
+        w.write_all(&self.seed.to_le_bytes())?;
+        w.write_all(&self.ipf.to_le_bytes())?;
+        w.write_all(&self.key_order)?;
+        w.write_all(&self.checkpoint_interval.to_le_bytes())?;
+        w.write_all(&self.snapshot_interval.to_le_bytes())?;
+
+        w.write_all(&(self.inputs.len() as u32).to_le_bytes())?;
+        for input in self.inputs.iter() {
+            w.write_all(&input.cycle.to_le_bytes())?;
+            w.write_all(&[input.key, input.pressed as u8])?;
+        }
+
+        w.write_all(&(self.checkpoints.len() as u32).to_le_bytes())?;
+        for checkpoint in self.checkpoints.iter() {
+            w.write_all(&checkpoint.cycle.to_le_bytes())?;
+            w.write_all(&checkpoint.hash.to_le_bytes())?;
+        }
+
+        w.write_all(&(self.snapshots.len() as u32).to_le_bytes())?;
+        for snapshot in self.snapshots.iter() {
+            snapshot.write_to(w)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<Recording> {
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC || read_u8(r)? != VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a chip8rs recording"));
+        }
+
+        let seed = read_u64(r)?;
+        let ipf = read_u32(r)?;
+        let mut key_order = [0; 16];
+        r.read_exact(&mut key_order)?;
+        let checkpoint_interval = read_u64(r)?;
+        let mut recording = Recording::new(seed, ipf, checkpoint_interval);
+        recording.key_order = key_order;
+        recording.snapshot_interval = read_u64(r)?.max(1);
+
+        for _ in 0..read_u32(r)? {
+            let cycle = read_u64(r)?;
+            let key = read_u8(r)?;
+            let pressed = read_u8(r)? != 0;
+
+            if key >= 16 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "recorded key out of range"));
+            }
+            recording.inputs.push(InputEvent { cycle, key, pressed });
+        }
+
+        for _ in 0..read_u32(r)? {
+            let cycle = read_u64(r)?;
+            let hash = read_u64(r)?;
+            recording.checkpoints.push(Checkpoint { cycle, hash });
+        }
+
+        for _ in 0..read_u32(r)? {
+            recording.snapshots.push(SaveState::read_from(r)?);
+        }
+
+        Ok(recording)
+    }
+}
+
+pub(super) fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+pub(super) fn read_u16<R: Read>(r: &mut R) -> io::Result<u16> {
+    let mut buf = [0; 2];
+    r.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+pub(super) fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+pub(super) fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+    use crate::emu::Rom;
+
+    // Draws the 0 glyph at x=62, where wrap_x changes what ends up on screen.
+    fn edge_draw(wrap_x: bool) -> Chip8 {
+        let mut c8 = Chip8::with_seed(1);
+        c8.quirks.wrap_x = wrap_x;
+        c8.load_rom(&Rom::try_from(&crate::chip8_program![0x603E, 0x6100, 0xD015, 0x1206][..]).unwrap());
+        c8
+    }
+
+    #[test]
+    fn altered_quirk_diverges() {
+        let mut c8 = edge_draw(false);
+        let mut recording = Recording::start(&c8, 10, 1);
+        for _ in 0..6 {
+            c8.cycle_in_frame(10);
+            recording.checkpoint(&c8);
+        }
+
+        assert_eq!(recording.replay(&mut edge_draw(false)), Ok(()));
+
+        let divergence = recording.replay(&mut edge_draw(true)).unwrap_err();
+        assert_eq!(divergence.cycle, 3);
+        assert_eq!(divergence.last_match, 2);
+        assert_eq!(divergence.expected, recording.checkpoints[2].hash);
+    }
+
+    #[test]
+    fn seek_matches_linear_run() {
+        // Counts in V0, adds to V2 while key 0 is held, and keeps a random number in V3.
+        let program = crate::chip8_program![0x7001, 0xE19E, 0x7210, 0xC3FF, 0xF015, 0x1200];
+        let fresh = || {
+            let mut c8 = Chip8::with_seed(3);
+            c8.load_rom(&Rom::try_from(&program[..]).unwrap());
+            c8
+        };
+
+        let mut c8 = fresh();
+        let mut recording = Recording::start(&c8, 4, 10);
+        recording.snapshot_interval = 8;
+        let mut linear = None;
+
+        while c8.cycles() < 60 {
+            match c8.cycles() {
+                5 => { c8.key_pressed(0); recording.record_key(5, 0, true); },
+                20 => { c8.key_released(0); recording.record_key(20, 0, false); },
+                37 => linear = Some(c8.save_state()),
+                _ => {},
+            }
+            c8.cycle_in_frame(4);
+            recording.checkpoint(&c8);
+        }
+        assert!(recording.snapshots.len() > 2);
+
+        // Forwards from the start, then back to an earlier snapshot.
+        let mut seeking = fresh();
+        recording.seek_to(&mut seeking, 50);
+        recording.seek_to(&mut seeking, 37);
+        assert_eq!(Some(seeking.save_state()), linear);
+    }
+
+    #[test]
+    fn key_order_round_trips() {
+        let mut c8 = Chip8::with_seed(7);
+        c8.key_order[0x1] = 0x4;
+        c8.key_order[0x4] = 0x1;
+
+        let mut recording = Recording::start(&c8, 10, 60);
+        recording.record_key(3, 0x4, true);
+        assert_eq!(recording.key_order, c8.key_order);
+
+        let mut bytes = Vec::new();
+        recording.write_to(&mut bytes).unwrap();
+        assert_eq!(Recording::read_from(&mut &bytes[..]).unwrap(), recording);
+    }
+}
@@ -0,0 +1,282 @@
+// A small reproduction of a session for bug reports: the ROM, seed and
+// quirks it started from plus the keys pressed, instead of whole save states.
+// Loading one replays the inputs on a fresh machine to get back to the end.
+
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+use super::emu::{Chip8, MAX_ROM_SIZE};
+use super::quirks::{Quirks, StackPolicy};
+use super::replay::{read_u32, read_u64, read_u8, InputEvent, Recording};
+use super::rom::Rom;
+
+const MAGIC: &[u8; 4] = b"C8RP";
+const VERSION: u8 = 4;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Repro {
+    pub rom: Vec<u8>,
+    pub seed: u64,
+    pub ipf: u32,
+    pub quirks: Quirks,
+    pub key_order: [u8; 16], // `Chip8::key_order`, the inputs are keypad keys.
+    pub inputs: Vec<InputEvent>,
+    pub rng: Vec<u8>, // Random numbers the session used, when `Chip8::enable_rng_log` was on.
+    pub cycles: u64, // Where the reproduction ends.
+    pub hash: u64, // `Chip8::gfx_hash` at the end, to check the replay got there.
+}
+
+impl Repro {
+    // Sum up a session recorded from the start of `rom` (see `Recording::start`)
+    // as it stands on `c8` now.
+    pub fn capture(rom: &Rom, recording: &Recording, c8: &Chip8) -> Repro {
+        Repro {
+            rom: rom.bytes().to_vec(),
+            seed: recording.seed,
+            ipf: recording.ipf,
+            quirks: c8.quirks,
+            key_order: c8.key_order,
+            inputs: recording.inputs.iter().filter(|input| input.cycle <= c8.cycles()).copied().collect(),
+            rng: c8.rng_log().to_vec(),
+            cycles: c8.cycles(),
+            hash: c8.gfx_hash(),
+        }
+    }
+
+    // Replay onto a new machine. The result is where the session ended, as
+    // long as nothing outside the ROM, seed, quirks, key order, keys and random
+    // numbers affected it.
+    pub fn reconstruct(&self) -> Result<Chip8, String> {
+        let mut c8 = self.start()?;
+        self.replay(&mut c8);
+        Ok(c8)
+    }
+
+    // The machine as the session started, before anything has run. This and
+    // `replay` are `reconstruct` split in two, for frontends that run the
+    // replay under their own crash handling.
+    pub fn start(&self) -> Result<Chip8, String> {
+        let rom = Rom::try_from(&self.rom[..]).map_err(|e| e.to_string())?;
+
+        let mut c8 = Chip8::with_seed(self.seed);
+        c8.quirks = self.quirks;
+        c8.key_order = self.key_order;
+        c8.load_rom(&rom);
+        if !self.rng.is_empty() {
+            c8.replay_rng(self.rng.clone());
+        }
+
+        Ok(c8)
+    }
+
+    // Run `c8`, fresh from `start`, through the recorded keys to the end.
+    pub fn replay(&self, c8: &mut Chip8) {
+        let mut inputs = self.inputs.iter().peekable();
+        while c8.cycles() < self.cycles && !c8.stopped() {
+            while let Some(input) = inputs.peek() {
+                if input.cycle > c8.cycles() {
+                    break;
+                }
+
+                if input.pressed {
+                    c8.key_pressed(input.key as usize);
+                } else {
+                    c8.key_released(input.key as usize);
+                }
+                inputs.next();
+            }
+
+            c8.cycle_in_frame(self.ipf);
+        }
+    }
+
+    // Whether `c8` ended up showing what the original session did.
+    pub fn matches(&self, c8: &Chip8) -> bool {
+        c8.cycles() == self.cycles && c8.gfx_hash() == self.hash
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&[VERSION])?;
+        w.write_all(&(self.rom.len() as u32).to_le_bytes())?;
+        w.write_all(&self.rom)?;
+        w.write_all(&self.seed.to_le_bytes())?;
+        w.write_all(&self.ipf.to_le_bytes())?;
+
+        let q = &self.quirks;
+        let flags = [
+            q.wrap_x, q.wrap_y, q.xo_chip, q.schip, q.shift_vy, q.jump_vx,
+            q.load_store_increment, q.vf_reset, q.display_wait, q.clamp_index,
+            q.half_pixel_scroll,
+        ];
+        for &flag in flags.iter() {
+            w.write_all(&[flag as u8])?;
+        }
+        w.write_all(&[q.stack as u8])?;
+        w.write_all(&self.key_order)?;
+
+        w.write_all(&(self.inputs.len() as u32).to_le_bytes())?;
+        for input in self.inputs.iter() {
+            w.write_all(&input.cycle.to_le_bytes())?;
+            w.write_all(&[input.key, input.pressed as u8])?;
+        }
+
+        w.write_all(&(self.rng.len() as u32).to_le_bytes())?;
+        w.write_all(&self.rng)?;
+
+        w.write_all(&self.cycles.to_le_bytes())?;
+        w.write_all(&self.hash.to_le_bytes())?;
+
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<Repro> {
+        let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC || read_u8(r)? != VERSION {
+            return Err(invalid("not a chip8rs reproduction"));
+        }
+
+        // Lengths come from the file, check them before allocating anything.
+        let rom_len = read_u32(r)? as usize;
+        if rom_len > MAX_ROM_SIZE {
+            return Err(invalid("ROM too large"));
+        }
+        let mut rom = vec![0; rom_len];
+        r.read_exact(&mut rom)?;
+        let seed = read_u64(r)?;
+        let ipf = read_u32(r)?.max(1);
+
+        let mut flags = [false; 11];
+        for flag in flags.iter_mut() {
+            *flag = read_u8(r)? != 0;
+        }
+        let stack = match read_u8(r)? {
+            0 => StackPolicy::Error,
+            1 => StackPolicy::Wrap,
+            2 => StackPolicy::Grow,
+            _ => return Err(invalid("unknown stack policy")),
+        };
+        let quirks = Quirks {
+            wrap_x: flags[0],
+            wrap_y: flags[1],
+            xo_chip: flags[2],
+            schip: flags[3],
+            shift_vy: flags[4],
+            jump_vx: flags[5],
+            load_store_increment: flags[6],
+            vf_reset: flags[7],
+            display_wait: flags[8],
+            clamp_index: flags[9],
+            half_pixel_scroll: flags[10],
+            stack,
+        };
+        let mut key_order = [0; 16];
+        r.read_exact(&mut key_order)?;
+
+        let mut inputs = Vec::new();
+        for _ in 0..read_u32(r)? {
+            let cycle = read_u64(r)?;
+            let key = read_u8(r)?;
+            let pressed = read_u8(r)? != 0;
+
+            if key >= 16 {
+                return Err(invalid("recorded key out of range"));
+            }
+            inputs.push(InputEvent { cycle, key, pressed });
+        }
+
+        // Any number of these is valid, so only take what's really there.
+        let rng_len = read_u32(r)? as u64;
+        let mut rng = Vec::new();
+        if r.by_ref().take(rng_len).read_to_end(&mut rng)? as u64 != rng_len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "random numbers cut short"));
+        }
+
+        let cycles = read_u64(r)?;
+        let hash = read_u64(r)?;
+
+        Ok(Repro { rom, seed, ipf, quirks, key_order, inputs, rng, cycles, hash })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_order_round_trips() {
+        let rom = Rom::try_from(&crate::chip8_program![0xF00A, 0x1202][..]).unwrap();
+        let mut c8 = Chip8::with_seed(7);
+        c8.key_order[0x1] = 0x4;
+        c8.key_order[0x4] = 0x1;
+        c8.load_rom(&rom);
+
+        let mut recording = Recording::start(&c8, 10, 60);
+        recording.record_key(0, 0x4, true);
+        c8.key_pressed(0x4);
+        c8.cycle();
+        assert_eq!(c8.reg(crate::emu::VReg::V0), 0x1);
+
+        let repro = Repro::capture(&rom, &recording, &c8);
+        let mut bytes = Vec::new();
+        repro.write_to(&mut bytes).unwrap();
+        let loaded = Repro::read_from(&mut &bytes[..]).unwrap();
+        assert_eq!(loaded, repro);
+
+        let replayed = loaded.reconstruct().unwrap();
+        assert_eq!(replayed.reg(crate::emu::VReg::V0), 0x1);
+    }
+
+    #[test]
+    fn oversized_lengths_are_rejected() {
+        let rom = Rom::try_from(&crate::chip8_program![0x1200][..]).unwrap();
+        let c8 = Chip8::with_seed(0);
+        let repro = Repro::capture(&rom, &Recording::start(&c8, 10, 60), &c8);
+        let mut bytes = Vec::new();
+        repro.write_to(&mut bytes).unwrap();
+
+        // A ROM length of 4 GiB.
+        let mut huge_rom = bytes.clone();
+        huge_rom[5..9].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(Repro::read_from(&mut &huge_rom[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        // As many random numbers, with none of them there.
+        let rng_len = bytes.len() - 16 - 4;
+        let mut huge_rng = bytes[..rng_len].to_vec();
+        huge_rng.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(Repro::read_from(&mut &huge_rng[..]).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn reconstruction_matches_the_framebuffer() {
+        // Wait for a key and draw its digit, over and over.
+        let rom = Rom::try_from(&crate::chip8_program![0xF00A, 0x00E0, 0xF029, 0xD015, 0x1200][..]).unwrap();
+        let mut c8 = Chip8::with_seed(3);
+        c8.load_rom(&rom);
+
+        let mut recording = Recording::start(&c8, 10, 60);
+        for &(cycle, key, pressed) in [(0, 0x7, true), (1, 0x7, false), (6, 0xC, true)].iter() {
+            while c8.cycles() < cycle {
+                c8.cycle();
+            }
+            recording.record_key(cycle, key, pressed);
+            if pressed {
+                c8.key_pressed(key);
+            } else {
+                c8.key_released(key);
+            }
+        }
+        while c8.cycles() < 12 {
+            c8.cycle();
+        }
+        assert_eq!(c8.reg(crate::emu::VReg::V0), 0xC);
+
+        let repro = Repro::capture(&rom, &recording, &c8);
+        let replayed = repro.reconstruct().unwrap();
+        assert_eq!(replayed.gfx_hash(), c8.gfx_hash());
+        assert!(repro.matches(&replayed));
+    }
+}
@@ -0,0 +1,130 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use super::emu::MAX_ROM_SIZE;
+use super::error::Chip8Error;
+
+// Why some bytes can't be loaded as a ROM.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RomError {
+    Empty,
+    TooLarge(usize, usize), // Length of the rejected ROM, and the limit it went over.
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            RomError::Empty => write!(f, "ROM is empty"),
+            RomError::TooLarge(len, limit) if limit >= MAX_ROM_SIZE =>
+                write!(f, "ROM is {} bytes, only {} fit in memory", len, MAX_ROM_SIZE),
+            RomError::TooLarge(len, limit) =>
+                write!(f, "ROM is {} bytes, over the limit of {}", len, limit),
+        }
+    }
+}
+
+// Program bytes that are known to fit in memory. An odd length is allowed,
+// `Chip8::load_rom` reports it as a warning.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rom {
+    bytes: Vec<u8>,
+}
+
+impl Rom {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Rom, Chip8Error> {
+        Rom::from_path_limited(path, MAX_ROM_SIZE)
+    }
+
+    // Same, but refuse files over `limit` bytes, which can't be more than fits
+    // in memory. The size is checked before reading, so pointing this at some
+    // huge file by mistake fails straight away.
+    pub fn from_path_limited<P: AsRef<Path>>(path: P, limit: usize) -> Result<Rom, Chip8Error> {
+        let path = path.as_ref();
+        let limit = limit.min(MAX_ROM_SIZE);
+
+        // Reading a directory only fails once it's open, with a less helpful error.
+        if path.is_dir() {
+            return Err(Chip8Error::IsDirectory(path.to_path_buf()));
+        }
+
+        let size = fs::metadata(path).map_err(|e| Chip8Error::from_io(e, path.to_path_buf()))?.len();
+        if size > limit as u64 {
+            return Err(RomError::TooLarge(size as usize, limit).into());
+        }
+
+        let bytes = fs::read(path).map_err(|e| Chip8Error::from_io(e, path.to_path_buf()))?;
+        Ok(Rom::from_bytes_limited(&bytes, limit)?)
+    }
+
+    fn from_bytes_limited(bytes: &[u8], limit: usize) -> Result<Rom, RomError> {
+        if bytes.is_empty() {
+            return Err(RomError::Empty);
+        }
+        if bytes.len() > limit {
+            return Err(RomError::TooLarge(bytes.len(), limit));
+        }
+
+        Ok(Rom { bytes: bytes.to_vec() })
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    // Always false, empty ROMs are rejected.
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+impl TryFrom<&[u8]> for Rom {
+    type Error = RomError;
+
+    fn try_from(bytes: &[u8]) -> Result<Rom, RomError> {
+        Rom::from_bytes_limited(bytes, MAX_ROM_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_bytes() {
+        let rom = Rom::try_from(&[0x60, 0x01, 0x12][..]).unwrap();
+        assert_eq!(rom.bytes(), &[0x60, 0x01, 0x12]);
+        assert_eq!(rom.len(), 3);
+
+        assert_eq!(Rom::try_from(&vec![0; MAX_ROM_SIZE][..]).unwrap().len(), MAX_ROM_SIZE);
+    }
+
+    #[test]
+    fn oversized_bytes() {
+        assert_eq!(Rom::try_from(&vec![0; MAX_ROM_SIZE + 1][..]), Err(RomError::TooLarge(MAX_ROM_SIZE + 1, MAX_ROM_SIZE)));
+    }
+
+    #[test]
+    fn oversized_file_names_its_size() {
+        let path = std::env::temp_dir().join(format!("chip8rs-oversized-{}.ch8", std::process::id()));
+        fs::write(&path, vec![0x12; 600]).unwrap();
+
+        let limited = Rom::from_path_limited(&path, 512).unwrap_err().to_string();
+        let unlimited = Rom::from_path_limited(&path, 8192).map(|rom| rom.len());
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(limited, "ROM is 600 bytes, over the limit of 512");
+        assert_eq!(unlimited.unwrap(), 600);
+        assert_eq!(RomError::TooLarge(5000, MAX_ROM_SIZE).to_string(), "ROM is 5000 bytes, only 3584 fit in memory");
+    }
+
+    #[test]
+    fn empty_bytes() {
+        assert_eq!(Rom::try_from(&[][..]), Err(RomError::Empty));
+    }
+}
@@ -0,0 +1,109 @@
+use super::replay::InputEvent;
+use super::Chip8;
+
+// Key presses written by hand for headless runs, one per line:
+//
+//   # Start the game, then hold 5 for a second.
+//   100: 5 down
+//   160: 5 up
+//
+// The number before the colon is the cycle the change happens at, keys are
+// the hex digits of the keypad.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InputScript {
+    pub inputs: Vec<InputEvent>, // In cycle order.
+    next: usize, // First input not applied yet.
+}
+
+impl InputScript {
+    pub fn parse(text: &str) -> Result<InputScript, String> {
+        let mut inputs = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let input = parse_line(line).ok_or_else(|| format!("line {}: expected `<cycle>: <key> down|up`, found `{}`", number + 1, line))?;
+            inputs.push(input);
+        }
+
+        // Changes on the same cycle keep the order they were written in.
+        inputs.sort_by_key(|input| input.cycle);
+
+        Ok(InputScript { inputs, next: 0 })
+    }
+
+    // Apply every change due by the machine's current cycle, call this before each `cycle`.
+    pub fn apply(&mut self, c8: &mut Chip8) {
+        while let Some(input) = self.inputs.get(self.next) {
+            if input.cycle > c8.cycles() {
+                break;
+            }
+
+            if input.pressed {
+                c8.key_pressed(input.key as usize);
+            } else {
+                c8.key_released(input.key as usize);
+            }
+            self.next += 1;
+        }
+    }
+}
+
+fn parse_line(line: &str) -> Option<InputEvent> {
+    let mut halves = line.splitn(2, ':');
+    let cycle = halves.next()?.trim().parse().ok()?;
+
+    let mut words = halves.next()?.split_whitespace();
+    let key = u8::from_str_radix(words.next()?, 16).ok().filter(|&key| key < 16)?;
+    let pressed = match words.next()? {
+        "down" => true,
+        "up" => false,
+        _ => return None,
+    };
+
+    if words.next().is_some() {
+        return None;
+    }
+
+    Some(InputEvent { cycle, key, pressed })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emu::Rom;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn keys_change_on_their_cycles() {
+        let mut script = InputScript::parse("
+            # Hold A, then press 5 while it's down.
+            2: a down
+            4: 5 down
+            4: A up
+            6: 5 up
+        ").unwrap();
+        assert_eq!(script.inputs.len(), 4);
+
+        let mut c8 = Chip8::with_seed(0);
+        c8.load_rom(&Rom::try_from(&crate::chip8_program![0x1200][..]).unwrap());
+
+        let mut down = Vec::new();
+        for _ in 0..8 {
+            script.apply(&mut c8);
+            down.push((0..16).filter(|&key| c8.is_key_down(key)).collect::<Vec<_>>());
+            c8.cycle();
+        }
+        assert_eq!(down, vec![vec![], vec![], vec![0xA], vec![0xA], vec![5], vec![5], vec![], vec![]]);
+    }
+
+    #[test]
+    fn bad_lines_are_reported() {
+        assert_eq!(InputScript::parse("10: 5 down\n20: G up").unwrap_err(), "line 2: expected `<cycle>: <key> down|up`, found `20: G up`");
+        assert!(InputScript::parse("10: 5 sideways").is_err());
+        assert!(InputScript::parse("10: 5 down now").is_err());
+    }
+}
@@ -0,0 +1,297 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use super::emu::{Frame, STACK_SIZE};
+use super::replay::{read_u16, read_u64, read_u8};
+
+const MAGIC: &[u8; 4] = b"C8ST";
+const VERSION: u8 = 4;
+
+// About a billion, a few seconds to draw again on load.
+const MAX_RNG_DRAWS: u64 = 1 << 30;
+
+// Everything needed to put a machine back where it was. The random number
+// generator is rebuilt from the seed and the number of values already drawn,
+// so CXNN carries on exactly where the original run was.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SaveState {
+    pub memory: Vec<u8>,
+    pub registers: [u8; 16],
+    pub index: u16,
+    pub pc: u16,
+    pub stack: Vec<u16>, // Longer than STACK_SIZE if StackPolicy::Grow made room.
+    pub sp: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub keys: [u8; 16],
+    pub held: [bool; 16], // Host key state, only differs from keys after a pulse was used up.
+    pub vblank: bool, // Whether a DXYN held by display_wait may go ahead.
+    pub audio_pattern: [u8; 16],
+    pub pitch: u8,
+    pub gfx: Frame,
+    pub seed: u64,
+    pub rng_draws: u64,
+    pub cycles: u64,
+}
+
+// One way in which two save states differ.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StateDiff {
+    Register(u8, u8, u8), // (register, a, b)
+    Index(u16, u16),
+    Pc(u16, u16),
+    Sp(u16, u16),
+    Stack(u8, u16, u16), // (slot, a, b)
+    DelayTimer(u8, u8),
+    SoundTimer(u8, u8),
+    Memory(u16, u16), // Inclusive range of addresses holding different bytes.
+    Pixel(u8, u8), // (x, y) of a pixel that is lit in only one of them.
+}
+
+impl fmt::Display for StateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            StateDiff::Register(reg, a, b) => write!(f, "V{:X}: {:02X} != {:02X}", reg, a, b),
+            StateDiff::Index(a, b) => write!(f, "I: {:03X} != {:03X}", a, b),
+            StateDiff::Pc(a, b) => write!(f, "PC: {:03X} != {:03X}", a, b),
+            StateDiff::Sp(a, b) => write!(f, "SP: {} != {}", a, b),
+            StateDiff::Stack(slot, a, b) => write!(f, "Stack[{}]: {:03X} != {:03X}", slot, a, b),
+            StateDiff::DelayTimer(a, b) => write!(f, "DT: {:02X} != {:02X}", a, b),
+            StateDiff::SoundTimer(a, b) => write!(f, "ST: {:02X} != {:02X}", a, b),
+            StateDiff::Memory(start, end) if start == end => write!(f, "Memory {:03X}", start),
+            StateDiff::Memory(start, end) => write!(f, "Memory {:03X}-{:03X}", start, end),
+            StateDiff::Pixel(x, y) => write!(f, "Pixel ({}, {})", x, y),
+        }
+    }
+}
+
+impl SaveState {
+    // Everything that differs between this state and `other`, registers first,
+    // then memory as runs of adjacent addresses, then the display.
+    pub fn diff(&self, other: &SaveState) -> Vec<StateDiff> {
+        let mut diffs = Vec::new();
+
+        for (reg, (&a, &b)) in self.registers.iter().zip(other.registers.iter()).enumerate() {
+            if a != b {
+                diffs.push(StateDiff::Register(reg as u8, a, b));
+            }
+        }
+        if self.index != other.index {
+            diffs.push(StateDiff::Index(self.index, other.index));
+        }
+        if self.pc != other.pc {
+            diffs.push(StateDiff::Pc(self.pc, other.pc));
+        }
+        if self.sp != other.sp {
+            diffs.push(StateDiff::Sp(self.sp, other.sp));
+        }
+        // A grown stack is compared against empty slots in the other.
+        let slots = self.stack.len().max(other.stack.len());
+        for slot in 0..slots {
+            let a = self.stack.get(slot).copied().unwrap_or(0);
+            let b = other.stack.get(slot).copied().unwrap_or(0);
+            if a != b {
+                diffs.push(StateDiff::Stack(slot as u8, a, b));
+            }
+        }
+        if self.delay_timer != other.delay_timer {
+            diffs.push(StateDiff::DelayTimer(self.delay_timer, other.delay_timer));
+        }
+        if self.sound_timer != other.sound_timer {
+            diffs.push(StateDiff::SoundTimer(self.sound_timer, other.sound_timer));
+        }
+
+        let mut run: Option<(usize, usize)> = None;
+        for (addr, (a, b)) in self.memory.iter().zip(other.memory.iter()).enumerate() {
+            if a == b {
+                if let Some((start, end)) = run.take() {
+                    diffs.push(StateDiff::Memory(start as u16, end as u16));
+                }
+            } else {
+                run = Some((run.map_or(addr, |(start, _)| start), addr));
+            }
+        }
+        if let Some((start, end)) = run {
+            diffs.push(StateDiff::Memory(start as u16, end as u16));
+        }
+
+        for (y, (row_a, row_b)) in self.gfx.iter().zip(other.gfx.iter()).enumerate() {
+            for (x, (a, b)) in row_a.iter().zip(row_b.iter()).enumerate() {
+                if a != b {
+                    diffs.push(StateDiff::Pixel(x as u8, y as u8));
+                }
+            }
+        }
+
+        diffs
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&[VERSION])?;
+        w.write_all(&self.memory)?;
+        w.write_all(&self.registers)?;
+        w.write_all(&self.index.to_le_bytes())?;
+        w.write_all(&self.pc.to_le_bytes())?;
+        w.write_all(&(self.stack.len() as u16).to_le_bytes())?;
+        for addr in self.stack.iter() {
+            w.write_all(&addr.to_le_bytes())?;
+        }
+        w.write_all(&self.sp.to_le_bytes())?;
+        w.write_all(&[self.delay_timer, self.sound_timer])?;
+        w.write_all(&self.keys)?;
+        for &held in self.held.iter() {
+            w.write_all(&[held as u8])?;
+        }
+        w.write_all(&[self.vblank as u8])?;
+        w.write_all(&self.audio_pattern)?;
+        w.write_all(&[self.pitch])?;
+        for row in self.gfx.iter() {
+            w.write_all(row)?;
+        }
+        w.write_all(&self.seed.to_le_bytes())?;
+        w.write_all(&self.rng_draws.to_le_bytes())?;
+        w.write_all(&self.cycles.to_le_bytes())?;
+
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<SaveState> {
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC || read_u8(r)? != VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a chip8rs save state"));
+        }
+
+        let mut memory = vec![0; 4096];
+        r.read_exact(&mut memory)?;
+        let mut registers = [0; 16];
+        r.read_exact(&mut registers)?;
+        let index = read_u16(r)?;
+        let pc = read_u16(r)?;
+        let mut stack = vec![0; read_u16(r)? as usize];
+        if stack.len() < STACK_SIZE {
+            // Calls index into it without checking, only Grow ever resizes it.
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "stack shorter than 16 entries"));
+        }
+        for addr in stack.iter_mut() {
+            *addr = read_u16(r)?;
+        }
+        let sp = read_u16(r)?;
+        if sp as usize > stack.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "stack pointer out of range"));
+        }
+        let delay_timer = read_u8(r)?;
+        let sound_timer = read_u8(r)?;
+        let mut keys = [0; 16];
+        r.read_exact(&mut keys)?;
+        let mut held = [false; 16];
+        for pressed in held.iter_mut() {
+            *pressed = read_u8(r)? != 0;
+        }
+        let vblank = read_u8(r)? != 0;
+        let mut audio_pattern = [0; 16];
+        r.read_exact(&mut audio_pattern)?;
+        let pitch = read_u8(r)?;
+        let mut gfx = [[0; 64]; 32];
+        for row in gfx.iter_mut() {
+            r.read_exact(row)?;
+        }
+        let seed = read_u64(r)?;
+        let rng_draws = read_u64(r)?;
+        let cycles = read_u64(r)?;
+        // Loading draws all of these again to catch the generator up. CXNN
+        // takes at most one a cycle, and past MAX_RNG_DRAWS that would take
+        // longer than any real session could have run for.
+        if rng_draws > cycles || rng_draws > MAX_RNG_DRAWS {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "more random numbers used than possible"));
+        }
+
+        Ok(SaveState {
+            memory,
+            registers,
+            index,
+            pc,
+            stack,
+            sp,
+            delay_timer,
+            sound_timer,
+            keys,
+            held,
+            vblank,
+            audio_pattern,
+            pitch,
+            gfx,
+            seed,
+            rng_draws,
+            cycles,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emu::{Chip8, VReg};
+
+    #[test]
+    fn diff_reports_only_the_changed_register() {
+        let mut c8 = Chip8::with_seed(0);
+        let a = c8.save_state();
+        c8.set_reg(VReg::V3, 0x42);
+        let b = c8.save_state();
+
+        assert_eq!(a.diff(&b), vec![StateDiff::Register(3, 0x00, 0x42)]);
+        assert_eq!(a.diff(&b)[0].to_string(), "V3: 00 != 42");
+        assert!(a.diff(&a).is_empty());
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut c8 = Chip8::with_seed(9);
+        c8.set_reg(VReg::VA, 0x55);
+        c8.set_index(0x321);
+        c8.gfx[4][5] = 1;
+        let state = c8.save_state();
+
+        let mut bytes = Vec::new();
+        state.write_to(&mut bytes).unwrap();
+        assert_eq!(SaveState::read_from(&mut &bytes[..]).unwrap(), state);
+    }
+
+    #[test]
+    fn impossible_rng_draws_are_rejected() {
+        let mut state = Chip8::with_seed(0).save_state();
+        state.cycles = 100;
+        for &draws in [101, u64::MAX].iter() {
+            state.rng_draws = draws;
+            let mut bytes = Vec::new();
+            state.write_to(&mut bytes).unwrap();
+            assert_eq!(SaveState::read_from(&mut &bytes[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+
+        state.cycles = u64::MAX;
+        state.rng_draws = MAX_RNG_DRAWS + 1;
+        let mut bytes = Vec::new();
+        state.write_to(&mut bytes).unwrap();
+        assert!(SaveState::read_from(&mut &bytes[..]).is_err());
+
+        state.rng_draws = 100;
+        let mut bytes = Vec::new();
+        state.write_to(&mut bytes).unwrap();
+        assert_eq!(SaveState::read_from(&mut &bytes[..]).unwrap(), state);
+    }
+
+    #[test]
+    fn short_stack_is_rejected() {
+        let mut state = Chip8::with_seed(0).save_state();
+        for len in [STACK_SIZE - 1, 0].iter() {
+            state.stack.truncate(*len);
+            let mut bytes = Vec::new();
+            state.write_to(&mut bytes).unwrap();
+
+            let error = SaveState::read_from(&mut &bytes[..]).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+// Helpers for display regression tests.
+
+use super::Chip8;
+
+// Check the display against ASCII art, `#` for on and `.` for off. The art
+// covers the top left corner of the screen, every pixel outside it must be
+// off. Leading and trailing blank lines and indentation are ignored, so the
+// picture can be written as an indented raw string.
+pub fn assert_framebuffer(c8: &Chip8, expected_ascii: &str) {
+    let lines: Vec<&str> = expected_ascii.lines().map(|line| line.trim()).collect();
+    let first = lines.iter().position(|line| !line.is_empty()).unwrap_or(lines.len());
+    let last = lines.iter().rposition(|line| !line.is_empty()).map_or(first, |last| last + 1);
+    let expected = &lines[first..last];
+
+    if expected.len() > c8.gfx.len() || expected.iter().any(|line| line.len() > c8.gfx[0].len()) {
+        panic!("expected framebuffer is larger than the 64x32 display");
+    }
+
+    let mut mismatched = Vec::new();
+    for (y, row) in c8.gfx.iter().enumerate() {
+        let line = expected.get(y).map_or(&[][..], |line| line.as_bytes());
+
+        for (x, &pixel) in row.iter().enumerate() {
+            let want = line.get(x) == Some(&b'#');
+            if want != (pixel != 0) {
+                mismatched.push((x, y));
+            }
+        }
+    }
+
+    if !mismatched.is_empty() {
+        panic!("framebuffer differs at {} pixels, first at {:?}\n{}",
+            mismatched.len(), mismatched[0], side_by_side(c8, expected));
+    }
+}
+
+// Expected and actual rows next to each other, rows that differ marked with `!`.
+fn side_by_side(c8: &Chip8, expected: &[&str]) -> String {
+    let actual = c8.to_ascii();
+    let width = c8.gfx[0].len();
+    let mut out = format!("  {:<w$}  {}\n", "expected", "actual", w = width);
+
+    for (y, actual_row) in actual.lines().enumerate() {
+        let expected_row: String = (0..width)
+            .map(|x| if expected.get(y).and_then(|line| line.as_bytes().get(x)) == Some(&b'#') { '#' } else { '.' })
+            .collect();
+        let marker = if expected_row == actual_row { ' ' } else { '!' };
+
+        out.push_str(&format!("{} {}  {}\n", marker, expected_row, actual_row));
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emu::Rom;
+    use std::convert::TryFrom;
+
+    // Draw the font glyph for `digit` at (1, 2) and run up to the draw.
+    fn draw_glyph(digit: u8) -> Chip8 {
+        let mut c8 = Chip8::with_seed(0);
+        let program = crate::chip8_program![0x6000 | digit as u16, 0xF029, 0x6101, 0x6202, 0xD125];
+        c8.load_rom(&Rom::try_from(&program[..]).unwrap());
+        for _ in 0..5 {
+            c8.cycle();
+        }
+        c8
+    }
+
+    #[test]
+    fn glyph_1() {
+        assert_framebuffer(&draw_glyph(0x1), "
+            ....
+            ....
+            ...#
+            ..##
+            ...#
+            ...#
+            ..###
+        ");
+    }
+
+    #[test]
+    fn glyph_a() {
+        assert_framebuffer(&draw_glyph(0xA), "
+            .....
+            .....
+            .####
+            .#..#
+            .####
+            .#..#
+            .#..#
+        ");
+    }
+
+    #[test]
+    fn glyph_f() {
+        assert_framebuffer(&draw_glyph(0xF), "
+            .....
+            .....
+            .####
+            .#...
+            .####
+            .#...
+            .#...
+        ");
+    }
+
+    #[test]
+    #[should_panic(expected = "framebuffer differs at 1 pixels")]
+    fn mismatch_is_reported() {
+        assert_framebuffer(&draw_glyph(0xF), "
+            .....
+            .....
+            .####
+            .#...
+            .####
+            .#...
+            .##..
+        ");
+    }
+
+    #[test]
+    #[should_panic(expected = "larger than the 64x32 display")]
+    fn oversized_art_is_rejected() {
+        let c8 = Chip8::with_seed(0);
+        assert_framebuffer(&c8, &"#".repeat(65));
+    }
+}
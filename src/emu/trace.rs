@@ -0,0 +1,57 @@
+use std::fmt::Write;
+
+use super::disasm;
+use super::instruction::VReg;
+
+// One executed instruction and the registers it changed, written as a JSON
+// line while a JSON trace is on (see `Chip8::set_json_trace`). Other
+// emulators can be made to log the same fields, so runs can be diffed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceRecord {
+    pub cycle: u64, // Cycle count when the instruction ran.
+    pub pc: u16,
+    pub opcode: u16,
+    pub registers: Vec<(VReg, u8)>, // New value of every V register that changed.
+    pub index: Option<u16>, // New I, if it changed.
+}
+
+impl TraceRecord {
+    // A single line, with numbers in decimal and the disassembly as the mnemonic:
+    // {"cycle":0,"pc":512,"opcode":24586,"mnemonic":"LD V0, #0A","changes":{"V0":10}}
+    pub fn to_json(&self) -> String {
+        let mut json = format!("{{\"cycle\":{},\"pc\":{},\"opcode\":{},\"mnemonic\":\"{}\",\"changes\":{{",
+            self.cycle, self.pc, self.opcode, escape(&disasm::disassemble(self.opcode)));
+
+        let mut first = true;
+        for &(reg, value) in self.registers.iter() {
+            if !first {
+                json.push(',');
+            }
+            first = false;
+            write!(json, "\"{}\":{}", reg, value).unwrap();
+        }
+        if let Some(index) = self.index {
+            if !first {
+                json.push(',');
+            }
+            write!(json, "\"I\":{}", index).unwrap();
+        }
+
+        json.push_str("}}");
+        json
+    }
+}
+
+// Mnemonics are plain ASCII, but keep the output valid JSON whatever they hold.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => write!(escaped, "\\u{:04x}", c as u32).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
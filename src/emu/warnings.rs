@@ -0,0 +1,44 @@
+use std::fmt;
+
+// Things a ROM did that work here but may not on other interpreters, or that
+// point at a bug in the ROM. Addresses are where the offending opcode was.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Warning {
+    UnknownOpcode { pc: u16, opcode: u16 },
+    IndexOverflow { pc: u16, index: u16 }, // FX1E pushed I past the end of memory.
+    FontWrite { pc: u16, addr: u16 }, // A store overwrote the built in font.
+    OddRomLength(usize),
+    DeepStack { pc: u16, depth: u16 }, // More nested calls than the original interpreter allowed.
+    ReservedTarget { pc: u16, target: u16 }, // A 1NNN, 2NNN or ANNN in the ROM points below 0x200.
+    SpriteWrap { pc: u16, index: u16 }, // A sprite ran off the end of memory, the rest came from the start.
+    RngExhausted { pc: u16 }, // The replayed random numbers ran out, CXNN went back to the seeded generator.
+    PcWrap { pc: u16 }, // PC ran off the end of memory and carried on from the start.
+    LowResScroll { pc: u16 }, // A scroll in low-res, where SCHIP 1.0 and 1.1 disagree on the distance.
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Warning::UnknownOpcode { pc, opcode } =>
+                write!(f, "Unknown opcode {:04X} at {:03X}", opcode, pc),
+            Warning::IndexOverflow { pc, index } =>
+                write!(f, "I overflowed to {:04X} at {:03X}", index, pc),
+            Warning::FontWrite { pc, addr } =>
+                write!(f, "Font overwritten at {:03X} by the opcode at {:03X}", addr, pc),
+            Warning::OddRomLength(len) =>
+                write!(f, "ROM has an odd length ({} bytes), its last instruction is misaligned", len),
+            Warning::DeepStack { pc, depth } =>
+                write!(f, "Call at {:03X} nested {} deep", pc, depth),
+            Warning::ReservedTarget { pc, target } =>
+                write!(f, "Opcode at {:03X} points into the reserved area at {:03X}", pc, target),
+            Warning::SpriteWrap { pc, index } =>
+                write!(f, "Sprite at {:04X} drawn at {:03X} runs past the end of memory", index, pc),
+            Warning::RngExhausted { pc } =>
+                write!(f, "Ran out of replayed random numbers at {:03X}", pc),
+            Warning::PcWrap { pc } =>
+                write!(f, "PC ran past the end of memory to {:04X} and wrapped around", pc),
+            Warning::LowResScroll { pc } =>
+                write!(f, "Low-res scroll at {:03X}, try --half-pixel-scroll if it moves twice as far as it should", pc),
+        }
+    }
+}
@@ -0,0 +1,179 @@
+// Runs a `Chip8` on its own thread at its own pace, so a frontend can draw
+// whenever it likes. Input and control go in over one channel, snapshots of the
+// display come back over another.
+
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TryRecvError};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use super::clock::{FrameClock, MAX_CATCH_UP_FRAMES};
+use super::emu::{Chip8, Frame};
+use super::events::Chip8Event;
+
+// Snapshots waiting for the frontend before the worker starts dropping them.
+pub const SNAPSHOT_QUEUE: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Control {
+    KeyDown(usize),
+    KeyUp(usize),
+    Pause,
+    Resume,
+    Step, // Run one instruction while paused.
+    Reset, // Go back to the state the worker was started with.
+    Quit,
+}
+
+// The machine as of the end of a frame (or a step).
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    pub gfx: Frame,
+    pub events: Vec<Chip8Event>, // Since the previous snapshot.
+    pub cycles: u64,
+    pub paused: bool,
+}
+
+pub struct Worker {
+    control: Sender<Control>,
+    snapshots: Receiver<Snapshot>,
+    thread: Option<JoinHandle<Chip8>>,
+}
+
+impl Worker {
+    // Start running `c8`, `ipf` instructions for every timer tick.
+    pub fn spawn(c8: Chip8, ipf: u32) -> Worker {
+        let (control, control_rx) = mpsc::channel();
+        let (snapshot_tx, snapshots) = mpsc::sync_channel(SNAPSHOT_QUEUE);
+
+        let thread = thread::spawn(move || run(c8, ipf, control_rx, snapshot_tx));
+
+        Worker { control, snapshots, thread: Some(thread) }
+    }
+
+    pub fn send(&self, control: Control) {
+        // Only fails once the worker has quit, and then there's nobody to tell.
+        let _ = self.control.send(control);
+    }
+
+    // Snapshots that arrived since the last call, oldest first. A frontend
+    // that falls behind misses some along with their events, but each one
+    // has the whole display.
+    pub fn snapshots(&self) -> impl Iterator<Item = Snapshot> + '_ {
+        self.snapshots.try_iter()
+    }
+
+    // Stop the thread and hand the machine back.
+    pub fn shutdown(mut self) -> Chip8 {
+        self.stop().unwrap()
+    }
+
+    fn stop(&mut self) -> Option<Chip8> {
+        self.send(Control::Quit);
+        self.thread.take().map(|thread| thread.join().expect("emulation thread panicked"))
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn run(mut c8: Chip8, ipf: u32, control: Receiver<Control>, snapshots: SyncSender<Snapshot>) -> Chip8 {
+    let start = c8.save_state();
+    let mut clock = FrameClock::new(c8.timer_interval(), MAX_CATCH_UP_FRAMES);
+    let mut last = Instant::now();
+
+    loop {
+        let mut stepped = false;
+
+        loop {
+            match control.try_recv() {
+                Ok(Control::KeyDown(key)) => c8.key_pressed(key),
+                Ok(Control::KeyUp(key)) => c8.key_released(key),
+                Ok(Control::Pause) => c8.paused = true,
+                Ok(Control::Resume) => c8.paused = false,
+                Ok(Control::Step) => {
+                    // cycle() does nothing while paused.
+                    let paused = c8.paused;
+                    c8.paused = false;
+                    c8.cycle_in_frame(ipf);
+                    c8.paused = paused;
+                    stepped = true;
+                },
+                Ok(Control::Reset) => c8.load_state(&start),
+                Ok(Control::Quit) | Err(TryRecvError::Disconnected) => return c8,
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+
+        let now = Instant::now();
+        let frames = clock.advance(now - last);
+        last = now;
+
+        for _ in 0..frames {
+            c8.advance_frame(ipf);
+        }
+
+        if frames > 0 || stepped {
+            let snapshot = Snapshot {
+                gfx: c8.gfx,
+                events: c8.take_events(),
+                cycles: c8.cycles(),
+                paused: c8.paused,
+            };
+            let _ = snapshots.try_send(snapshot);
+        }
+
+        thread::sleep(clock.until_next());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emu::Rom;
+    use std::convert::TryFrom;
+    use std::time::Duration;
+
+    // Wait up to a second for a snapshot matching `want`.
+    fn wait_for<F: Fn(&Snapshot) -> bool>(worker: &Worker, want: F) -> Snapshot {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(1) {
+            if let Some(snapshot) = worker.snapshots().find(|snapshot| want(snapshot)) {
+                return snapshot;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        panic!("no matching snapshot from the worker");
+    }
+
+    #[test]
+    fn controlled_worker_sends_snapshots() {
+        let mut c8 = Chip8::with_seed(0);
+        c8.load_rom(&Rom::try_from(&crate::chip8_program![0xD005, 0x7001, 0x1202][..]).unwrap());
+        c8.paused = true;
+        let worker = Worker::spawn(c8, 10);
+
+        // Stepping while paused draws the 0 glyph.
+        worker.send(Control::Step);
+        let stepped = wait_for(&worker, |snapshot| snapshot.cycles == 1);
+        assert!(stepped.paused);
+        assert_eq!(stepped.gfx[0][..4], [1, 1, 1, 1]);
+        assert_eq!(stepped.events.len(), 14);
+
+        worker.send(Control::KeyDown(0x5));
+        worker.send(Control::Resume);
+        wait_for(&worker, |snapshot| !snapshot.paused && snapshot.cycles > 1);
+
+        worker.send(Control::Pause);
+        worker.send(Control::Reset);
+        worker.send(Control::Step);
+        wait_for(&worker, |snapshot| snapshot.cycles == 1);
+
+        // Reset went back to the start, keys included.
+        let c8 = worker.shutdown();
+        assert_eq!(c8.cycles(), 1);
+        assert!(!c8.is_key_down(0x5));
+    }
+}
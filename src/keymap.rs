@@ -0,0 +1,75 @@
+// Host keys for each CHIP-8 key (the array index), per keyboard layout. They
+// all cover the same physical 4x4 block under the number row, 1-4 down to Z-V
+// on QWERTY, since piston reports keys by the character they type.
+
+use piston_window::keyboard::Key;
+
+pub const QWERTY: [Key; 16] = [
+    Key::D1, Key::D2, Key::D3, Key::D4,
+    Key::Q, Key::W, Key::E, Key::R,
+    Key::A, Key::S, Key::D, Key::F,
+    Key::Z, Key::X, Key::C, Key::V
+];
+
+pub const AZERTY: [Key; 16] = [
+    Key::D1, Key::D2, Key::D3, Key::D4,
+    Key::A, Key::Z, Key::E, Key::R,
+    Key::Q, Key::S, Key::D, Key::F,
+    Key::W, Key::X, Key::C, Key::V
+];
+
+pub const DVORAK: [Key; 16] = [
+    Key::D1, Key::D2, Key::D3, Key::D4,
+    Key::Quote, Key::Comma, Key::Period, Key::P,
+    Key::A, Key::O, Key::E, Key::U,
+    Key::Semicolon, Key::Q, Key::J, Key::K
+];
+
+pub const COLEMAK: [Key; 16] = [
+    Key::D1, Key::D2, Key::D3, Key::D4,
+    Key::Q, Key::W, Key::F, Key::P,
+    Key::A, Key::R, Key::S, Key::T,
+    Key::Z, Key::X, Key::C, Key::V
+];
+
+pub fn layout(name: &str) -> Option<[Key; 16]> {
+    match name {
+        "qwerty" => Some(QWERTY),
+        "azerty" => Some(AZERTY),
+        "dvorak" => Some(DVORAK),
+        "colemak" => Some(COLEMAK),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAMES: [&str; 4] = ["qwerty", "azerty", "dvorak", "colemak"];
+
+    #[test]
+    fn layouts_have_16_distinct_keys() {
+        for name in NAMES.iter() {
+            let keys = layout(name).unwrap();
+            for (i, key) in keys.iter().enumerate() {
+                assert!(!keys[i + 1..].contains(key), "{} maps {:?} twice", name, key);
+            }
+        }
+        assert_eq!(layout("workman"), None);
+    }
+
+    #[test]
+    fn directional_keys_stay_in_place() {
+        // CHIP-8 keys 2, 4, 6 and 8 on the keys QWERTY has as 3, Q, E and A.
+        let directions = |name| {
+            let keys = layout(name).unwrap();
+            [keys[0x2], keys[0x4], keys[0x6], keys[0x8]]
+        };
+
+        assert_eq!(directions("qwerty"), [Key::D3, Key::Q, Key::E, Key::A]);
+        assert_eq!(directions("azerty"), [Key::D3, Key::A, Key::E, Key::Q]);
+        assert_eq!(directions("dvorak"), [Key::D3, Key::Quote, Key::Period, Key::A]);
+        assert_eq!(directions("colemak"), [Key::D3, Key::Q, Key::F, Key::A]);
+    }
+}
@@ -0,0 +1,48 @@
+extern crate rand;
+
+pub mod emu;
+
+pub use emu::Chip8;
+
+// Program bytes from a list of opcodes, each split into its big endian byte
+// pair, so short test ROMs can be written as `chip8_program![0x600A, 0x6105]`
+// instead of byte by byte. Gives a `Vec<u8>` for `Rom::try_from`.
+#[macro_export]
+macro_rules! chip8_program {
+    ($($opcode:expr),* $(,)?) => {{
+        let opcodes: &[u16] = &[$($opcode),*];
+        let mut bytes = Vec::with_capacity(opcodes.len() * 2);
+        for opcode in opcodes {
+            bytes.extend_from_slice(&opcode.to_be_bytes());
+        }
+        bytes
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::emu::{Rom, VReg};
+    use crate::Chip8;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn program_is_big_endian_pairs() {
+        assert_eq!(chip8_program![0x600A, 0x6105, 0x8014], vec![0x60, 0x0A, 0x61, 0x05, 0x80, 0x14]);
+        assert_eq!(chip8_program![0x00E0,], vec![0x00, 0xE0]);
+        assert!(chip8_program![].is_empty());
+    }
+
+    #[test]
+    fn program_runs() {
+        let mut c8 = Chip8::with_seed(0);
+        c8.load_rom(&Rom::try_from(&chip8_program![0x600A, 0x6105, 0x8014][..]).unwrap());
+        for _ in 0..3 {
+            c8.cycle();
+        }
+
+        assert_eq!(c8.reg(VReg::V0), 0x0F);
+        assert_eq!(c8.reg(VReg::V1), 0x05);
+        assert_eq!(c8.reg(VReg::VF), 0);
+        assert_eq!(c8.pc(), 0x206);
+    }
+}
@@ -1,16 +1,44 @@
 extern crate piston_window;
 extern crate image as im;
 extern crate fps_counter;
+extern crate chip8rs;
 
 use piston_window::*;
 use piston_window::keyboard::Key;
 
-mod emu;
-use emu::Chip8;
+mod browse;
 
+mod keymap;
+
+mod options;
+use options::Options;
+
+mod shortcuts;
+use shortcuts::{DebugAction, DebugShortcuts, Modifier};
+
+#[cfg(feature = "tui-debug")]
+mod tui;
+
+#[cfg(feature = "audio")]
+mod rodio_audio;
+
+use chip8rs::Chip8;
+use chip8rs::emu::{Chip8Event, Frame, FrameSnapshot, KeyRepeat, KeySampling, Opcode, Quirks, Rom, SaveState, Warning};
+use chip8rs::emu::audio::{self, AudioBackend, FallbackAudio};
+use chip8rs::emu::clock;
+use chip8rs::emu::replay::{self, Recording};
+use chip8rs::emu::repro::Repro;
+use chip8rs::emu::script::InputScript;
+use chip8rs::emu::{self, disasm, instruction, minimize};
+
+use std::convert::TryFrom;
 use std::env;
-use std::fs::File;
-use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
 
 const SCALE: u32 = 2;
 const SCALING_FACTOR: u32 = SCALE * 4;
@@ -24,58 +52,144 @@ const SCREEN_HEIGHT: u32 = HEIGHT * SCALING_FACTOR;
 const STEP_BY_ONE: bool = false;
 const DEBUG_MSG: bool = false;
 
-// Map keys to which key register will hold them (the array index).
-const KEYS: [Key; 16] = [
-    Key::D1, Key::D2, Key::D3, Key::D4,
-    Key::Q, Key::W, Key::E, Key::R,
-    Key::A, Key::S, Key::D, Key::F,
-    Key::Z, Key::X, Key::C, Key::V
+// Most repaints per second with --throttle-draws.
+const REPAINT_HZ: u32 = 60;
+
+// Size and spacing in window pixels of the squares in the F5 key overlay.
+const KEYPAD_CELL: f64 = 16.0;
+const KEYPAD_MARGIN: f64 = 4.0;
+
+// Where the machine state is written when emulation panics.
+const CRASH_REPORT: &str = "chip8rs-crash.txt";
+
+// How long --verify-determinism runs for when --cycles is not given.
+const DETERMINISM_CYCLES: u64 = 100_000;
+
+// How long each try of --minimize-crash runs for when --cycles is not given,
+// the same as the fuzz target.
+const MINIMIZE_CYCLES: u64 = 10_000;
+
+// What --benchmark runs: draws every glyph across the screen while doing
+// some arithmetic, forever.
+const BENCHMARK_ROM: &[u8] = &[
+    0x60, 0x00, // LD V0, #00
+    0x61, 0x00, // LD V1, #00
+    0xF0, 0x29, // LD F, V0
+    0xD0, 0x15, // DRW V0, V1, 5
+    0x70, 0x01, // ADD V0, #01
+    0x81, 0x04, // ADD V1, V0
+    0x82, 0x13, // XOR V2, V1
+    0x12, 0x04, // JP #204
 ];
 
-fn main() {
+// Instructions run between looks at the clock during --benchmark.
+const BENCHMARK_BATCH: u64 = 10_000;
 
-    let mut should_step = STEP_BY_ONE;
-    let mut should_debug = DEBUG_MSG;
+fn main() {
 
     let args: Vec<String> = env::args().collect();
-    if args.len() < 2 || args.len() > 4 {
-        // Weird number of command line args.
-        println!("Usage: {} <romfile> [stepbyone=1|0] [debug=1|0]", &args[0]);
+    if args.iter().skip(1).any(|arg| arg == "--help" || arg == "-h") {
+        println!("{}", options::help(&args[0]));
         return;
     }
 
-    if args.len() > 2 {
-        if let Ok(by_one) = (&args[2]).parse::<bool>() {
-            should_step = by_one;
-        } else {
-            // We weren't given a bool show usage and return.
-            println!("Usage: {} <romfile> [stepbyone=1|0] [debug=1|0]", &args[0]);
-            return;
+    let opts = match Options::parse(&args, STEP_BY_ONE, DEBUG_MSG) {
+        Ok(opts) => opts,
+        Err(e) => {
+            println!("[-] {}", e);
+            println!("{}", options::usage(&args[0]));
+            process::exit(1);
         }
+    };
+
+    let should_step = opts.step_by_one;
+
+    if let Some((a, b)) = &opts.diff_states {
+        diff_states(a, b);
+        return;
     }
 
-    if args.len() == 3 {
-        if let Ok(debug_msg) = (&args[3]).parse::<bool>() {
-            should_debug = debug_msg;
-        } else {
-            // We weren't given a bool show usage and return.
-            println!("Usage: {} <romfile> [stepbyone=1|0] [debug=1|0]", &args[0]);
-            return;
-        }
+    if let Some(seconds) = opts.benchmark {
+        benchmark(seconds, &opts);
+        return;
+    }
+
+    if let Some(path) = &opts.load_repro {
+        load_repro(path, &opts);
+        return;
     }
 
+    if opts.dump_font {
+        let c8 = Chip8::new();
+        for digit in 0..16 {
+            println!("{:X}:\n{}", digit, c8.glyph_to_ascii(digit));
+        }
+        return;
+    }
 
     println!("Loading memory into emulator...");
 
-    // Load game ROM into buffer.
-    let mut rom = [0;4096 - 0x200];
+    let mut rom = match Rom::from_path_limited(&opts.rom_path, opts.max_rom_size) {
+        Ok(rom) => rom,
+        Err(e) => {
+            println!("[-] ROM couldn't be loaded: {}", e);
+            process::exit(1);
+        }
+    };
+    println!("ROM loaded!");
 
-    let mut rom_file = File::open(&args[1]).expect("File not found");
+    if let Some(path) = &opts.replay {
+        run_replay(path, &rom, &opts);
+        return;
+    }
 
-    if let Ok(_) = rom_file.read(&mut rom) {
-        println!("ROM loaded!");
-    } else {
-        println!("[-] ROM couldn't be loaded.");
+    if opts.compat_check {
+        compat_check(&rom, &opts);
+        return;
+    }
+
+    if opts.minimize_crash {
+        minimize_crash(&rom, &opts);
+        return;
+    }
+
+    if opts.verify_determinism {
+        if !verify_determinism(&rom, &opts) {
+            process::exit(1);
+        }
+        return;
+    }
+
+    if opts.headless {
+        run_headless(&rom, &opts);
+        return;
+    }
+
+    if opts.tui {
+        run_tui(&rom, &opts);
+        return;
+    }
+
+    if let Some((start, end)) = opts.disasm_range {
+        let mut c8 = Chip8::new();
+        c8.load_rom(&rom);
+        report_warnings(&mut c8);
+
+        let len = (end - start) as usize + 1;
+        print!("{}", disasm::annotated_disassembly(&c8, start, len));
+        return;
+    }
+
+    if let Some(needle) = &opts.find {
+        let mut c8 = Chip8::new();
+        c8.load_rom(&rom);
+        report_warnings(&mut c8);
+
+        let found = c8.find_bytes(needle);
+        for addr in found.iter() {
+            println!("{:#05X}", addr);
+        }
+        println!("{} matches.", found.len());
         return;
     }
 
@@ -83,10 +197,20 @@ fn main() {
     let mut window: PistonWindow = WindowSettings::new(
         "Chip8",
         (SCREEN_WIDTH, SCREEN_HEIGHT)
-    ).exit_on_esc(true).build().unwrap();
+    ).exit_on_esc(true).resizable(true).build().unwrap();
+
+    // One update per timer tick, each runs a frame's worth of instructions.
+    // After a stall piston skips the updates past the cap rather than running them all.
+    window.set_ups(opts.timer_hz as u64);
+    window.set_ups_reset(clock::MAX_CATCH_UP_FRAMES as u64);
+
+    // Pixels are either expanded on the CPU or drawn at native size and scaled by the GPU.
+    let buf_scale = if opts.gpu_scale { 1 } else { SCALING_FACTOR };
+    let buf_width = WIDTH * buf_scale;
+    let buf_height = HEIGHT * buf_scale;
 
     // Buffer for drawing
-    let mut draw_buf = im::ImageBuffer::new(SCREEN_WIDTH, SCREEN_HEIGHT);
+    let mut draw_buf = im::ImageBuffer::new(buf_width, buf_height);
     let mut texture_context = TextureContext {
         factory: window.factory.clone(),
         encoder: window.factory.create_command_buffer().into()
@@ -94,95 +218,1337 @@ fn main() {
     let mut texture: G2dTexture = Texture::from_image(
         &mut texture_context,
         &draw_buf,
-        &TextureSettings::new()
+        &TextureSettings::new().filter(Filter::Nearest)
     ).unwrap();
 
     let mut fps_cnt = fps_counter::FPSCounter::new();
 
     // Create a new chip8 emulator
     let mut c8 = Chip8::new();
-    c8.show_debug = should_debug;
+    configure(&mut c8, &opts);
     c8.load_rom(&rom);
+    load_start_state(&mut c8, &opts);
+    c8.paused = opts.start_paused;
+
+    if opts.coverage.is_some() {
+        c8.enable_coverage();
+    }
+    if opts.profile {
+        c8.enable_profiling();
+    }
+
+    let ipf = c8.cycles_per_timer_tick();
+
+    // With --show-cycles the title also measures instructions per second, once a second.
+    let mut ips_sample = (Instant::now(), c8.cycles());
+    let mut ips = 0;
+
+    let mut sound = FallbackAudio::new(audio_backend());
+    let mut tuning = 0; // Ctrl + and Ctrl - move the pitch, see `audio::tuned_pitch`.
+
+    // A reproduction is built from a recording too.
+    let mut recording = if opts.record.is_some() || opts.save_repro.is_some() {
+        Some(Recording::start(&c8, ipf, replay::DEFAULT_CHECKPOINT_INTERVAL))
+    } else {
+        None
+    };
+
+    // Modifier state and half typed input for the single step shortcuts.
+    let mut ctrl_held = false;
+    let mut alt_held = false;
+    let mut shortcuts = DebugShortcuts::new();
+
+    // F2 toggles the scanlines, which means painting every pixel again.
+    let mut scanlines = opts.scanlines;
+    let mut repaint = false;
+
+    // F5 shows which CHIP-8 keys are held, in the top left corner.
+    let mut show_keypad = false;
+
+    // PageDown and PageUp swap to the ROMs beside this one.
+    let mut rom_path = PathBuf::from(&opts.rom_path);
+
+    // With --throttle-draws the picture is only painted from gfx, at most REPAINT_HZ times a second.
+    let repaint_interval = Duration::from_secs(1) / REPAINT_HZ;
+    let mut last_repaint = Instant::now();
+    let mut dirty = false;
 
     while let Some(event) = window.next() {
-        if let Some(_) = event.render_args() {
+        if let Some(render_args) = event.render_args() {
+
+            let (scale, offset_x, offset_y) = fit_to_window(render_args.window_size, buf_width, buf_height);
+
+            // Nothing is presented at all until the ROM draws, with --blank-until-draw.
+            if !opts.blank_until_draw || c8.has_drawn() {
+                texture.update(&mut texture_context, &draw_buf).unwrap();
+                window.draw_2d(&event, |context, graphics, device| {
+                    texture_context.encoder.flush(device);
+                    clear([0.0, 0.0, 0.0, 1.0], graphics);
 
-            texture.update(&mut texture_context, &draw_buf).unwrap();
-            window.draw_2d(&event, |context, graphics, device| {
-                texture_context.encoder.flush(device);
-                clear([0.0, 0.0, 0.0, 1.0], graphics);
+                    let transform = context.transform.trans(offset_x, offset_y).scale(scale, scale);
+                    image(&texture, transform, graphics);
 
-                image(&texture, context.transform, graphics);
-            });
+                    if show_keypad {
+                        for key_index in 0..16 {
+                            let (col, row) = keypad_cell(key_index);
+                            let color = if c8.is_key_down(key_index) { [1.0, 1.0, 1.0, 0.8] } else { [0.5, 0.5, 0.5, 0.4] };
+                            let x = KEYPAD_MARGIN + col as f64 * (KEYPAD_CELL + KEYPAD_MARGIN);
+                            let y = KEYPAD_MARGIN + row as f64 * (KEYPAD_CELL + KEYPAD_MARGIN);
+
+                            rectangle(color, [x, y, KEYPAD_CELL, KEYPAD_CELL], context.transform, graphics);
+                        }
+                    }
+                });
+            }
 
             let fps = fps_cnt.tick();
-            let title = format!("Chip8-rs {}FPS", fps);
+            if ips_sample.0.elapsed() >= Duration::from_secs(1) {
+                let elapsed = ips_sample.0.elapsed().as_secs_f64();
+                ips = ((c8.cycles() - ips_sample.1) as f64 / elapsed) as u64;
+                ips_sample = (Instant::now(), c8.cycles());
+            }
+            let cycles = if opts.show_cycles { Some((ips, c8.cycles())) } else { None };
+
+            let title = if let Some((pc, opcode)) = c8.paused_on_unknown() {
+                format!("Chip8-rs PAUSED: unknown opcode {:04X} at {:03X} (Return to skip)", opcode, pc)
+            } else if let Some(pc) = c8.pc_overrun() {
+                format!("Chip8-rs STOPPED: PC ran past the end of memory to {:04X}", pc)
+            } else if c8.paused {
+                "Chip8-rs PAUSED (Return to continue)".to_string()
+            } else if should_step {
+                step_title(&c8)
+            } else if c8.show_debug {
+                format!("{} [{}]", running_title(fps, cycles), c8.quirks.summary())
+            } else {
+                format!("{} [sprites: {}] (F3 toggles)", running_title(fps, cycles), if c8.quirks.wraps_sprites() { "wrap" } else { "clip" })
+            };
             window.set_title(title);
         } // end renger_args
 
-        if let Some(_) = event.update_args() {
-            if !should_step {
-                c8.cycle();
+        if event.update_args().is_some() && !should_step {
+            guarded(&mut c8, |c8| c8.advance_frame(ipf));
+            report_warnings(&mut c8);
+
+            // FallbackAudio deals with failures itself.
+            let _ = sound.set_pattern(c8.audio_pattern(), audio::tuned_pitch(c8.pitch(), tuning));
+            let _ = sound.set_tone(c8.sound_active());
+
+            if let Some(rec) = recording.as_mut() {
+                rec.checkpoint(&c8);
             }
         } // end update_args
 
+        if event.focus_args() == Some(false) {
+            for key_index in c8.on_focus_lost() {
+                if let Some(rec) = recording.as_mut() {
+                    rec.record_key(c8.cycles(), key_index, false);
+                }
+            }
+            ctrl_held = false;
+            alt_held = false;
+            shortcuts.cancel();
+        }
+
         if let Some(button_args) = event.button_args() {
 
             // Check if it was a key press.
             if let Button::Keyboard(key) = button_args.button {
 
-                // Check if it's a key we care about.
-                if let Some(key_index) = KEYS.iter().position(|&x| x == key) {
+                let pressed = button_args.state == ButtonState::Press;
+
+                // Track modifiers for the single step shortcuts.
+                match key {
+                    Key::LCtrl | Key::RCtrl => ctrl_held = pressed,
+                    Key::LAlt | Key::RAlt => {
+                        alt_held = pressed;
+                        shortcuts.cancel();
+                    },
+                    _ => {},
+                }
+
+                let modifier = if ctrl_held {
+                    Some(Modifier::Ctrl)
+                } else if alt_held {
+                    Some(Modifier::Alt)
+                } else {
+                    None
+                };
+                let shortcut = modifier
+                    .filter(|_| should_step)
+                    .and_then(|modifier| shortcuts::hex_digit(key).map(|digit| (modifier, digit)));
+
+                if let Some((modifier, digit)) = shortcut {
+                    let action = if pressed { shortcuts.on_digit(modifier, digit) } else { None };
+
+                    match action {
+                        Some(DebugAction::ToggleKey(key_index)) => {
+                            let key_index = key_index as usize;
+                            let pressed = !c8.is_key_down(key_index);
+                            if pressed {
+                                c8.key_pressed(key_index);
+                            } else {
+                                c8.key_released(key_index);
+                            }
+
+                            if let Some(rec) = recording.as_mut() {
+                                rec.record_key(c8.cycles(), key_index, pressed);
+                            }
+                            println!("Key {:X} {}", key_index, if pressed { "down" } else { "up" });
+                        },
+                        Some(DebugAction::SetRegister(reg, value)) => {
+                            c8.set_register(reg as usize, value);
+                            println!("V{:X} = {:#04X}", reg, value);
+                        },
+                        None => {},
+                    }
+                } else if let Some(key_index) = opts.keys.iter().position(|&x| x == key) {
 
                     // Set/unset keystate based on press/release.
-                    if button_args.state == ButtonState::Press {
+                    if pressed {
                         c8.key_pressed(key_index);
                     } else {
                         c8.key_released(key_index);
                     }
+
+                    if let Some(rec) = recording.as_mut() {
+                        rec.record_key(c8.cycles(), key_index, pressed);
+                    }
+                } else if key == Key::F2 {
+                    if pressed {
+                        scanlines = !scanlines;
+                        repaint = true;
+                    }
+                } else if key == Key::F3 {
+                    // Takes effect from the next draw, repaint so the change is seen straight away.
+                    if pressed {
+                        let wrap = !c8.quirks.wraps_sprites();
+                        c8.quirks.set_wrap_sprites(wrap);
+                        repaint = true;
+                        println!("Sprites now {}", if wrap { "wrap" } else { "clip" });
+                    }
+                } else if key == Key::F4 {
+                    if pressed {
+                        export_disassembly(&c8, rom.len(), Path::new("."));
+                    }
+                } else if key == Key::F5 {
+                    if pressed {
+                        show_keypad = !show_keypad;
+                    }
+                } else if key == Key::F6 {
+                    if pressed {
+                        let height = sprite_height(&c8);
+                        println!("Sprite at I ({:03X}), {} rows:\n{}", c8.index(), height, c8.sprite_ascii(height));
+                    }
+                } else if key == Key::PageDown || key == Key::PageUp {
+                    if pressed {
+                        match browse::neighbour(&rom_path, key == Key::PageDown) {
+                            Ok(Some(path)) => match Rom::from_path_limited(&path, opts.max_rom_size) {
+                                Ok(next) => {
+                                    c8.load_rom_and_reset(&next);
+                                    report_warnings(&mut c8);
+                                    rom = next;
+                                    rom_path = path;
+                                    repaint = true;
+                                    println!("Loaded {}", rom_path.display());
+
+                                    // What was recorded belongs to the old ROM, start over with this one.
+                                    if let Some(rec) = recording.as_mut() {
+                                        *rec = Recording::start(&c8, ipf, replay::DEFAULT_CHECKPOINT_INTERVAL);
+                                    }
+                                },
+                                Err(e) => println!("[-] ROM couldn't be loaded: {}", e),
+                            },
+                            Ok(None) => println!("[!] No other ROMs beside {}", rom_path.display()),
+                            Err(e) => println!("[-] Couldn't list the ROMs beside {}: {}", rom_path.display(), e),
+                        }
+                    }
+                } else if ctrl_held && (key == Key::Equals || key == Key::Minus) {
+                    if pressed {
+                        let step = if key == Key::Equals { audio::TUNING_STEP } else { -audio::TUNING_STEP };
+                        tuning = audio::clamp_tuning(tuning + step);
+
+                        let pitch = audio::tuned_pitch(c8.pitch(), tuning);
+                        println!("Audio tuning {:+} ({:.0} bits/s)", tuning, audio::playback_rate(pitch));
+                    }
+                } else if key == Key::Return && c8.paused {
+                    if button_args.state == ButtonState::Press {
+                        c8.paused = false;
+                    }
+                } else if key == Key::Return && c8.paused_on_unknown().is_some() {
+                    if button_args.state == ButtonState::Press {
+                        c8.resume_from_unknown();
+                    }
                 } else if key == Key::Return && should_step {
-                    c8.cycle();
+                    guarded(&mut c8, |c8| c8.cycle_in_frame(ipf));
+                    report_warnings(&mut c8);
+
+                    if let Some(rec) = recording.as_mut() {
+                        rec.checkpoint(&c8);
+                    }
                 }
             }
 
         } // end button_args
 
-        if c8.redraw {
-            draw_buf = im::ImageBuffer::new(SCREEN_WIDTH, SCREEN_HEIGHT);
-            c8.redraw = false;
+        if opts.throttle_draws {
+            if c8.drain_events().count() > 0 {
+                dirty = true;
+            }
+            if dirty && last_repaint.elapsed() >= repaint_interval {
+                repaint = true;
+                dirty = false;
+                last_repaint = Instant::now();
+            }
+        }
+
+        if repaint {
+            for (y, row) in c8.gfx.iter().enumerate() {
+                for (x, &on) in row.iter().enumerate() {
+                    paint_pixel(&mut draw_buf, x as u32, y as u32, on, buf_scale, scanlines);
+                }
+            }
+            repaint = false;
         }
 
-        // Draw pixels from queue
-        while !c8.draw_queue.is_empty() {
+        // Paint the changes as they come, when throttling these were already drained.
+        for event in c8.drain_events() {
+            match event {
+                Chip8Event::ClearScreen => draw_buf = im::ImageBuffer::new(buf_width, buf_height),
+                Chip8Event::Draw { x, y, on } => paint_pixel(&mut draw_buf, x as u32, y as u32, on as u8, buf_scale, scanlines),
+                Chip8Event::BeepStart | Chip8Event::BeepStop => {},
+            }
+        }
+    }
 
-            if let Some((x, y, to_draw)) = c8.draw_queue.pop_front() {
+    if let (Some(path), Some(rec)) = (&opts.record, &recording) {
+        let written = File::create(path)
+            .and_then(|file| rec.write_to(&mut BufWriter::new(file)));
 
-                let dx = x as u32 * SCALING_FACTOR;
-                let dy = y as u32 * SCALING_FACTOR;
+        match written {
+            Ok(_) => println!("Recording saved to {}", path),
+            Err(e) => println!("[-] Couldn't save recording: {}", e),
+        }
+    }
 
-                for ry in dy..(dy + SCALING_FACTOR) {
-                    for rx in dx..(dx + SCALING_FACTOR) {
-                        draw_buf.put_pixel(rx, ry,
-                            if to_draw == 1 {
-                                im::Rgba([255,255,255,255])
-                            } else {
-                                im::Rgba([0,0,0,255])
-                            }
-                        );
-                    }
-                }
-            }
-            /*if let Some((x, y, val)) = c8.draw_queue.pop_front() {
-              draw_buf.put_pixel(x as u32, y as u32,
-              if val == 0 {
-              im::Rgba([0,0,0,255])
-              } else {
-              im::Rgba([255,255,255,255])
-              }
-              );
-              }*/
+    if let (Some(path), Some(rec)) = (&opts.save_repro, &recording) {
+        if opts.load_state.is_some() {
+            println!("[!] Started from a saved state, the reproduction replays from the top of the ROM instead.");
+        }
+
+        let written = File::create(path)
+            .and_then(|file| Repro::capture(&rom, rec, &c8).write_to(&mut BufWriter::new(file)));
+
+        match written {
+            Ok(_) => println!("Reproduction saved to {}", path),
+            Err(e) => println!("[-] Couldn't save reproduction: {}", e),
         }
     }
 
+    save_outputs(&c8, &opts, rom.len());
+
     println!("Exited...");
 }
+
+#[cfg(feature = "audio")]
+fn audio_backend() -> Box<dyn AudioBackend> {
+    Box::new(rodio_audio::RodioAudio::new())
+}
+
+#[cfg(not(feature = "audio"))]
+fn audio_backend() -> Box<dyn AudioBackend> {
+    Box::new(chip8rs::emu::audio::NullAudio)
+}
+
+// Fill in the block of the draw buffer that CHIP-8 pixel (x, y) scales up to.
+fn paint_pixel(buf: &mut im::RgbaImage, x: u32, y: u32, on: u8, buf_scale: u32, scanlines: bool) {
+    let color = if on == 1 { [255, 255, 255, 255] } else { [0, 0, 0, 255] };
+
+    for ry in y * buf_scale..(y + 1) * buf_scale {
+        for rx in x * buf_scale..(x + 1) * buf_scale {
+            buf.put_pixel(rx, ry, im::Rgba(scanline_shade(color, ry, buf_scale, scanlines)));
+        }
+    }
+}
+
+// Darken every other row of the scaled output like the gaps on a CRT. A buffer
+// at native resolution has no rows to spare, so it's left alone.
+fn scanline_shade(color: [u8; 4], row: u32, buf_scale: u32, scanlines: bool) -> [u8; 4] {
+    if !scanlines || buf_scale == 1 || row.is_multiple_of(2) {
+        return color;
+    }
+
+    [color[0] / 2, color[1] / 2, color[2] / 2, color[3]]
+}
+
+// Start of the title while running, `cycles` is the measured instructions
+// per second and the total executed when --show-cycles is on.
+fn running_title(fps: usize, cycles: Option<(u64, u64)>) -> String {
+    match cycles {
+        Some((ips, total)) => format!("Chip8-rs {}FPS {}IPS {} instructions", fps, ips, total),
+        None => format!("Chip8-rs {}FPS", fps),
+    }
+}
+
+// While single stepping the title shows the instruction Return will run next.
+fn step_title(c8: &Chip8) -> String {
+    let pc = c8.pc();
+    let opcode = c8.fetch();
+
+    format!("Chip8-rs STEP {:03X}: {:04X} {}", pc, opcode, disasm::disassemble(opcode))
+}
+
+// How many rows F6 shows: N of the DXYN about to run, else the tallest
+// CHIP-8 sprite.
+fn sprite_height(c8: &Chip8) -> u8 {
+    let next = Opcode(c8.fetch());
+
+    if next.family() == 0xD { next.n() } else { 15 }
+}
+
+// Column and row of a key in the F5 overlay, laid out like the host keys in `keymap`.
+fn keypad_cell(key_index: usize) -> (u32, u32) {
+    (key_index as u32 % 4, key_index as u32 / 4)
+}
+
+// Largest uniform scale that fits a width x height frame in the window,
+// plus the offset that centers it, so the aspect ratio is kept on resize.
+fn fit_to_window(window_size: [f64; 2], width: u32, height: u32) -> (f64, f64, f64) {
+    let scale = (window_size[0] / width as f64).min(window_size[1] / height as f64);
+    let offset_x = (window_size[0] - width as f64 * scale) / 2.0;
+    let offset_y = (window_size[1] - height as f64 * scale) / 2.0;
+
+    (scale, offset_x, offset_y)
+}
+
+// Apply the emulation settings from the command line.
+fn configure(c8: &mut Chip8, opts: &Options) {
+    if opts.capture.is_some() || opts.capture_ppm.is_some() || opts.film_strip.is_some() {
+        c8.enable_frame_capture();
+    }
+
+    c8.show_debug = opts.debug;
+    if let Some(path) = &opts.trace {
+        match File::create(path) {
+            Ok(file) if opts.trace_json => c8.set_json_trace(Box::new(BufWriter::new(file))),
+            Ok(file) => {
+                c8.set_debug_output(Box::new(BufWriter::new(file)));
+                c8.show_debug = true;
+            },
+            Err(e) => println!("[-] Couldn't create trace {}: {}", path, e),
+        }
+    }
+    if opts.audit_vf.is_some() {
+        c8.enable_flag_audit();
+    }
+    c8.ips = opts.ips;
+    c8.timer_hz = opts.timer_hz;
+    c8.quirks = applied_quirks(opts);
+    c8.pause_on_unknown = opts.pause_on_unknown;
+    c8.strict_pc = opts.strict_pc;
+    c8.check_targets = opts.check_targets;
+    c8.coalesce_events = opts.coalesce_draws;
+    c8.pause_on_focus_lost = opts.pause_on_focus_lost;
+    // Reproductions carry the random numbers along too.
+    if opts.record_rng.is_some() || opts.save_repro.is_some() {
+        c8.enable_rng_log();
+    }
+    if let Some(path) = &opts.replay_rng {
+        match fs::read(path) {
+            Ok(numbers) => c8.replay_rng(numbers),
+            Err(e) => println!("[-] Couldn't load random numbers from {}: {}", path, e),
+        }
+    }
+    c8.key_order = opts.key_order;
+    if opts.key_pulse {
+        c8.key_repeat = KeyRepeat::Pulse;
+    }
+    if opts.sample_keys_per_frame {
+        c8.key_sampling = KeySampling::PerFrame;
+    }
+
+    println!("Quirks: {}", c8.quirks.summary());
+}
+
+// The preset from the command line with the quirk flags on top of it.
+fn applied_quirks(opts: &Options) -> Quirks {
+    let mut quirks = opts.preset;
+    quirks.wrap_x |= opts.wrap_sprites || opts.wrap_x;
+    quirks.wrap_y |= opts.wrap_sprites || opts.wrap_y;
+    quirks.xo_chip |= opts.xo_chip;
+    quirks.schip |= opts.schip;
+    quirks.shift_vy |= opts.shift_vy;
+    quirks.jump_vx |= opts.jump_vx;
+    quirks.load_store_increment |= opts.load_store_increment;
+    quirks.vf_reset |= opts.vf_reset;
+    quirks.display_wait |= opts.display_wait;
+    quirks.clamp_index |= opts.clamp_index;
+    quirks.half_pixel_scroll |= opts.half_pixel_scroll;
+    if let Some(policy) = opts.stack_policy {
+        quirks.stack = policy;
+    }
+    quirks
+}
+
+// Run some emulation, and if it panics dump the machine state to stderr and
+// CRASH_REPORT before letting the panic carry on.
+fn guarded<F: FnOnce(&mut Chip8)>(c8: &mut Chip8, f: F) {
+    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| f(c8))) {
+        let report = c8.crash_report();
+        eprintln!("[-] Emulation crashed, machine state:\n{}", report);
+        match fs::write(CRASH_REPORT, &report) {
+            Ok(_) => eprintln!("Crash report saved to {}", CRASH_REPORT),
+            Err(e) => eprintln!("[-] Couldn't save crash report: {}", e),
+        }
+        panic::resume_unwind(payload);
+    }
+}
+
+fn report_warnings(c8: &mut Chip8) {
+    for warning in c8.take_warnings() {
+        println!("[!] {}", warning);
+    }
+}
+
+// Write whatever end of run reports were asked for.
+fn save_outputs(c8: &Chip8, opts: &Options, rom_len: usize) {
+    if let Some(path) = &opts.coverage {
+        let listing = disasm::annotated_disassembly(c8, emu::PROGRAM_START as u16, rom_len);
+        save_text(path, "Coverage", &listing);
+    }
+
+    if let Some(profile) = c8.profile() {
+        println!("Time per opcode family ({:?} total):", profile.total_time());
+        print!("{}", profile);
+
+        if let Some(path) = &opts.profile_output {
+            save_text(path, "Profile", &profile.to_csv());
+        }
+    }
+
+    if let Some(path) = &opts.svg {
+        save_text(path, "Screenshot", &c8.to_svg("#FFFFFF", "#000000"));
+    }
+
+    if let Some(path) = &opts.audit_vf {
+        let log: String = c8.flag_audit().iter().map(|access| format!("{}\n", access)).collect();
+        save_text(path, "Flag audit", &log);
+    }
+
+    if let Some(dir) = &opts.capture {
+        save_frames(dir, c8.captured_frames());
+    }
+
+    if let Some(dir) = &opts.capture_ppm {
+        save_ppm_frames(dir, c8.captured_frames());
+    }
+
+    if let Some(count) = opts.film_strip {
+        print!("{}", emu::film_strip(c8.captured_frames(), count));
+    }
+
+    if let Some(path) = &opts.record_rng {
+        match fs::write(path, c8.rng_log()) {
+            Ok(_) => println!("{} random numbers saved to {}", c8.rng_log().len(), path),
+            Err(e) => println!("[-] Couldn't save random numbers: {}", e),
+        }
+    }
+
+    if let Some(path) = &opts.save_state {
+        match File::create(path).and_then(|file| c8.save_state().write_to(&mut BufWriter::new(file))) {
+            Ok(_) => println!("State saved to {}", path),
+            Err(e) => println!("[-] Couldn't save state: {}", e),
+        }
+    }
+}
+
+// Save the disassembly of the ROM as it is in memory right now, with
+// execution counts when coverage is on, to a file in `dir` named after the time.
+fn export_disassembly(c8: &Chip8, rom_len: usize, dir: &Path) -> PathBuf {
+    let stamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let path = dir.join(format!("disassembly-{}.txt", stamp));
+    let listing = disasm::annotated_disassembly(c8, emu::PROGRAM_START as u16, rom_len);
+    save_text(&path.to_string_lossy(), "Disassembly", &listing);
+    path
+}
+
+fn load_state_file(path: &str) -> Option<SaveState> {
+    match File::open(path).and_then(|file| SaveState::read_from(&mut BufReader::new(file))) {
+        Ok(state) => Some(state),
+        Err(e) => {
+            println!("[-] Couldn't load state {}: {}", path, e);
+            None
+        }
+    }
+}
+
+fn load_input_script(path: &str) -> Option<InputScript> {
+    let parsed = fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|text| InputScript::parse(&text));
+
+    match parsed {
+        Ok(script) => Some(script),
+        Err(e) => {
+            println!("[-] Couldn't load input script {}: {}", path, e);
+            None
+        }
+    }
+}
+
+// Carry on from --load-state rather than the start of the ROM.
+fn load_start_state(c8: &mut Chip8, opts: &Options) {
+    if let Some(path) = &opts.load_state {
+        match load_state_file(path) {
+            Some(state) => {
+                c8.load_state(&state);
+                println!("Resuming from {} at cycle {}", path, c8.cycles());
+            },
+            None => process::exit(1),
+        }
+    }
+}
+
+fn diff_states(path_a: &str, path_b: &str) {
+    let (a, b) = match (load_state_file(path_a), load_state_file(path_b)) {
+        (Some(a), Some(b)) => (a, b),
+        _ => process::exit(1),
+    };
+
+    let diffs = a.diff(&b);
+    for diff in diffs.iter() {
+        println!("{}", diff);
+    }
+    println!("{} differences.", diffs.len());
+}
+
+// A core frame snapshot as an image the image crate can save.
+fn snapshot_image(snapshot: FrameSnapshot) -> im::RgbaImage {
+    // from_raw only fails when the buffer is the wrong size, which a snapshot never is.
+    im::ImageBuffer::from_raw(snapshot.width, snapshot.height, snapshot.pixels).unwrap()
+}
+
+fn save_frames(dir: &str, frames: &[Frame]) {
+    if let Err(e) = fs::create_dir_all(dir) {
+        println!("[-] Couldn't create {}: {}", dir, e);
+        return;
+    }
+
+    for (i, frame) in frames.iter().enumerate() {
+        let img = snapshot_image(FrameSnapshot::from_frame(frame, SCALING_FACTOR));
+
+        let path = Path::new(dir).join(format!("frame_{:05}.png", i));
+        if let Err(e) = img.save(&path) {
+            println!("[-] Couldn't save {}: {}", path.display(), e);
+            return;
+        }
+    }
+
+    println!("{} frames saved to {}", frames.len(), dir);
+}
+
+// Like `save_frames`, as PPMs at native resolution for tools that don't read PNG.
+fn save_ppm_frames(dir: &str, frames: &[Frame]) {
+    if let Err(e) = fs::create_dir_all(dir) {
+        println!("[-] Couldn't create {}: {}", dir, e);
+        return;
+    }
+
+    for (i, frame) in frames.iter().enumerate() {
+        let path = Path::new(dir).join(format!("frame_{:05}.ppm", i));
+        if let Err(e) = fs::write(&path, emu::frame_to_ppm(frame)) {
+            println!("[-] Couldn't save {}: {}", path.display(), e);
+            return;
+        }
+    }
+
+    println!("{} frames saved to {}", frames.len(), dir);
+}
+
+fn save_text(path: &str, what: &str, text: &str) {
+    match File::create(path).and_then(|mut file| file.write_all(text.as_bytes())) {
+        Ok(_) => println!("{} saved to {}", what, path),
+        Err(e) => println!("[-] Couldn't save {}: {}", what.to_lowercase(), e),
+    }
+}
+
+// Run BENCHMARK_ROM flat out for `seconds` and print how fast it went, in
+// instructions per second.
+fn benchmark(seconds: f64, opts: &Options) -> f64 {
+    let rom = Rom::try_from(BENCHMARK_ROM).unwrap();
+    let mut c8 = Chip8::with_seed(0);
+    configure(&mut c8, opts);
+    c8.load_rom(&rom);
+
+    let duration = Duration::from_secs_f64(seconds);
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        for _ in 0..BENCHMARK_BATCH {
+            c8.cycle();
+        }
+        c8.drain_events().for_each(drop);
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    let rate = c8.cycles() as f64 / elapsed;
+    println!("Benchmark: {} instructions in {:.2}s, {:.0} instructions per second", c8.cycles(), elapsed, rate);
+    rate
+}
+
+// Run the ROM twice from the same seed and make sure nothing outside of the
+// emulator leaked into either run.
+fn verify_determinism(rom: &Rom, opts: &Options) -> bool {
+    let cycles = opts.cycles.unwrap_or(DETERMINISM_CYCLES);
+    let seed = rand::random();
+
+    let mut runs = Vec::new();
+    for _ in 0..2 {
+        let mut c8 = Chip8::with_seed(seed);
+        configure(&mut c8, opts);
+        c8.load_rom(rom);
+
+        let ipf = c8.cycles_per_timer_tick();
+        while c8.cycles() < cycles && !c8.stopped() {
+            guarded(&mut c8, |c8| c8.cycle_in_frame(ipf));
+        }
+        report_warnings(&mut c8);
+
+        runs.push(c8.save_state());
+    }
+
+    let diffs = runs[0].diff(&runs[1]);
+    if diffs.is_empty() && runs[0] == runs[1] {
+        println!("PASS: both runs matched after {} cycles (seed {:#X}).", runs[0].cycles, seed);
+        return true;
+    }
+
+    println!("[-] FAIL: runs with seed {:#X} diverged after {} cycles.", seed, cycles);
+    for diff in diffs.iter() {
+        println!("{}", diff);
+    }
+    false
+}
+
+// Shrink a ROM that crashes the emulator, keeping the same kind of crash, and
+// print the result ready to paste into a bug report or test.
+fn minimize_crash(rom: &Rom, opts: &Options) {
+    let quirks = applied_quirks(opts);
+    let cycles = opts.cycles.unwrap_or(MINIMIZE_CYCLES);
+
+    // Every try that crashes would print the panic otherwise.
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+
+    let crash = crash_message(rom.bytes(), quirks, opts.ips, cycles);
+    let minimized = crash.as_ref().map(|message| {
+        let kind = crash_kind(message).to_string();
+        minimize::minimize(rom.bytes(), |program| {
+            crash_message(program, quirks, opts.ips, cycles).is_some_and(|other| crash_kind(&other) == kind)
+        })
+    });
+
+    panic::set_hook(hook);
+
+    match (crash, minimized) {
+        (Some(message), Some(program)) => {
+            println!("Crash: {}", message);
+            println!("Minimized from {} to {} bytes:", rom.len(), program.len());
+            println!("{}", minimize::program_snippet(&program));
+        },
+        _ => {
+            println!("[-] The ROM didn't crash within {} cycles, nothing to minimize.", cycles);
+            process::exit(1);
+        },
+    }
+}
+
+// What the emulator panicked with running `program` from a fixed seed, if it did.
+fn crash_message(program: &[u8], quirks: Quirks, ips: u32, cycles: u64) -> Option<String> {
+    let rom = Rom::try_from(program).ok()?;
+
+    let payload = panic::catch_unwind(|| {
+        let mut c8 = Chip8::with_seed(0);
+        c8.ips = ips;
+        c8.quirks = quirks;
+        c8.load_rom(&rom);
+
+        let ipf = c8.cycles_per_timer_tick();
+        while c8.cycles() < cycles {
+            c8.cycle_in_frame(ipf);
+        }
+    }).err()?;
+
+    Some(payload.downcast_ref::<String>().cloned()
+        .or_else(|| payload.downcast_ref::<&str>().map(|message| message.to_string()))
+        .unwrap_or_else(|| "unknown panic".to_string()))
+}
+
+// The message up to the first number, which is usually an address that moves
+// as the program shrinks, so "stack overflow: call at 208" matches at 200 too.
+fn crash_kind(message: &str) -> &str {
+    message.split(|c: char| c.is_ascii_digit()).next().unwrap_or(message)
+}
+
+// Run the ROM under every preset and suggest the one that ran into the
+// fewest problems, crashing being the worst of them.
+fn compat_check(rom: &Rom, opts: &Options) -> Option<&'static str> {
+    let cycles = opts.cycles.unwrap_or(DETERMINISM_CYCLES);
+    let seed = rand::random();
+
+    // Out of bounds accesses panic, keep the messages out of the report.
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+
+    let mut results = Vec::new();
+    for (name, quirks) in Quirks::presets() {
+        let mut c8 = Chip8::with_seed(seed);
+        c8.ips = opts.ips;
+        c8.timer_hz = opts.timer_hz;
+        c8.quirks = quirks;
+        c8.pause_on_unknown = true;
+        c8.load_rom(rom);
+
+        let ipf = c8.cycles_per_timer_tick();
+        let crashed = panic::catch_unwind(AssertUnwindSafe(|| {
+            while c8.cycles() < cycles && !c8.stopped() {
+                c8.cycle_in_frame(ipf);
+            }
+        })).is_err();
+
+        let warnings = c8.take_warnings();
+        let unknown = warnings.iter().filter(|w| matches!(w, Warning::UnknownOpcode { .. })).count();
+        let blank = c8.gfx.iter().flatten().all(|&pixel| pixel == 0);
+
+        let mut problems = Vec::new();
+        if crashed {
+            problems.push(format!("crashed at PC {:03X}", c8.pc()));
+        }
+        if unknown > 0 {
+            problems.push(format!("{} unknown opcodes", unknown));
+        }
+        if warnings.len() > unknown {
+            problems.push(format!("{} other warnings", warnings.len() - unknown));
+        }
+        if blank {
+            problems.push("blank screen".to_string());
+        }
+
+        println!("{:<8} {}", name, if problems.is_empty() { "ok".to_string() } else { problems.join(", ") });
+        results.push((name, (crashed, unknown, warnings.len(), blank)));
+    }
+
+    panic::set_hook(hook);
+
+    // The first preset with the fewest problems wins, so ties go to the default.
+    let suggestion = results.iter().min_by_key(|(_, score)| *score).map(|&(name, _)| name);
+    if let Some(name) = suggestion {
+        println!("Suggested preset: {} (run with --preset {})", name, name);
+
+        // Whatever the other options ask for wins over the suggestion when running normally.
+        let suggested = Quirks::preset(name).unwrap_or_default();
+        let differences = suggested.differences(&applied_quirks(opts));
+        if !differences.is_empty() {
+            println!("[!] These options run it with different quirks than suggested:");
+            for (quirk, suggested, applied) in differences {
+                println!("    {:<12} {} suggested, {} applied", quirk, suggested, applied);
+            }
+        }
+
+        let missing = instruction::unimplemented(name);
+        if !missing.is_empty() {
+            let patterns: Vec<_> = missing.iter().map(|op| op.pattern).collect();
+            println!("[!] This build doesn't run these {} opcodes yet: {}", name, patterns.join(" "));
+        }
+    }
+    suggestion
+}
+
+fn run_headless(rom: &Rom, opts: &Options) {
+    let mut c8 = Chip8::new();
+    configure(&mut c8, opts);
+    c8.load_rom(rom);
+    load_start_state(&mut c8, opts);
+
+    if opts.coverage.is_some() {
+        c8.enable_coverage();
+    }
+    if opts.profile {
+        c8.enable_profiling();
+    }
+
+    let script = opts.input_script.as_ref()
+        .map(|path| load_input_script(path).unwrap_or_else(|| process::exit(1)));
+
+    let status = run_batch(&mut c8, opts, script);
+
+    if opts.print_state {
+        print!("{}", c8.state_report());
+    } else {
+        println!("{}", c8);
+    }
+    if opts.print_braille {
+        print!("{}", c8.gfx_braille());
+    }
+
+    save_outputs(&c8, opts, rom.len());
+
+    // Exiting skips destructors, so let the trace flush first.
+    drop(c8);
+    process::exit(status);
+}
+
+// Run until one of the options says to stop, giving the exit status: 0 when
+// the run finished, 1 when the ROM stopped the machine, 2 at the step limit.
+fn run_batch(c8: &mut Chip8, opts: &Options, mut script: Option<InputScript>) -> i32 {
+    let ipf = c8.cycles_per_timer_tick();
+
+    // A --load-state can start the count anywhere, the limits are for this run.
+    let start = c8.cycles();
+
+    loop {
+        let ran = c8.cycles() - start;
+        if run_complete(opts, ran, ipf) {
+            return 0;
+        }
+
+        // Compute style ROMs say they're done by getting somewhere.
+        if opts.stop_at_pc == Some(c8.pc()) {
+            return 0;
+        }
+        if opts.stop_on_halt && c8.halted() {
+            return 0;
+        }
+
+        // Keep runaway ROMs from hanging CI.
+        if let Some(limit) = opts.step_limit {
+            if ran >= limit {
+                println!("[-] Step limit of {} cycles reached, stopping.", limit);
+                return 2;
+            }
+        }
+
+        if let Some(script) = script.as_mut() {
+            script.apply(c8);
+        }
+
+        guarded(c8, |c8| c8.cycle_in_frame(ipf));
+        report_warnings(c8);
+
+        // Nothing to show them on, don't let them pile up.
+        c8.drain_events().for_each(drop);
+
+        if let Some((pc, opcode)) = c8.paused_on_unknown() {
+            println!("[-] Unknown opcode {:04X} at {:03X}, stopping.", opcode, pc);
+            return 1;
+        }
+        if let Some(pc) = c8.pc_overrun() {
+            println!("[-] PC ran past the end of memory to {:04X}, stopping.", pc);
+            return 1;
+        }
+    }
+}
+
+// Whether the --cycles or --frames asked for have run, `ran` cycles in.
+fn run_complete(opts: &Options, ran: u64, ipf: u32) -> bool {
+    opts.cycles.is_some_and(|cycles| ran >= cycles) || opts.frames.is_some_and(|frames| ran >= frames * ipf as u64)
+}
+
+#[cfg(feature = "tui-debug")]
+fn run_tui(rom: &Rom, opts: &Options) {
+    let mut c8 = Chip8::new();
+    configure(&mut c8, opts);
+    c8.load_rom(rom);
+    load_start_state(&mut c8, opts);
+
+    // Printing would scribble over the UI, and the debugger should stop on bad opcodes.
+    if opts.trace.is_none() || opts.trace_json {
+        c8.show_debug = false;
+    }
+    c8.pause_on_unknown = true;
+
+    // The terminal is put back by ratatui's panic hook before the crash report is printed.
+    let ipf = c8.cycles_per_timer_tick();
+    let mut result = Ok(());
+    guarded(&mut c8, |c8| result = tui::run(c8, ipf));
+    if let Err(e) = result {
+        println!("[-] Terminal debugger failed: {}", e);
+    }
+
+    save_outputs(&c8, opts, rom.len());
+}
+
+#[cfg(not(feature = "tui-debug"))]
+fn run_tui(_rom: &Rom, _opts: &Options) {
+    println!("[-] Built without the terminal debugger, rebuild with --features tui-debug.");
+}
+
+fn load_repro(path: &str, opts: &Options) {
+    let repro = match File::open(path).and_then(|file| Repro::read_from(&mut BufReader::new(file))) {
+        Ok(repro) => repro,
+        Err(e) => {
+            println!("[-] Couldn't load reproduction: {}", e);
+            return;
+        }
+    };
+
+    let mut c8 = match repro.start() {
+        Ok(c8) => c8,
+        Err(e) => {
+            println!("[-] Reproduction has a bad ROM: {}", e);
+            return;
+        }
+    };
+    guarded(&mut c8, |c8| repro.replay(c8));
+
+    if repro.matches(&c8) {
+        println!("Reproduced {} cycles, {} key events: {}", c8.cycles(), repro.inputs.len(), c8);
+    } else {
+        println!("[-] Reproduction diverged, the display doesn't match after {} cycles: {}", c8.cycles(), c8);
+    }
+    if opts.print_state {
+        print!("{}", c8.state_report());
+    }
+
+    save_outputs(&c8, opts, repro.rom.len());
+}
+
+fn run_replay(path: &str, rom: &Rom, opts: &Options) {
+    let recording = match File::open(path).and_then(|file| Recording::read_from(&mut BufReader::new(file))) {
+        Ok(recording) => recording,
+        Err(e) => {
+            println!("[-] Couldn't load recording: {}", e);
+            return;
+        }
+    };
+
+    let mut c8 = Chip8::with_seed(recording.seed);
+    configure(&mut c8, opts);
+    c8.key_order = recording.key_order;
+    c8.load_rom(rom);
+
+    if opts.coverage.is_some() {
+        c8.enable_coverage();
+    }
+    if opts.profile {
+        c8.enable_profiling();
+    }
+
+    if let Some(target) = opts.seek {
+        guarded(&mut c8, |c8| recording.seek_to(c8, target));
+        println!("Seeked to cycle {}: {}", c8.cycles(), c8);
+    } else {
+        let mut result = Ok(());
+        guarded(&mut c8, |c8| result = recording.replay(c8));
+        match result {
+            Ok(_) => println!("Replay matched all {} checkpoints ({} cycles).", recording.checkpoints.len(), c8.cycles()),
+            Err(divergence) => println!("[-] {}", divergence),
+        }
+    }
+    report_warnings(&mut c8);
+
+    save_outputs(&c8, opts, rom.len());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8rs::emu::instruction::Instruction;
+
+    // Skipping the blank 0000 at 0xFFE leaves PC at 0x1000.
+    fn pc_past_end() -> Chip8 {
+        let mut c8 = Chip8::with_seed(0);
+        c8.execute(Instruction::Jump(0xFFE));
+        c8.cycle();
+        assert_eq!(c8.pc(), 0x1000);
+        c8
+    }
+
+    fn parse(args: &str) -> Options {
+        let args: Vec<String> = ["chip8rs", "rom.ch8"].iter().copied().chain(args.split_whitespace()).map(String::from).collect();
+        Options::parse(&args, false, false).unwrap()
+    }
+
+    #[test]
+    fn run_complete_counts_from_start() {
+        let opts = parse("--cycles 100");
+        assert!(!run_complete(&opts, 99, 10));
+        assert!(run_complete(&opts, 100, 10));
+
+        let opts = parse("--frames 3");
+        assert!(!run_complete(&opts, 29, 10));
+        assert!(run_complete(&opts, 30, 10));
+
+        assert!(!run_complete(&parse(""), 1_000_000, 10));
+    }
+
+    #[test]
+    fn start_paused_runs_nothing_until_resumed() {
+        let opts = parse("--start-paused");
+        let mut c8 = Chip8::with_seed(0);
+        configure(&mut c8, &opts);
+        c8.load_rom(&Rom::try_from(&chip8rs::chip8_program![0x7001, 0x1200][..]).unwrap());
+        c8.paused = opts.start_paused;
+
+        c8.run_frames(10, opts.ips);
+        assert_eq!(c8.cycles(), 0);
+        assert_eq!(c8.pc(), 0x200);
+
+        // What Return does in the window.
+        c8.paused = false;
+        c8.run_frames(1, opts.ips);
+        assert_eq!(c8.cycles(), c8.cycles_per_timer_tick() as u64);
+    }
+
+    #[test]
+    fn embedded_rom_is_deterministic() {
+        // Random sprites at random places, with the delay timer going.
+        let program = chip8rs::chip8_program![0xC00F, 0xF029, 0xC13F, 0xC21F, 0xD125, 0x6303, 0xF315, 0x1200];
+        let rom = Rom::try_from(&program[..]).unwrap();
+        assert!(verify_determinism(&rom, &parse("--verify-determinism --cycles 5000")));
+    }
+
+    #[test]
+    fn export_writes_a_line_per_instruction() {
+        let program = chip8rs::chip8_program![0x6001, 0x7101, 0xA300, 0xD015, 0x1208];
+        let mut c8 = Chip8::with_seed(0);
+        c8.enable_coverage();
+        c8.load_rom(&Rom::try_from(&program[..]).unwrap());
+        c8.cycle();
+
+        let dir = env::temp_dir().join(format!("chip8rs-export-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = export_disassembly(&c8, program.len(), &dir);
+        let listing = fs::read_to_string(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(listing.lines().count(), program.len() / 2);
+        assert!(listing.lines().next().unwrap().starts_with("*"));
+    }
+
+    #[test]
+    fn render_replay_saves_a_frame_per_tick() {
+        let rom = Rom::try_from(&chip8rs::chip8_program![0x7001, 0xF029, 0x00E0, 0xD125, 0x1200][..]).unwrap();
+        let mut c8 = Chip8::with_seed(5);
+        c8.load_rom(&rom);
+        let mut recording = Recording::start(&c8, 10, 10);
+        for _ in 0..60 {
+            c8.cycle_in_frame(10);
+            recording.checkpoint(&c8);
+        }
+
+        let dir = env::temp_dir().join(format!("chip8rs-render-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let replay = dir.join("session.rec");
+        recording.write_to(&mut File::create(&replay).unwrap()).unwrap();
+
+        let frames = dir.join("frames");
+        let opts = parse(&format!("--render-replay {} {}", replay.display(), frames.display()));
+        run_replay(opts.replay.as_ref().unwrap(), &rom, &opts);
+        let saved = fs::read_dir(&frames).unwrap().count();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(saved, 6);
+    }
+
+    #[test]
+    fn schip_rom_needs_schip_preset() {
+        // A 16x16 DXY0 sprite made of the program itself.
+        let rom = Rom::try_from(&chip8rs::chip8_program![0xA200, 0xD010, 0x1204][..]).unwrap();
+        assert_eq!(compat_check(&rom, &parse("--compat-check --cycles 100")), Some("schip"));
+
+        let rom = Rom::try_from(&chip8rs::chip8_program![0xA200, 0xD015, 0x1204][..]).unwrap();
+        assert_eq!(compat_check(&rom, &parse("--compat-check --cycles 100")), Some("default"));
+    }
+
+    #[test]
+    fn overrides_differ_from_the_suggested_preset() {
+        let suggested = Quirks::preset("schip").unwrap();
+        assert!(suggested.differences(&applied_quirks(&parse("--preset schip"))).is_empty());
+
+        let applied = applied_quirks(&parse("--preset schip --shift-vy --wrap-x --stack-policy grow"));
+        assert_eq!(suggested.differences(&applied), vec![
+            ("sprites", "clip", "wrap x"),
+            ("shift", "VX", "VY"),
+            ("stack", "error", "grow"),
+        ]);
+    }
+
+    #[test]
+    fn load_state_resumes_saved_machine() {
+        let rom = Rom::try_from(&chip8rs::chip8_program![0x6007, 0x7101, 0xA321, 0x220A, 0x1208, 0x00EE][..]).unwrap();
+        let mut c8 = Chip8::with_seed(0);
+        c8.load_rom(&rom);
+        // Stops inside the subroutine.
+        for _ in 0..4 {
+            c8.cycle();
+        }
+
+        let path = env::temp_dir().join(format!("chip8rs-state-{}.bin", process::id()));
+        c8.save_state().write_to(&mut File::create(&path).unwrap()).unwrap();
+
+        let opts = parse(&format!("--load-state {}", path.display()));
+        let mut resumed = Chip8::with_seed(0);
+        configure(&mut resumed, &opts);
+        resumed.load_rom(&rom);
+        load_start_state(&mut resumed, &opts);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(resumed.pc(), 0x20A);
+        assert_eq!(resumed.pc(), c8.pc());
+        assert_eq!(resumed.registers(), c8.registers());
+        assert_eq!(resumed.stack(), c8.stack());
+        assert_eq!(resumed.save_state(), c8.save_state());
+    }
+
+    #[test]
+    fn throttled_draws_compose_into_final_frame() {
+        // 0 then 8 on top of it, leaving their difference, and a 1 beside them.
+        let program = chip8rs::chip8_program![0x6200, 0xF229, 0xD015, 0x6208, 0xF229, 0xD015, 0x6201, 0xF229, 0x6005, 0xD015, 0x1214];
+        let mut c8 = Chip8::with_seed(0);
+        c8.load_rom(&Rom::try_from(&program[..]).unwrap());
+        c8.advance_frame(20);
+
+        // Painting each change as it comes, like the window without --throttle-draws.
+        let mut live = im::ImageBuffer::new(64, 32);
+        for event in c8.take_events() {
+            if let Chip8Event::Draw { x, y, on } = event {
+                paint_pixel(&mut live, x as u32, y as u32, on as u8, 1, false);
+            }
+        }
+
+        // And once from the framebuffer after all of them.
+        let mut throttled = im::ImageBuffer::new(64, 32);
+        for (y, row) in c8.gfx.iter().enumerate() {
+            for (x, &on) in row.iter().enumerate() {
+                paint_pixel(&mut throttled, x as u32, y as u32, on, 1, false);
+            }
+        }
+
+        let lit = |buf: &im::RgbaImage| buf.pixels().map(|pixel| pixel.data[0] == 255).collect::<Vec<_>>();
+        assert_eq!(lit(&throttled), lit(&live));
+        chip8rs::emu::testing::assert_framebuffer(&c8, "
+            .......#
+            ......##
+            .##....#
+            .......#
+            ......###
+        ");
+    }
+
+    fn batch(program: &[u8], args: &str) -> (Chip8, i32) {
+        let opts = parse(args);
+        let mut c8 = Chip8::with_seed(0);
+        configure(&mut c8, &opts);
+        c8.load_rom(&Rom::try_from(program).unwrap());
+        let status = run_batch(&mut c8, &opts, None);
+        (c8, status)
+    }
+
+    #[test]
+    fn batch_computes_a_sum() {
+        // V0 = 10 + 9 + ... + 1, counting V1 down.
+        let program = chip8rs::chip8_program![0x6000, 0x610A, 0x8014, 0x71FF, 0x3100, 0x1204, 0x120C];
+        let (c8, status) = batch(&program, "--no-window --cycles 200 --print-state");
+        assert_eq!(status, 0);
+        assert_eq!(c8.cycles(), 200);
+        assert_eq!(c8.registers()[..2], [55, 0]);
+        assert_eq!(c8.pc(), 0x20C);
+    }
+
+    #[test]
+    fn batch_stops_at_pc() {
+        // Counts V0 to 5, then skips on to 0x208.
+        let program = chip8rs::chip8_program![0x6000, 0x7001, 0x3005, 0x1202, 0x6101, 0x1200];
+        let (c8, status) = batch(&program, "--no-window --stop-at-pc 208 --step-limit 1000");
+        assert_eq!(status, 0);
+        assert_eq!(c8.pc(), 0x208);
+        assert_eq!(c8.registers()[..2], [5, 0]);
+        assert_eq!(c8.cycles(), 1 + 5 * 3 - 1);
+    }
+
+    #[test]
+    fn step_limit_stops_runaway_rom() {
+        // Counts forever, never jumping to itself, so it doesn't look halted.
+        let (c8, status) = batch(&chip8rs::chip8_program![0x7001, 0x1200], "--step-limit 1000 --stop-on-halt");
+        assert_eq!(status, 2);
+        assert_eq!(c8.cycles(), 1000);
+    }
+
+    #[test]
+    fn scanlines_darken_odd_rows() {
+        let mut buf = im::RgbaImage::new(8, 4);
+        paint_pixel(&mut buf, 1, 0, 1, 4, true);
+
+        let column: Vec<[u8; 4]> = (0..4).map(|row| buf.get_pixel(4, row).data).collect();
+        assert_eq!(column, vec![[255, 255, 255, 255], [127, 127, 127, 255], [255, 255, 255, 255], [127, 127, 127, 255]]);
+
+        assert_eq!(scanline_shade([200, 100, 50, 255], 1, 4, false), [200, 100, 50, 255]);
+        assert_eq!(scanline_shade([200, 100, 50, 255], 1, 1, true), [200, 100, 50, 255]);
+    }
+
+    #[test]
+    fn fit_to_window_keeps_aspect() {
+        assert_eq!(fit_to_window([640.0, 320.0], 64, 32), (10.0, 0.0, 0.0));
+        // Too tall, centered vertically.
+        assert_eq!(fit_to_window([640.0, 480.0], 64, 32), (10.0, 0.0, 80.0));
+        // Too wide, centered horizontally.
+        assert_eq!(fit_to_window([800.0, 320.0], 64, 32), (10.0, 80.0, 0.0));
+    }
+
+    #[test]
+    fn benchmark_reports_a_rate() {
+        assert!(benchmark(0.01, &parse("--benchmark 0.01")) > 0.0);
+    }
+
+    #[test]
+    fn keypad_cells_follow_the_keymap() {
+        assert_eq!(keypad_cell(0x0), (0, 0));
+        assert_eq!(keypad_cell(0x3), (3, 0));
+        assert_eq!(keypad_cell(0x4), (0, 1));
+        assert_eq!(keypad_cell(0xF), (3, 3));
+
+        // W is second on the second row of the block on the keyboard too.
+        assert_eq!(keymap::QWERTY[5], Key::W);
+        assert_eq!(keypad_cell(5), (1, 1));
+
+        let mut cells: Vec<_> = (0..16).map(keypad_cell).collect();
+        cells.sort();
+        cells.dedup();
+        assert_eq!(cells.len(), 16);
+    }
+
+    #[test]
+    fn running_title_with_and_without_cycles() {
+        assert_eq!(running_title(60, None), "Chip8-rs 60FPS");
+        assert_eq!(running_title(59, Some((700, 123456))), "Chip8-rs 59FPS 700IPS 123456 instructions");
+    }
+
+    #[test]
+    fn step_title_shows_next_instruction() {
+        let mut c8 = Chip8::with_seed(0);
+        c8.load_rom(&Rom::try_from(&chip8rs::chip8_program![0x600A][..]).unwrap());
+
+        assert_eq!(step_title(&c8), "Chip8-rs STEP 200: 600A LD V0, #0A");
+    }
+
+    #[test]
+    fn step_title_past_end_of_memory() {
+        let c8 = pc_past_end();
+
+        assert!(step_title(&c8).starts_with("Chip8-rs STEP 1000: "));
+    }
+
+    #[test]
+    fn sprite_height_of_next_draw() {
+        let mut c8 = Chip8::with_seed(0);
+        c8.load_rom(&Rom::try_from(&chip8rs::chip8_program![0xD015, 0x600A][..]).unwrap());
+        assert_eq!(sprite_height(&c8), 5);
+
+        c8.execute(Instruction::Jump(0x202));
+        assert_eq!(sprite_height(&c8), 15);
+    }
+
+    #[test]
+    fn sprite_height_past_end_of_memory() {
+        assert_eq!(sprite_height(&pc_past_end()), 15);
+    }
+}
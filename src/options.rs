@@ -0,0 +1,592 @@
+use chip8rs::emu::{Quirks, StackPolicy, DEFAULT_IPS, MAX_ROM_SIZE, STANDARD_KEY_ORDER, TIMER_HZ};
+use piston_window::keyboard::Key;
+
+use crate::keymap;
+
+// Command line options for the frontend.
+pub struct Options {
+    pub rom_path: String,
+    pub step_by_one: bool,
+    pub debug: bool,
+    pub preset: Quirks, // Starting point for the quirks, the flags below turn more on.
+    pub wrap_sprites: bool,
+    pub wrap_x: bool,
+    pub wrap_y: bool,
+    pub xo_chip: bool,
+    pub schip: bool,
+    pub shift_vy: bool,
+    pub jump_vx: bool,
+    pub load_store_increment: bool,
+    pub vf_reset: bool,
+    pub display_wait: bool,
+    pub clamp_index: bool,
+    pub half_pixel_scroll: bool,
+    pub stack_policy: Option<StackPolicy>, // Overrides the preset's choice.
+    pub pause_on_unknown: bool,
+    pub strict_pc: bool, // Crash instead of wrapping when PC runs off the end of memory.
+    pub check_targets: bool, // Warn about jumps, calls and ANNNs into the reserved area when loading.
+    pub max_rom_size: usize, // Refuse ROM files bigger than this, it can't go past what fits in memory.
+    pub start_paused: bool, // Load the ROM but wait for Return before running it.
+    pub pause_on_focus_lost: bool, // Pause when the window loses focus, Return resumes.
+    pub key_pulse: bool, // Held keys only register once, for menu-driven ROMs.
+    pub key_order: [u8; 16], // Key number the ROM sees for each keypad key.
+    pub sample_keys_per_frame: bool, // Only let the ROM see key changes at the end of each frame.
+    pub gpu_scale: bool, // Draw at native resolution and let the GPU scale it up.
+    pub throttle_draws: bool, // Repaint the whole screen at 60Hz instead of every pixel as it changes.
+    pub coalesce_draws: bool, // Skip pixels that were drawn over and ended up as they started.
+    pub scanlines: bool, // Darken every other row of the scaled display, F2 toggles it.
+    pub blank_until_draw: bool, // Present no frames until the ROM first draws or clears, like the VIP.
+    pub keys: [Key; 16], // Host key for each CHIP-8 key, from --layout.
+    pub show_cycles: bool, // Show instructions per second and the total executed in the title.
+
+    pub ips: u32, // Instructions executed per second, from --ipf, --ips or --preset.
+    pub timer_hz: u32, // Rate the delay and sound timers count down at.
+
+    pub headless: bool, // Run without a window and print the final state.
+    pub print_state: bool, // Print every register at the end of a headless run, not just the summary.
+    pub print_braille: bool, // Print the final display of a headless run in braille.
+    pub tui: bool, // Debug in the terminal instead of opening a window.
+    pub cycles: Option<u64>, // Stop a headless run after this many cycles.
+    pub frames: Option<u64>, // Or after this many timer frames.
+    pub verify_determinism: bool, // Run the ROM twice and check both runs end up the same.
+    pub compat_check: bool, // Run the ROM under every quirk preset and suggest one.
+    pub minimize_crash: bool, // Shrink a crashing ROM and print what's left as a chip8_program!.
+    pub benchmark: Option<f64>, // Run a built in ROM for this many seconds, print the speed and exit.
+    pub step_limit: Option<u64>, // Abort a headless run that goes on longer than this.
+    pub stop_at_pc: Option<u16>, // End a headless run when execution reaches this address.
+    pub stop_on_halt: bool, // End a headless run when the ROM jumps to itself.
+
+    pub record: Option<String>, // Write a recording of the session here on exit.
+    pub input_script: Option<String>, // Press keys at the cycles listed in this file during a headless run.
+    pub replay: Option<String>, // Replay this recording headlessly and report divergence.
+    pub seek: Option<u64>, // Instead of checking the whole replay, stop at this cycle.
+    pub trace: Option<String>, // Write the debug messages here instead of stdout, turns them on.
+    pub trace_json: bool, // With --trace-format json, --trace gets a JSON line per instruction instead.
+    pub audit_vf: Option<String>, // Log every read and write of VF here.
+    pub svg: Option<String>, // Save the final display as an SVG here.
+    pub capture: Option<String>, // Save the display after every timer tick as a numbered PNG in this directory.
+    pub capture_ppm: Option<String>, // Same, as PPM files at native resolution.
+    pub film_strip: Option<usize>, // Print this many of the last frames as ASCII art on exit.
+    pub save_state: Option<String>, // Write the final machine state here on exit.
+    pub load_state: Option<String>, // Start from this saved state instead of the top of the ROM.
+    pub save_repro: Option<String>, // Write a reproduction of the session (ROM, seed, quirks, inputs) here on exit.
+    pub load_repro: Option<String>, // Rebuild the state a reproduction ends in and print it.
+    pub record_rng: Option<String>, // Write every random number CXNN used here on exit.
+    pub replay_rng: Option<String>, // Feed CXNN the random numbers in this file instead of the generator's.
+    pub diff_states: Option<(String, String)>, // Compare these two save states and exit.
+    pub find: Option<Vec<u8>>, // Print every address holding these bytes and exit.
+    pub dump_font: bool, // Print the built in hex digit glyphs and exit.
+    pub disasm_range: Option<(u16, u16)>, // Disassemble these addresses (inclusive) after loading and exit.
+    pub coverage: Option<String>, // Write a disassembly annotated with execution counts here on exit.
+    pub profile: bool, // Print the time spent in each opcode family on exit.
+    pub profile_output: Option<String>, // Also write it here as CSV, turns profiling on.
+}
+
+// Every option as (name, what follows it, description), for --help and for
+// naming the option that went wrong. `Options::parse_option` handles them.
+const OPTIONS: &[(&str, &str, &str)] = &[
+    ("--preset", "default|vip|schip|xo-chip", "Start from the quirks (and speed) of a known interpreter"),
+    ("--wrap-sprites", "", "Wrap sprites around every edge of the screen instead of clipping them"),
+    ("--wrap-x", "", "Wrap sprites around the left and right edges only"),
+    ("--wrap-y", "", "Wrap sprites around the top and bottom edges only"),
+    ("--xo-chip", "", "Enable the XO-CHIP instructions"),
+    ("--schip", "", "Enable the SUPER-CHIP instructions"),
+    ("--shift-vy", "", "8XY6/8XYE shift VY into VX like the COSMAC VIP"),
+    ("--jump-vx", "", "BXNN jumps to XNN + VX like CHIP-48"),
+    ("--load-store-increment", "", "FX55/FX65 leave I past the last register"),
+    ("--vf-reset", "", "8XY1/8XY2/8XY3 clear VF afterwards"),
+    ("--display-wait", "", "DXYN waits for the next vblank"),
+    ("--clamp-index", "", "FX1E stops I at 0xFFF instead of wrapping"),
+    ("--half-pixel-scroll", "", "Low-res scrolls move half as far, like SCHIP 1.1"),
+    ("--stack-policy", "error|wrap|grow", "What a call with the stack already full does"),
+    ("--pause-on-unknown", "", "Stop at unknown opcodes instead of skipping them"),
+    ("--strict-pc", "", "Crash when PC runs off the end of memory instead of wrapping it"),
+    ("--check-targets", "", "Warn about jumps, calls and ANNNs into the reserved area when loading"),
+    ("--max-rom-size", "<bytes>", "Refuse ROM files bigger than this"),
+    ("--start-paused", "", "Load the ROM but wait for Return before running it"),
+    ("--pause-on-focus-lost", "", "Pause when the window loses focus, Return resumes"),
+    ("--key-pulse", "", "Held keys only register once, for menu-driven ROMs"),
+    ("--key-order", "<16 hex digits>", "Digit i is the key number the ROM sees for keypad key i"),
+    ("--sample-keys-per-frame", "", "Only let the ROM see key changes at the end of each frame"),
+    ("--gpu-scale", "", "Draw at native resolution and let the GPU scale it up"),
+    ("--scanlines", "", "Darken every other row of the display, F2 toggles it"),
+    ("--blank-until-draw", "", "Show nothing until the ROM first draws or clears the screen"),
+    ("--layout", "qwerty|azerty|dvorak|colemak", "Keyboard layout the keypad is mapped onto"),
+    ("--show-cycles", "", "Show instructions per second and the total executed in the title"),
+    ("--throttle-draws", "", "Repaint the whole screen at 60Hz instead of every pixel as it changes"),
+    ("--coalesce-draws", "", "Skip pixels that were drawn over and ended up as they started"),
+    ("--ipf", "<n>", "Instructions to run per 60Hz frame"),
+    ("--ips", "<n>", "Instructions to run per second, instead of --ipf"),
+    ("--timer-hz", "<n>", "Rate the delay and sound timers count down at"),
+    ("--headless|--no-window", "", "Run without a window and print the final state"),
+    ("--print-state", "", "Print every register at the end of a headless run"),
+    ("--braille", "", "Print the final display of a headless run in braille"),
+    ("--verify-determinism", "", "Run the ROM twice and check both runs end up the same"),
+    ("--compat-check", "", "Run the ROM under every preset and suggest one"),
+    ("--minimize-crash", "", "Shrink a ROM that crashes the emulator to the instructions it takes"),
+    ("--benchmark", "<seconds>", "Run a built in ROM flat out for this long and print the speed"),
+    ("--tui", "", "Debug in the terminal instead of opening a window"),
+    ("--cycles", "<n>", "Stop a headless run after this many cycles"),
+    ("--frames", "<n>", "Stop a headless run after this many timer frames"),
+    ("--step-limit", "<n>", "Abort a headless run that goes on longer than this"),
+    ("--stop-at-pc", "<hex>", "End a headless run when execution reaches this address"),
+    ("--stop-on-halt", "", "End a headless run when the ROM jumps to itself"),
+    ("--input-script", "<file>", "Press keys at the cycles listed in this file during a headless run"),
+    ("--record", "<file>", "Write a recording of the session here on exit"),
+    ("--replay", "<file>", "Replay this recording headlessly and report divergence"),
+    ("--render-replay", "<file> <dir>", "Replay a recording, saving every frame in the directory"),
+    ("--seek", "<cycle>", "Stop a replay at this cycle instead of checking all of it"),
+    ("--coverage", "<file>", "Write a disassembly annotated with execution counts here on exit"),
+    ("--trace", "<file>", "Write the debug messages here instead of stdout, turns them on"),
+    ("--trace-format", "text|json", "With json, --trace gets a JSON line per instruction instead"),
+    ("--audit-vf", "<file>", "Log every read and write of VF here"),
+    ("--profile", "", "Print the time spent in each opcode family on exit"),
+    ("--profile-output", "<file>", "Also write the profile here as CSV"),
+    ("--svg", "<file>", "Save the final display as an SVG here"),
+    ("--capture", "<dir>", "Save the display after every timer tick as numbered PNGs here"),
+    ("--capture-ppm", "<dir>", "Same, as PPM files at native resolution"),
+    ("--film-strip", "<n>", "Print the last n frames as ASCII art on exit"),
+    ("--save-state", "<file>", "Write the final machine state here on exit"),
+    ("--load-state", "<file>", "Start from this saved state instead of the top of the ROM"),
+    ("--save-repro", "<file>", "Write a reproduction of the session here on exit"),
+    ("--load-repro", "<file>", "Rebuild the state a reproduction ends in and print it, no ROM needed"),
+    ("--record-rng", "<file>", "Write every random number CXNN used here on exit"),
+    ("--replay-rng", "<file>", "Feed CXNN the random numbers in this file"),
+    ("--diff-states", "<a> <b>", "Compare two save states and exit, no ROM needed"),
+    ("--find", "<hex>", "Print every address holding these bytes and exit"),
+    ("--dump-font", "", "Print the built in hex digit glyphs and exit, no ROM needed"),
+    ("--disasm-range", "<start>:<end>", "Disassemble these addresses (hex, inclusive) and exit"),
+];
+
+pub fn usage(program: &str) -> String {
+    format!("Usage: {} <romfile> [stepbyone=1|0] [debug=1|0] [options]\nRun with --help to list the options.", program)
+}
+
+// The usage line followed by every option and what it does.
+pub fn help(program: &str) -> String {
+    let spelled: Vec<String> = OPTIONS.iter()
+        .map(|&(name, value, _)| if value.is_empty() { name.to_string() } else { format!("{} {}", name, value) })
+        .collect();
+    let width = spelled.iter().map(|option| option.len()).max().unwrap_or(0);
+
+    let mut help = format!("Usage: {} <romfile> [stepbyone=1|0] [debug=1|0] [options]\n\nOptions:\n", program);
+    for (option, &(_, _, description)) in spelled.iter().zip(OPTIONS.iter()) {
+        help.push_str(&format!("  {:<width$}  {}\n", option, description, width = width));
+    }
+    help.push_str(&format!("  {:<width$}  {}", "--help|-h", "Show this and exit", width = width));
+    help
+}
+
+// Points at the option that was probably meant, for typos like --ipss.
+fn unknown_option(arg: &str) -> String {
+    let closest = OPTIONS.iter()
+        .flat_map(|&(name, _, _)| name.split('|'))
+        .map(|name| (edit_distance(arg, name), name))
+        .min();
+
+    match closest {
+        Some((distance, name)) if distance <= 2 => format!("Unknown option {}, did you mean {}?", arg, name),
+        _ => format!("Unknown option {}", arg),
+    }
+}
+
+// Levenshtein distance, the number of single character edits from `a` to `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (above + 1).min(row[j] + 1).min(diagonal + (ca != cb) as usize);
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+fn parse_bool(arg: &str) -> Option<bool> {
+    match arg {
+        "1" | "true" => Some(true),
+        "0" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+// Parse a string of hex digit pairs like "F0909090F0" into bytes.
+fn parse_hex_bytes(arg: &str) -> Option<Vec<u8>> {
+    let digits = arg.trim_start_matches("0x");
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..digits.len()).step_by(2)
+        .map(|i| u8::from_str_radix(digits.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// 16 hex digits using each of 0-F once, like "123C456D789EA0BF".
+fn parse_key_order(arg: &str) -> Option<[u8; 16]> {
+    let mut order = [0; 16];
+    let mut seen = [false; 16];
+    let mut digits = arg.chars();
+
+    for slot in order.iter_mut() {
+        let digit = digits.next()?.to_digit(16)? as usize;
+        if seen[digit] {
+            return None;
+        }
+        seen[digit] = true;
+        *slot = digit as u8;
+    }
+
+    if digits.next().is_some() {
+        return None;
+    }
+    Some(order)
+}
+
+// "start:end" in hex, both inclusive, for example "200:2FF".
+fn parse_hex_range(arg: &str) -> Option<(u16, u16)> {
+    let mut ends = arg.splitn(2, ':');
+    let start = u16::from_str_radix(ends.next()?.trim_start_matches("0x"), 16).ok()?;
+    let end = u16::from_str_radix(ends.next()?.trim_start_matches("0x"), 16).ok()?;
+
+    if start > end {
+        return None;
+    }
+    Some((start, end))
+}
+
+// The options that decide how fast to run, which are only settled once all of
+// them have been seen.
+#[derive(Default)]
+struct Speed {
+    ipf: Option<u32>,
+    ips: Option<u32>,
+    preset_ips: Option<u32>, // What --preset would pick, if neither of those is given.
+}
+
+impl Options {
+    // Says what's wrong if the arguments don't make sense, the caller should
+    // show it with the usage.
+    pub fn parse(args: &[String], step_by_one: bool, debug: bool) -> Result<Options, String> {
+        let mut opts = Options {
+            rom_path: String::new(),
+            step_by_one,
+            debug,
+            preset: Quirks::default(),
+            wrap_sprites: false,
+            wrap_x: false,
+            wrap_y: false,
+            xo_chip: false,
+            schip: false,
+            shift_vy: false,
+            jump_vx: false,
+            load_store_increment: false,
+            vf_reset: false,
+            display_wait: false,
+            clamp_index: false,
+            half_pixel_scroll: false,
+            stack_policy: None,
+            pause_on_unknown: false,
+            strict_pc: false,
+            check_targets: false,
+            max_rom_size: MAX_ROM_SIZE,
+            start_paused: false,
+            pause_on_focus_lost: false,
+            key_pulse: false,
+            key_order: STANDARD_KEY_ORDER,
+            sample_keys_per_frame: false,
+            gpu_scale: false,
+            scanlines: false,
+            blank_until_draw: false,
+            keys: keymap::QWERTY,
+            show_cycles: false,
+            throttle_draws: false,
+            coalesce_draws: false,
+            ips: DEFAULT_IPS,
+            timer_hz: TIMER_HZ,
+            headless: false,
+            print_state: false,
+            print_braille: false,
+            tui: false,
+            cycles: None,
+            frames: None,
+            verify_determinism: false,
+            compat_check: false,
+            minimize_crash: false,
+            benchmark: None,
+            step_limit: None,
+            stop_at_pc: None,
+            stop_on_halt: false,
+            record: None,
+            replay: None,
+            seek: None,
+            coverage: None,
+            input_script: None,
+            trace: None,
+            trace_json: false,
+            audit_vf: None,
+            profile: false,
+            profile_output: None,
+            svg: None,
+            capture: None,
+            capture_ppm: None,
+            film_strip: None,
+            save_state: None,
+            load_state: None,
+            save_repro: None,
+            load_repro: None,
+            record_rng: None,
+            replay_rng: None,
+            diff_states: None,
+            find: None,
+            dump_font: false,
+            disasm_range: None,
+        };
+
+        let mut speed = Speed::default();
+        let mut positional = Vec::new();
+        let mut iter = args.iter().skip(1);
+        while let Some(arg) = iter.next() {
+            if !arg.starts_with("--") {
+                positional.push(arg.as_str());
+                continue;
+            }
+
+            let &(_, value, _) = OPTIONS.iter().find(|&&(name, _, _)| name.split('|').any(|name| name == arg))
+                .ok_or_else(|| unknown_option(arg))?;
+            if opts.parse_option(arg, &mut iter, &mut speed).is_none() {
+                return Err(format!("{} needs {}", arg, value));
+            }
+        }
+
+        // Only one of them can say how fast to go, otherwise the preset does.
+        match (speed.ipf, speed.ips) {
+            (Some(_), Some(_)) => return Err("Only one of --ipf and --ips can be given".to_string()),
+            (Some(ipf), None) => opts.ips = ipf.checked_mul(TIMER_HZ).ok_or_else(|| "--ipf is too large".to_string())?,
+            (None, Some(ips)) => opts.ips = ips,
+            (None, None) => {
+                if let Some(ips) = speed.preset_ips {
+                    opts.ips = ips;
+                }
+            },
+        }
+
+        // Comparing save states, dumping the font, benchmarking and loading a
+        // reproduction (which has its own copy) are all that work without a ROM.
+        let needs_rom = opts.diff_states.is_none() && !opts.dump_font && opts.benchmark.is_none() && opts.load_repro.is_none();
+        if positional.len() > 3 {
+            return Err(format!("Unexpected argument {}", positional[3]));
+        }
+        if positional.is_empty() && needs_rom {
+            return Err("No ROM given".to_string());
+        }
+
+        if let Some(arg) = positional.first() {
+            opts.rom_path = arg.to_string();
+        }
+
+        if let Some(arg) = positional.get(1) {
+            opts.step_by_one = parse_bool(arg).ok_or("stepbyone should be 1 or 0")?;
+        }
+
+        if let Some(arg) = positional.get(2) {
+            opts.debug = parse_bool(arg).ok_or("debug should be 1 or 0")?;
+        }
+
+        Ok(opts)
+    }
+
+    // Apply the option `name` from `OPTIONS`, taking its values from `iter`.
+    // None if they're missing or don't parse.
+    fn parse_option<'a, I: Iterator<Item = &'a String>>(&mut self, name: &str, iter: &mut I, speed: &mut Speed) -> Option<()> {
+        match name {
+            "--record" => self.record = Some(iter.next()?.clone()),
+            "--input-script" => self.input_script = Some(iter.next()?.clone()),
+            "--replay" => self.replay = Some(iter.next()?.clone()),
+            // Shorthand for replaying while capturing every frame.
+            "--render-replay" => {
+                self.replay = Some(iter.next()?.clone());
+                self.capture = Some(iter.next()?.clone());
+            },
+            "--seek" => self.seek = Some(iter.next()?.parse().ok()?),
+            "--coverage" => self.coverage = Some(iter.next()?.clone()),
+            "--capture" => self.capture = Some(iter.next()?.clone()),
+            "--capture-ppm" => self.capture_ppm = Some(iter.next()?.clone()),
+            "--film-strip" => self.film_strip = Some(iter.next()?.parse().ok().filter(|&count| count > 0)?),
+            "--save-state" => self.save_state = Some(iter.next()?.clone()),
+            "--load-state" => self.load_state = Some(iter.next()?.clone()),
+            "--save-repro" => self.save_repro = Some(iter.next()?.clone()),
+            "--load-repro" => self.load_repro = Some(iter.next()?.clone()),
+            "--record-rng" => self.record_rng = Some(iter.next()?.clone()),
+            "--replay-rng" => self.replay_rng = Some(iter.next()?.clone()),
+            "--diff-states" => self.diff_states = Some((iter.next()?.clone(), iter.next()?.clone())),
+            "--trace" => self.trace = Some(iter.next()?.clone()),
+            "--trace-format" => self.trace_json = match iter.next()?.as_str() {
+                "text" => false,
+                "json" => true,
+                _ => return None,
+            },
+            "--audit-vf" => self.audit_vf = Some(iter.next()?.clone()),
+            "--svg" => self.svg = Some(iter.next()?.clone()),
+            "--dump-font" => self.dump_font = true,
+            "--disasm-range" => self.disasm_range = Some(parse_hex_range(iter.next()?)?),
+            "--find" => self.find = Some(parse_hex_bytes(iter.next()?)?),
+            "--profile" => self.profile = true,
+            "--profile-output" => {
+                self.profile_output = Some(iter.next()?.clone());
+                self.profile = true;
+            },
+            "--preset" => {
+                let name = iter.next()?;
+                self.preset = Quirks::preset(name)?;
+                speed.preset_ips = Quirks::preset_ips(name);
+            },
+            "--wrap-sprites" => self.wrap_sprites = true,
+            "--wrap-x" => self.wrap_x = true,
+            "--wrap-y" => self.wrap_y = true,
+            "--xo-chip" => self.xo_chip = true,
+            "--schip" => self.schip = true,
+            "--shift-vy" => self.shift_vy = true,
+            "--jump-vx" => self.jump_vx = true,
+            "--load-store-increment" => self.load_store_increment = true,
+            "--vf-reset" => self.vf_reset = true,
+            "--display-wait" => self.display_wait = true,
+            "--clamp-index" => self.clamp_index = true,
+            "--half-pixel-scroll" => self.half_pixel_scroll = true,
+            "--stack-policy" => self.stack_policy = Some(StackPolicy::from_name(iter.next()?)?),
+            "--pause-on-unknown" => self.pause_on_unknown = true,
+            "--strict-pc" => self.strict_pc = true,
+            "--check-targets" => self.check_targets = true,
+            "--max-rom-size" => self.max_rom_size = iter.next()?.parse().ok().filter(|&size| size > 0)?,
+            "--start-paused" => self.start_paused = true,
+            "--pause-on-focus-lost" => self.pause_on_focus_lost = true,
+            "--key-pulse" => self.key_pulse = true,
+            "--key-order" => self.key_order = parse_key_order(iter.next()?)?,
+            "--sample-keys-per-frame" => self.sample_keys_per_frame = true,
+            "--gpu-scale" => self.gpu_scale = true,
+            "--scanlines" => self.scanlines = true,
+            "--blank-until-draw" => self.blank_until_draw = true,
+            "--layout" => self.keys = keymap::layout(iter.next()?)?,
+            "--show-cycles" => self.show_cycles = true,
+            "--throttle-draws" => self.throttle_draws = true,
+            "--coalesce-draws" => self.coalesce_draws = true,
+            "--ipf" => speed.ipf = Some(iter.next()?.parse().ok().filter(|&ipf| ipf > 0)?),
+            "--ips" => speed.ips = Some(iter.next()?.parse().ok().filter(|&ips| ips > 0)?),
+            "--timer-hz" => self.timer_hz = iter.next()?.parse().ok().filter(|&hz| hz > 0)?,
+            "--headless" | "--no-window" => self.headless = true,
+            "--print-state" => self.print_state = true,
+            "--braille" => self.print_braille = true,
+            "--verify-determinism" => self.verify_determinism = true,
+            "--compat-check" => self.compat_check = true,
+            "--minimize-crash" => self.minimize_crash = true,
+            "--benchmark" => self.benchmark = Some(iter.next()?.parse().ok().filter(|&secs: &f64| secs > 0.0 && secs.is_finite())?),
+            "--tui" => self.tui = true,
+            "--cycles" => self.cycles = Some(iter.next()?.parse().ok()?),
+            "--frames" => self.frames = Some(iter.next()?.parse().ok()?),
+            "--step-limit" => self.step_limit = Some(iter.next()?.parse().ok()?),
+            "--stop-at-pc" => self.stop_at_pc = Some(u16::from_str_radix(iter.next()?.trim_start_matches("0x"), 16).ok()?),
+            "--stop-on-halt" => self.stop_on_halt = true,
+            _ => return None,
+        }
+
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Options, String> {
+        let args: Vec<String> = ["chip8rs"].iter().copied().chain(args.split_whitespace()).map(String::from).collect();
+        Options::parse(&args, false, false)
+    }
+
+    #[test]
+    fn known_flags_parse() {
+        let opts = parse("rom.ch8 1 0 --headless --wrap-x --ipf 15 --stack-policy grow --key-order 123C456D789EA0BF").unwrap();
+        assert_eq!(opts.rom_path, "rom.ch8");
+        assert!(opts.step_by_one && !opts.debug);
+        assert!(opts.headless && opts.wrap_x);
+        assert_eq!(opts.ips, 900);
+        assert_eq!(opts.stack_policy, Some(StackPolicy::Grow));
+        assert_eq!(opts.key_order[0x3], 0xC);
+
+        assert!(parse("rom.ch8 --no-window").unwrap().headless);
+    }
+
+    #[test]
+    fn disasm_range_is_hex_and_ordered() {
+        assert_eq!(parse("rom.ch8 --disasm-range 202:2ff").unwrap().disasm_range, Some((0x202, 0x2FF)));
+        assert_eq!(parse("rom.ch8 --disasm-range 0x300:0x310").unwrap().disasm_range, Some((0x300, 0x310)));
+        assert!(parse("rom.ch8 --disasm-range 300:200").is_err());
+        assert!(parse("rom.ch8 --disasm-range 300").is_err());
+    }
+
+    #[test]
+    fn every_listed_option_is_handled() {
+        for &(names, value, _) in OPTIONS.iter() {
+            for name in names.split('|') {
+                let result = parse(&format!("rom.ch8 {}", name));
+                if value.is_empty() {
+                    assert!(result.is_ok(), "{}: {:?}", name, result.err());
+                } else {
+                    assert_eq!(result.err(), Some(format!("{} needs {}", name, value)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_flags_are_explained() {
+        assert_eq!(parse("rom.ch8 --ipss 600").err(), Some("Unknown option --ipss, did you mean --ips?".to_string()));
+        assert_eq!(parse("rom.ch8 --headles").err(), Some("Unknown option --headles, did you mean --headless?".to_string()));
+        assert_eq!(parse("rom.ch8 --frobnicate").err(), Some("Unknown option --frobnicate".to_string()));
+        assert_eq!(parse("rom.ch8 --ipf ten").err(), Some("--ipf needs <n>".to_string()));
+        assert_eq!(parse("").err(), Some("No ROM given".to_string()));
+    }
+
+    #[test]
+    fn speed_resolves_to_ips() {
+        assert_eq!(parse("rom.ch8").unwrap().ips, DEFAULT_IPS);
+        assert_eq!(parse("rom.ch8 --ipf 20").unwrap().ips, 1200);
+    }
+
+    #[test]
+    fn preset_sets_its_speed() {
+        assert_eq!(parse("rom.ch8 --preset schip").unwrap().ips, 1800);
+        assert_eq!(parse("rom.ch8 --preset vip").unwrap().ips, 600);
+
+        // Either speed flag still wins, whichever side of the preset it's on.
+        assert_eq!(parse("rom.ch8 --preset schip --ips 900").unwrap().ips, 900);
+        assert_eq!(parse("rom.ch8 --ipf 10 --preset schip").unwrap().ips, 600);
+    }
+
+    #[test]
+    fn huge_ipf_is_an_error() {
+        assert_eq!(parse("rom.ch8 --ipf 4000000000").err(), Some("--ipf is too large".to_string()));
+        assert_eq!(parse(&format!("rom.ch8 --ipf {}", u32::MAX / TIMER_HZ)).unwrap().ips, u32::MAX / TIMER_HZ * TIMER_HZ);
+    }
+
+    #[test]
+    fn ips_kept_exactly() {
+        assert_eq!(parse("rom.ch8 --ips 500").unwrap().ips, 500);
+        assert!(parse("rom.ch8 --ips 600 --ipf 10").is_err());
+
+        let mut c8 = chip8rs::Chip8::with_seed(0);
+        c8.ips = parse("rom.ch8 --ips 600").unwrap().ips;
+        assert_eq!(c8.cycles_per_timer_tick(), 10);
+    }
+
+    #[test]
+    fn timer_hz() {
+        assert_eq!(parse("rom.ch8").unwrap().timer_hz, TIMER_HZ);
+        assert_eq!(parse("rom.ch8 --timer-hz 50").unwrap().timer_hz, 50);
+        assert!(parse("rom.ch8 --timer-hz 0").is_err());
+    }
+}
@@ -0,0 +1,178 @@
+// Sound through rodio, playing the XO-CHIP pattern buffer while the sound timer runs.
+//
+// The output stream is only opened the first time a tone starts, so runs that
+// never beep don't touch the audio device. Without one the emulator carries on silently,
+// and if it goes away later the errors below let `FallbackAudio` turn sound off.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chip8rs::emu::audio::{self, AudioBackend, PatternPlayer};
+use rodio::{OutputStream, Sink, Source};
+
+const SAMPLE_RATE: u32 = 44100;
+const VOLUME: f32 = 0.25;
+
+// Samples generated per lock of the shared pattern.
+const CHUNK_LEN: usize = 512;
+
+struct Pattern {
+    bits: [u8; 16],
+    pitch: u8,
+}
+
+// Endless source that reads the current pattern as it goes.
+struct PatternSource {
+    player: PatternPlayer,
+    pattern: Arc<Mutex<Pattern>>,
+    samples: Vec<f32>,
+    position: usize,
+}
+
+impl Iterator for PatternSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.position == self.samples.len() {
+            let pattern = self.pattern.lock().unwrap();
+            self.player.fill(&pattern.bits, pattern.pitch, SAMPLE_RATE, &mut self.samples);
+            self.position = 0;
+        }
+
+        self.position += 1;
+        Some(self.samples[self.position - 1] * VOLUME)
+    }
+}
+
+impl Source for PatternSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+enum Device {
+    Unopened,
+    Open { _stream: OutputStream, sink: Sink }, // The stream has to outlive the sink playing on it.
+    Missing,
+}
+
+pub struct RodioAudio {
+    device: Device,
+    pattern: Arc<Mutex<Pattern>>,
+}
+
+impl RodioAudio {
+    pub fn new() -> RodioAudio {
+        RodioAudio {
+            device: Device::Unopened,
+            pattern: Arc::new(Mutex::new(Pattern { bits: audio::DEFAULT_PATTERN, pitch: audio::DEFAULT_PITCH })),
+        }
+    }
+
+    fn open(&mut self) -> Device {
+        let (stream, handle) = match OutputStream::try_default() {
+            Ok(output) => output,
+            Err(e) => {
+                println!("[!] No audio device ({}), continuing without sound.", e);
+                return Device::Missing;
+            }
+        };
+
+        let sink = match Sink::try_new(&handle) {
+            Ok(sink) => sink,
+            Err(e) => {
+                println!("[!] Couldn't start audio ({}), continuing without sound.", e);
+                return Device::Missing;
+            }
+        };
+
+        sink.pause();
+        sink.append(PatternSource {
+            player: PatternPlayer::new(),
+            pattern: self.pattern.clone(),
+            samples: vec![0.0; CHUNK_LEN],
+            position: CHUNK_LEN,
+        });
+
+        Device::Open { _stream: stream, sink }
+    }
+}
+
+impl AudioBackend for RodioAudio {
+    fn set_tone(&mut self, on: bool) -> Result<(), String> {
+        if on {
+            if let Device::Unopened = self.device {
+                self.device = self.open();
+            }
+        }
+
+        if let Device::Open { sink, .. } = &self.device {
+            // The source never ends, so an empty sink means the stream died under it.
+            if sink.empty() {
+                self.device = Device::Missing;
+                return Err("output stream stopped".to_string());
+            }
+
+            if on {
+                sink.play();
+            } else {
+                sink.pause();
+            }
+        }
+        Ok(())
+    }
+
+    fn set_pattern(&mut self, bits: &[u8; 16], pitch: u8) -> Result<(), String> {
+        // Poisoned if the playback thread panicked.
+        let mut pattern = self.pattern.lock().map_err(|_| "playback thread crashed".to_string())?;
+        pattern.bits = *bits;
+        pattern.pitch = pitch;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toggling_without_a_device_does_not_panic() {
+        // Whether or not the machine running this has a device, sound off is always fine
+        // and the first tone settles the device one way or the other.
+        let mut audio = RodioAudio::new();
+        assert!(audio.set_tone(false).is_ok());
+        for &on in [true, false, true, false].iter() {
+            let _ = audio.set_tone(on);
+        }
+        assert!(audio.set_pattern(&audio::DEFAULT_PATTERN, audio::DEFAULT_PITCH).is_ok());
+        match audio.device {
+            Device::Unopened => panic!("starting a tone should open the device"),
+            Device::Open { .. } | Device::Missing => {}
+        }
+    }
+
+    #[test]
+    fn missing_device_stays_silent() {
+        let mut audio = RodioAudio::new();
+        audio.device = Device::Missing;
+        for &on in [true, false, true].iter() {
+            assert!(audio.set_tone(on).is_ok());
+        }
+        match audio.device {
+            Device::Missing => {}
+            _ => panic!("a missing device shouldn't be reopened"),
+        }
+    }
+}
@@ -0,0 +1,103 @@
+// Keyboard shortcuts for poking at the machine while single stepping.
+//
+//   Ctrl + <hex digit>             toggle that CHIP-8 key
+//   Alt + <reg> <high> <low>       set register V<reg> to the byte <high><low>
+
+use piston_window::keyboard::Key;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DebugAction {
+    ToggleKey(u8),
+    SetRegister(u8, u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Modifier {
+    Ctrl,
+    Alt,
+}
+
+// Hex digit typed with a host key, shortcuts use the number row and A-F.
+pub fn hex_digit(key: Key) -> Option<u8> {
+    let digits = [
+        Key::D0, Key::D1, Key::D2, Key::D3, Key::D4, Key::D5, Key::D6, Key::D7,
+        Key::D8, Key::D9, Key::A, Key::B, Key::C, Key::D, Key::E, Key::F
+    ];
+
+    digits.iter().position(|&k| k == key).map(|digit| digit as u8)
+}
+
+#[derive(Default)]
+pub struct DebugShortcuts {
+    digits: Vec<u8>, // Digits typed so far for a register poke.
+}
+
+impl DebugShortcuts {
+    pub fn new() -> DebugShortcuts {
+        DebugShortcuts { digits: Vec::new() }
+    }
+
+    // Feed a hex digit typed while a modifier was held.
+    pub fn on_digit(&mut self, modifier: Modifier, digit: u8) -> Option<DebugAction> {
+        match modifier {
+            Modifier::Ctrl => {
+                self.digits.clear();
+                Some(DebugAction::ToggleKey(digit))
+            },
+
+            Modifier::Alt => {
+                self.digits.push(digit);
+                if self.digits.len() < 3 {
+                    return None;
+                }
+
+                let action = DebugAction::SetRegister(self.digits[0], self.digits[1] << 4 | self.digits[2]);
+                self.digits.clear();
+                Some(action)
+            },
+        }
+    }
+
+    // Letting go of the modifier abandons a half typed register poke.
+    pub fn cancel(&mut self) {
+        self.digits.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alt_digits_set_register() {
+        let mut shortcuts = DebugShortcuts::new();
+        let digits = [Key::A, Key::D3, Key::F].iter().map(|&key| hex_digit(key).unwrap());
+        let actions: Vec<_> = digits.map(|digit| shortcuts.on_digit(Modifier::Alt, digit)).collect();
+
+        assert_eq!(actions, vec![None, None, Some(DebugAction::SetRegister(0xA, 0x3F))]);
+    }
+
+    #[test]
+    fn ctrl_digit_toggles_key() {
+        let mut shortcuts = DebugShortcuts::new();
+        assert_eq!(shortcuts.on_digit(Modifier::Alt, 0x1), None);
+        assert_eq!(shortcuts.on_digit(Modifier::Ctrl, 0xC), Some(DebugAction::ToggleKey(0xC)));
+
+        // The Ctrl shortcut dropped the half typed poke.
+        assert_eq!(shortcuts.on_digit(Modifier::Alt, 0x2), None);
+        assert_eq!(shortcuts.on_digit(Modifier::Alt, 0x0), None);
+        assert_eq!(shortcuts.on_digit(Modifier::Alt, 0x1), Some(DebugAction::SetRegister(0x2, 0x01)));
+    }
+
+    #[test]
+    fn cancel_abandons_poke() {
+        let mut shortcuts = DebugShortcuts::new();
+        shortcuts.on_digit(Modifier::Alt, 0x5);
+        shortcuts.cancel();
+
+        assert_eq!(shortcuts.on_digit(Modifier::Alt, 0x1), None);
+        assert_eq!(shortcuts.on_digit(Modifier::Alt, 0x2), None);
+        assert_eq!(shortcuts.on_digit(Modifier::Alt, 0x3), Some(DebugAction::SetRegister(0x1, 0x23)));
+        assert_eq!(hex_digit(Key::G), None);
+    }
+}
@@ -0,0 +1,202 @@
+// Terminal debugger built on ratatui.
+//
+//   s / space      step one instruction
+//   o              step over a subroutine call
+//   c              continue until a breakpoint or unknown opcode
+//   d              run until the screen is next drawn to
+//   p              pause
+//   b              toggle a breakpoint at PC
+//   q / esc        quit
+
+use std::collections::BTreeSet;
+use std::io;
+
+use chip8rs::emu::{disasm, Opcode, Warning};
+use chip8rs::Chip8;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+// Instructions shown above and below PC in the disassembly pane.
+const DISASM_BEFORE: u16 = 8;
+const DISASM_AFTER: u16 = 16;
+
+// Give up looking for the next draw after this many instructions.
+const DRAW_SEARCH_CYCLES: usize = 100_000;
+
+// Same for waiting on a stepped over subroutine to return.
+const STEP_OVER_CYCLES: usize = 100_000;
+
+struct Debugger {
+    running: bool,
+    breakpoints: BTreeSet<u16>,
+    last_warning: Option<Warning>, // Shown in the status line until the next one.
+}
+
+pub fn run(c8: &mut Chip8, ipf: u32) -> io::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, c8, ipf);
+    ratatui::restore();
+    result
+}
+
+fn event_loop(terminal: &mut DefaultTerminal, c8: &mut Chip8, ipf: u32) -> io::Result<()> {
+    let mut dbg = Debugger { running: false, breakpoints: BTreeSet::new(), last_warning: None };
+
+    loop {
+        if let Some(warning) = c8.take_warnings().pop() {
+            dbg.last_warning = Some(warning);
+        }
+
+        // The display pane draws straight from gfx.
+        c8.drain_events().for_each(drop);
+
+        terminal.draw(|frame| draw(frame, c8, &dbg))?;
+
+        if event::poll(c8.timer_interval())? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+
+                match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                    KeyCode::Char('s') | KeyCode::Char(' ') => {
+                        dbg.running = false;
+                        step(c8, ipf);
+                    },
+                    KeyCode::Char('o') => {
+                        dbg.running = false;
+                        c8.resume_from_unknown();
+                        c8.step_over(STEP_OVER_CYCLES);
+                    },
+                    KeyCode::Char('d') => {
+                        dbg.running = false;
+                        c8.resume_from_unknown();
+                        c8.run_to_next_draw(DRAW_SEARCH_CYCLES);
+                    },
+                    KeyCode::Char('c') => dbg.running = true,
+                    KeyCode::Char('p') => dbg.running = false,
+                    KeyCode::Char('b') => {
+                        let pc = c8.pc();
+                        if !dbg.breakpoints.remove(&pc) {
+                            dbg.breakpoints.insert(pc);
+                        }
+                    },
+                    _ => {},
+                }
+            }
+        }
+
+        if dbg.running {
+            run_frame(c8, ipf, &mut dbg);
+        }
+    }
+}
+
+fn step(c8: &mut Chip8, ipf: u32) {
+    c8.resume_from_unknown();
+    c8.cycle_in_frame(ipf);
+}
+
+// Run up to a frame's worth of instructions, stopping early on a breakpoint.
+fn run_frame(c8: &mut Chip8, ipf: u32, dbg: &mut Debugger) {
+    for i in 0..ipf {
+        // Don't stop on the breakpoint we were resumed from.
+        if i > 0 && dbg.breakpoints.contains(&c8.pc()) {
+            dbg.running = false;
+            return;
+        }
+
+        step(c8, ipf);
+
+        if c8.stopped() {
+            dbg.running = false;
+            return;
+        }
+    }
+}
+
+fn draw(frame: &mut Frame, c8: &Chip8, dbg: &Debugger) {
+    let [main, help] = Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+    let [left, right] = Layout::horizontal([Constraint::Length(66), Constraint::Min(30)]).areas(main);
+    let [screen, regs, stack] = Layout::vertical([
+        Constraint::Length(18),
+        Constraint::Length(9),
+        Constraint::Min(3),
+    ]).areas(left);
+
+    frame.render_widget(Paragraph::new(screen_lines(c8)).block(Block::bordered().title("Display")), screen);
+    frame.render_widget(Paragraph::new(register_lines(c8)).block(Block::bordered().title("Registers")), regs);
+    frame.render_widget(Paragraph::new(stack_lines(c8)).block(Block::bordered().title("Stack")), stack);
+    frame.render_widget(Paragraph::new(disasm_lines(c8, dbg)).block(Block::bordered().title("Disassembly")), right);
+
+    let status = if let Some((pc, opcode)) = c8.paused_on_unknown() {
+        format!("Unknown opcode {:04X} at {:03X}", opcode, pc)
+    } else if let Some(pc) = c8.pc_overrun() {
+        format!("PC ran past the end of memory to {:04X}", pc)
+    } else if let Some(warning) = dbg.last_warning {
+        format!("{}, {}", if dbg.running { "Running" } else { "Paused" }, warning)
+    } else if dbg.running {
+        "Running".to_string()
+    } else {
+        "Paused".to_string()
+    };
+    frame.render_widget(Paragraph::new(format!(" {} | s step  o step over  c continue  d next draw  p pause  b breakpoint  q quit", status)), help);
+}
+
+// Two display rows per terminal line using half blocks.
+fn screen_lines(c8: &Chip8) -> Vec<Line<'static>> {
+    c8.gfx.chunks(2).map(|rows| {
+        let line: String = (0..64).map(|x| match (rows[0][x] != 0, rows[1][x] != 0) {
+            (true, true) => '█',
+            (true, false) => '▀',
+            (false, true) => '▄',
+            (false, false) => ' ',
+        }).collect();
+        Line::from(line)
+    }).collect()
+}
+
+fn register_lines(c8: &Chip8) -> Vec<Line<'static>> {
+    let regs = c8.registers();
+    let mut lines: Vec<Line> = regs.chunks(4).enumerate().map(|(row, chunk)| {
+        let line: Vec<String> = chunk.iter().enumerate()
+            .map(|(i, value)| format!("V{:X}={:02X}", row * 4 + i, value))
+            .collect();
+        Line::from(line.join("  "))
+    }).collect();
+
+    lines.push(Line::from(format!("PC={:03X}  I={:03X}  DT={:02X}  ST={:02X}",
+        c8.pc(), c8.index(), c8.delay_timer(), c8.sound_timer())));
+    lines.push(Line::from(format!("Cycles={}", c8.cycles())));
+    lines.push(Line::from(c8.quirks.summary()));
+    lines
+}
+
+fn stack_lines(c8: &Chip8) -> Vec<Line<'static>> {
+    c8.call_trace().into_iter().enumerate().rev()
+        .map(|(depth, (site, subroutine))| Line::from(format!("{:>2}: {:03X} CALL #{:03X}", depth, site, subroutine)))
+        .collect()
+}
+
+fn disasm_lines(c8: &Chip8, dbg: &Debugger) -> Vec<Line<'static>> {
+    let memory = c8.memory();
+
+    disasm::window(c8.pc(), DISASM_BEFORE, DISASM_AFTER).into_iter().map(|addr| {
+        // Reads wrap like fetches do, PC can sit on the last byte or just past it.
+        let opcode = Opcode::from_bytes(memory[addr as usize % 4096], memory[(addr as usize + 1) % 4096]).0;
+        let marker = if dbg.breakpoints.contains(&addr) { '●' } else { ' ' };
+        let text = format!("{} {:03X}: {:04X}  {}", marker, addr, opcode, disasm::disassemble(opcode));
+
+        if addr == c8.pc() {
+            Line::styled(text, Style::default().fg(Color::Black).bg(Color::Yellow))
+        } else {
+            Line::from(text)
+        }
+    }).collect()
+}